@@ -1,168 +1,393 @@
-use pcap::{Device, Capture, Error as pcap_error};
+use pcap::{Device, Capture, Activated, Linktype, Error as pcap_error};
+use serde::{Serialize, Deserialize, Serializer, ser::{SerializeSeq, SerializeStruct}};
 use std::{
-    collections::HashMap, io::Error as io_error, sync::{atomic::{AtomicU64, Ordering::Acquire}, mpsc, Arc, RwLock}, thread, time::Duration
+    collections::HashMap, path::PathBuf, io::Error as io_error, sync::{atomic::{AtomicU64, Ordering::Relaxed}, Arc}, time::{Duration, SystemTime, UNIX_EPOCH}
 };
+use crate::ieee80211;
 use crate::types::packet_types;
-
+use crate::channel_hopper::ChannelState;
+use crate::gps::{self, SharedGpsData, FixThresholds, PositionMethod, Fix};
+use crate::pipeline::SightingSender;
+use crate::radiotap::{self, RadiotapHeader};
+use crate::replay_clock::ReplayClock;
+use crate::shutdown;
+use crate::tag_decoders;
+use crate::tagged_params::tagged_params_ws;
+use crate::types;
+use crate::value_to_type;
+
+// Serialized for the JSON lines sink. Times are ISO 8601 strings and durations are seconds.
+#[derive(Serialize)]
 pub struct Broadcast {
+    #[serde(rename = "frame", serialize_with = "serialize_packet_type")]
     pub packet_type: u8, // First byte in ieee 802.11 header
+    #[serde(serialize_with = "serialize_mac_address")]
     pub transmitter_mac_address: [u8; 6],
-    pub found_tags: HashMap<u8, Vec<u8>>
+    #[serde(serialize_with = "serialize_mac_address")]
+    pub receiver_mac_address: [u8; 6],
+    #[serde(serialize_with = "serialize_mac_address")]
+    pub bssid: [u8; 6],
+    #[serde(rename = "tags", serialize_with = "serialize_found_tags")]
+    pub found_tags: HashMap<u8, Vec<u8>>,
+    pub radiotap: RadiotapHeader, // Empty if the packet had no radiotap header
+    pub channel: Option<u8>, // Channel the radio was tuned to. Falls back to the radiotap frequency when replaying a file
+    #[serde(serialize_with = "serialize_duration")]
+    pub timestamp: Duration, // Capture time since the unix epoch from the pcap packet header
+    #[serde(serialize_with = "serialize_time")]
+    pub received_at: SystemTime, // Host clock when the capture thread handled the frame
+    #[serde(serialize_with = "serialize_duration")]
+    pub received_monotonic: Duration, // CLOCK_MONOTONIC at the same moment. Unaffected by the host clock being stepped
+    pub original_length: u32, // Length of the frame on the air. Can be longer than raw_packet if it was truncated
+    #[serde(skip)]
+    pub raw_packet: Vec<u8>, // Whole packet including any radiotap header for archiving
+    #[serde(skip)]
+    pub archive_only: bool // Captured but not a sighting. Only sinks that archive every frame get these
 }
 
-#[derive(Clone, Debug)]
-pub struct GpsDataDecoded {
-    pub time: f64,
-    pub lat: f64,
-    pub lon: f64
+// libpcap's counters at the end of a live capture
+pub struct CaptureStats {
+    pub received: u32,
+    pub dropped: u32, // No room in the capture buffer, usually because the writer fell behind
+    pub interface_dropped: u32
 }
 
-fn get_location(atomic_coords: &[Arc<AtomicU64>; 3]) -> GpsDataDecoded {
-    let time = f64::from_bits(atomic_coords[0].load(Acquire));
-    let lat = f64::from_bits(atomic_coords[1].load(Acquire));
-    let lon = f64::from_bits(atomic_coords[2].load(Acquire));
+// What is captured and which tagged parameters are kept from each frame
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    pub bpf_filter: String, // Berkeley packet filter syntax. Anything other than beacons and probe requests is discarded after it anyway
+    pub tags: Vec<u8>, // Tag numbers kept in found_tags. Frames with none of them are skipped
+    pub skip_hidden_ssids: bool, // Hidden networks send an empty or zeroed SSID, which usually isnt worth logging
+    pub packet_timeout_ms: i32 // How often a live capture stops waiting for packets to check whether it should shut down
+}
 
-    return GpsDataDecoded {time: time, lat: lat, lon: lon};
+impl Default for CaptureConfig {
+    fn default() -> CaptureConfig {
+        CaptureConfig {
+            bpf_filter: "type mgt subtype probe-req or subtype beacon".to_string(),
+            tags: vec![tagged_params_ws::SSID],
+            skip_hidden_ssids: true,
+            packet_timeout_ms: 500
+        }
+    }
 }
 
-fn search_tagged_params(data: &[u8], target_tag_numbers: &Vec<u8>) -> HashMap<u8, Vec<u8>> {
-    let mut tags: HashMap<u8, Vec<u8>> = HashMap::new();
-    let mut position = 0;
+// Compiles the filter without a capture so a bad one is reported before anything is started
+pub fn check_bpf_filter(bpf_filter: &str) -> Result<(), String> {
+    let capture = Capture::dead(Linktype::IEEE802_11_RADIOTAP).map_err(|e| e.to_string())?;
 
-    while position < data.len() {
-        let tag_number = data[position];
-        let tag_length = data[position + 1] as usize;
+    capture.compile(bpf_filter, true).map(|_program| ()).map_err(|e| e.to_string())
+}
 
-        if target_tag_numbers.contains(&tag_number) {
-            tags.insert(tag_number, data[position + 2..position + 2 + tag_length].to_owned());  
-        }
+pub enum CaptureSource {
+    Interface(String), // Live capture from an interface already in monitor mode
+    File(PathBuf) // Replay of a .pcap or .pcapng file captured elsewhere
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GpsDataDecoded {
+    pub utc: Option<SystemTime>, // GPS date and time of the fix. None until the receiver has sent both
+    pub lat: f64,
+    pub lon: f64,
+    pub fix_valid: bool, // RMC status
+    pub fix_quality: Option<u8>, // GGA fix quality
+    pub satellites_used: Option<u8>,
+    pub hdop: Option<f64>,
+    pub pdop: Option<f64>,
+    pub fix_received_at: Option<SystemTime>, // Host time the fix arrived. None if there hasnt been one
+    pub altitude: Option<f64>, // Meters above mean sea level
+    pub speed: Option<f64>, // Meters per second over the ground
+    pub course: Option<f64>, // Degrees true
+    pub velocity_measured: bool, // False if speed and course were worked out from the last two fixes
+    pub has_position: bool, // Whether the fix met the thresholds when the frame was received. lat and lon should be ignored if not
+    pub position_method: PositionMethod, // How lat and lon were arrived at for this frame
+    pub next_fix: Option<Fix> // The fix after this one, if the location source knows it already
+}
 
-        // Increment position forward tag_length and then +2 to account for the original tag number and length
-        position += tag_length + 2;
+// Written out by hand so lat and lon are null without a position, rather than the last fix or 0, 0 that every reader would
+// have to check has_position for. next_fix is only for estimating the position and isnt written.
+impl Serialize for GpsDataDecoded {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (lat, lon) = if self.has_position { (Some(self.lat), Some(self.lon)) } else { (None, None) };
+
+        let mut gps_data = serializer.serialize_struct("GpsDataDecoded", 15)?;
+        gps_data.serialize_field("utc", &self.utc.map(gps::format_utc))?;
+        gps_data.serialize_field("lat", &lat)?;
+        gps_data.serialize_field("lon", &lon)?;
+        gps_data.serialize_field("fix_valid", &self.fix_valid)?;
+        gps_data.serialize_field("fix_quality", &self.fix_quality)?;
+        gps_data.serialize_field("satellites_used", &self.satellites_used)?;
+        gps_data.serialize_field("hdop", &self.hdop)?;
+        gps_data.serialize_field("pdop", &self.pdop)?;
+        gps_data.serialize_field("fix_received_at", &self.fix_received_at.map(gps::format_utc))?;
+        gps_data.serialize_field("altitude", &self.altitude)?;
+        gps_data.serialize_field("speed", &self.speed)?;
+        gps_data.serialize_field("course", &self.course)?;
+        gps_data.serialize_field("velocity_measured", &self.velocity_measured)?;
+        gps_data.serialize_field("has_position", &self.has_position)?;
+        gps_data.serialize_field("position_method", &self.position_method)?;
+
+        return gps_data.end();
     }
+}
 
-    return tags;
+impl GpsDataDecoded {
+    pub fn no_fix() -> GpsDataDecoded {
+        GpsDataDecoded {utc: None, lat: 0.0, lon: 0.0, fix_valid: false, fix_quality: None, satellites_used: None, hdop: None, pdop: None, fix_received_at: None, altitude: None, speed: None, course: None, velocity_measured: false, has_position: false, position_method: PositionMethod::NoPosition, next_fix: None}
+    }
 }
 
-pub fn mac_address_to_string(mac_address: &[u8]) -> String {
-    format!(
-        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-        mac_address[0], mac_address[1], mac_address[2], mac_address[3], mac_address[4], mac_address[5]
-    )
+fn serialize_time<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&gps::format_utc(*time))
 }
 
-pub fn get_interfaces() -> Result<Vec<Device>, pcap_error> {
-    Device::list()
+fn serialize_duration<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
 }
 
-pub fn get_changed_interfaces(original_devices: Vec<Device>, changed_devices: Vec<Device>) -> Vec<Device> {
-    let mut new_or_changed_devices: Vec<Device> = Vec::new();
+fn serialize_mac_address<S: Serializer>(mac_address: &[u8; 6], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&mac_address_to_string(mac_address))
+}
 
-    for changed_device in &changed_devices {
-        if original_devices.iter().any(|d| d.name == changed_device.name) {
-            continue;
+#[derive(Serialize)]
+struct FrameType {
+    name: &'static str,
+    #[serde(rename = "type")]
+    frame_type: u8,
+    subtype: u8
+}
+
+// The frame control byte holds the version in bits 0-1, the type in bits 2-3 and the subtype in bits 4-7
+fn serialize_packet_type<S: Serializer>(packet_type: &u8, serializer: S) -> Result<S::Ok, S::Error> {
+    FrameType {name: value_to_type!(*packet_type), frame_type: (packet_type >> 2) & 0x03, subtype: packet_type >> 4}.serialize(serializer)
+}
+
+// Decoded where there is a decoder for the tag and as hex otherwise, in tag number order
+fn serialize_found_tags<S: Serializer>(found_tags: &HashMap<u8, Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut tag_numbers: Vec<&u8> = found_tags.keys().collect();
+    tag_numbers.sort();
+
+    let mut sequence = serializer.serialize_seq(Some(tag_numbers.len()))?;
+
+    for tag_number in tag_numbers {
+        let data = &found_tags[tag_number];
+
+        match tag_decoders::decode(*tag_number, data) {
+            Some(decoded) => sequence.serialize_element(&serde_json::json!({"tag_number": tag_number, "decoded": decoded}))?,
+            None => sequence.serialize_element(&serde_json::json!({"tag_number": tag_number, "hex": tag_decoders::hex(data)}))?
         }
-        new_or_changed_devices.push(changed_device.clone());
     }
 
-    return new_or_changed_devices;
+    sequence.end()
 }
 
-pub fn start(interface_name: &str, tag_numbers: &Vec<u8>, mpsc_sender: mpsc::Sender<(Broadcast, GpsDataDecoded)>, gps_data_arc: Option<[Arc<AtomicU64>; 3]>) -> Result<(), std::io::Error> {
-    let global_gps_data = Arc::new(RwLock::new(GpsDataDecoded {time: 0.0, lat: 0.0, lon: 0.0}));
+impl Broadcast {
+    // Parses raw_packet again for what the capture thread didnt keep. Only packets from a radiotap capture have a
+    // radiotap header, and every radiotap header is at least 8 bytes long.
+    pub fn parse_raw_packet(&self) -> Result<ieee80211::ParsedFrame<'_>, ieee80211::ParseError> {
+        ieee80211::parse(&self.raw_packet, self.radiotap.length > 0)
+    }
+
+    // Every information element of the frame as hex, in the order they appeared
+    pub fn raw_ies(&self) -> Vec<serde_json::Value> {
+        match self.parse_raw_packet() {
+            Ok(parsed_frame) => parsed_frame.tags()
+                .map_while(|tag| tag.ok())
+                .map(|(tag_number, data)| serde_json::json!({"tag_number": tag_number, "hex": tag_decoders::hex(data)}))
+                .collect(),
+            Err(_e) => Vec::new()
+        }
+    }
+}
 
-    // If the caller has passed gps_data then assume to use gps
-    if let Some(gps_data) = gps_data_arc {
-        let global_gps_data_handle = Arc::clone(&global_gps_data);
+// https://man7.org/linux/man-pages/man2/clock_gettime.2.html
+pub fn monotonic_now() -> Duration {
+    let mut timespec = libc::timespec {tv_sec: 0, tv_nsec: 0};
 
-        thread::spawn(move || { 
-            loop {
-                let current_global_gps_data = global_gps_data_handle.read().unwrap();
+    // Can only fail with an invalid clock id or pointer
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut timespec) };
 
-                // Get GPS data
-                let current_gps_data = get_location(&gps_data);
+    Duration::new(timespec.tv_sec as u64, timespec.tv_nsec as u32)
+}
 
-                // If the GPS data is not different then do not change the global gps data
-                // If the GPS data is 0 for both lat and lon then dont change the global gps data
-                if (current_global_gps_data.lat == current_gps_data.lat && current_global_gps_data.lon == current_gps_data.lon) || (current_gps_data.lat == 0.0 || current_gps_data.lon == 0.0) {
-                    // Drop early
-                    drop(current_global_gps_data);
+pub fn mac_address_to_string(mac_address: &[u8]) -> String {
+    format!(
+        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        mac_address[0], mac_address[1], mac_address[2], mac_address[3], mac_address[4], mac_address[5]
+    )
+}
 
-                    thread::sleep(Duration::from_millis(400));
+pub fn get_interfaces() -> Result<Vec<Device>, pcap_error> {
+    Device::list()
+}
 
-                    continue;
-                }
-            
-                // Drop the last read to prevent a deadlock
-                drop(current_global_gps_data);
-
-                //println!("Updated GPS");
-                let mut current_global_gps_data = global_gps_data_handle.write().unwrap();
-                *current_global_gps_data = current_gps_data;
-            }       
-        });
+// Hidden networks send an SSID that is empty or all zeroes
+fn has_ssid(found_tags: &HashMap<u8, Vec<u8>>) -> bool {
+    match found_tags.get(&tagged_params_ws::SSID) {
+        Some(ssid) => !ssid.is_empty() && !ssid.iter().all(|&x| x == 0),
+        None => false
     }
+}
 
-    // immediate_mode(false) - Packets do not come through when
-    // promisc() - Promiscuous mode (true - captures all packets even if they werent addressed to us)
-    let mut capture = Capture::from_device(interface_name)
-        .map_err(|e| io_error::new(std::io::ErrorKind::Other, e))?
-        .immediate_mode(true).promisc(true).open()
-        .map_err(|e| io_error::new(std::io::ErrorKind::Other, e))?;
+// Returns once a replayed file has been read to the end or shutdown is requested, with libpcap's counters for a live capture.
+// Dropping sighting_sender then tells the writer it has everything.
+pub fn start(capture_source: &CaptureSource, capture_config: &CaptureConfig, sighting_sender: SightingSender, archive_every_frame: bool, shared_gps_data: Option<Arc<SharedGpsData>>, fix_thresholds: FixThresholds, discarded_frames: Arc<AtomicU64>, channel_state: Option<Arc<ChannelState>>, replay_clock: Option<Arc<ReplayClock>>) -> Result<Option<CaptureStats>, std::io::Error> {
+    let mut capture: Capture<dyn Activated> = match capture_source {
+        // immediate_mode(false) - Packets do not come through when
+        // promisc() - Promiscuous mode (true - captures all packets even if they werent addressed to us)
+        CaptureSource::Interface(interface_name) => Capture::from_device(interface_name.as_str())
+            .map_err(|e| io_error::new(std::io::ErrorKind::Other, e))?
+            .immediate_mode(true).promisc(true).timeout(capture_config.packet_timeout_ms).open()
+            .map_err(|e| io_error::new(std::io::ErrorKind::Other, e))?
+            .into(),
+        CaptureSource::File(path) => Capture::from_file(path)
+            .map_err(|e| io_error::new(std::io::ErrorKind::Other, e))?
+            .into()
+    };
+
+    // Anything else (PPI, Ethernet, ...) would only be counted as malformed frames
+    let has_radiotap = match capture.get_datalink() {
+        Linktype::IEEE802_11_RADIOTAP => true,
+        Linktype::IEEE802_11 => false,
+        linktype => {
+            let name = linktype.get_name().unwrap_or_else(|_e| linktype.0.to_string());
+
+            return Err(io_error::new(std::io::ErrorKind::InvalidData, format!("Unsupported link type {}. Only 802.11 with or without radiotap headers can be read", name)));
+        }
+    };
 
-    // Berkeley packet filter syntax
-    capture.filter("type mgt subtype probe-req or subtype beacon", true)
+    capture.filter(&capture_config.bpf_filter, true)
         .map_err(|e| io_error::new(std::io::ErrorKind::Other, e))?;
 
-    while let Ok(packet) = capture.next_packet() {
-        let packet = packet.data;
+    while !shutdown::requested() {
+        let packet = match capture.next_packet() {
+            Ok(packet) => packet,
+            // Nothing arrived within packet_timeout_ms
+            Err(pcap_error::TimeoutExpired) => continue,
+            // The end of a replayed file, or the interface went away
+            Err(_e) => break
+        };
+
+        // Replayed files are played back at the recorded pace so they line up with a replayed location log
+        if let Some(replay_clock) = &replay_clock {
+            replay_clock.wait_until(packet.header.ts.tv_sec as f64 + packet.header.ts.tv_usec as f64 / 1_000_000.0);
+        }
 
-        // https://howiwifi.com/2020/07/13/802-11-frame-types-and-formats/
-        let mut ieee_802_11_frame_start: usize = 0;
-        let first_byte = packet[0];
+        // Dont have to check for To/From DS because only management frames are kept. Frames that cant be parsed are counted
+        // in discarded_frames and skipped rather than stopping the capture.
+        let parsed_frame = match ieee80211::parse(packet.data, has_radiotap) {
+            Ok(parsed_frame) => parsed_frame,
+            Err(_e) => { discarded_frames.fetch_add(1, Relaxed); continue; }
+        };
 
-        // First byte of packet - meaning
-        // 0x00 - A radiotap header will be present
-        // Anything else - A radiotap header will NOT be present. Instead it is the immediate start of the 802.11 frame
-        if first_byte == 0x00 {
-            // https://www.radiotap.org/
-            // "Data is specified in little endian byte-order"
-            ieee_802_11_frame_start = u16::from_le_bytes([packet[2], packet[3]]) as usize; // u_int16_t
+        // The default filter only lets these through, but a configured one might not
+        if parsed_frame.packet_type() != packet_types::BEACON && parsed_frame.packet_type() != packet_types::PROBE_REQUEST {
+            continue;
         }
 
-        let ieee_80211_frame = &packet[ieee_802_11_frame_start..];
+        let found_tags = match ieee80211::search_tagged_params(&parsed_frame, &capture_config.tags) {
+            Ok(found_tags) => found_tags,
+            Err(_e) => { discarded_frames.fetch_add(1, Relaxed); continue; }
+        };
 
-        //let _frame_control_first_4 = ieee80211_frame[0];
-        //let _frame_control_last_4 = ieee80211_frame[1];
+        let channel = match &channel_state {
+            Some(channel_state) => {
+                if parsed_frame.packet_type() == packet_types::PROBE_REQUEST {
+                    channel_state.record_probe_request();
+                }
 
-        // Dont have to check for To/From DS because of the filter we applies earlier.
+                Some(channel_state.current_channel())
+            },
+            None => parsed_frame.radiotap.channel_frequency.and_then(radiotap::frequency_to_channel)
+        };
 
-        let transmitter_mac_address: &[u8] = &ieee_80211_frame[10..16];
-        let mut offset = 24;
+        // Frames with none of the wanted tags arent sightings and neither are hidden networks if they are skipped, but a
+        // pcapng archive still wants them
+        let archive_only = found_tags.is_empty() || (capture_config.skip_hidden_ssids && !has_ssid(&found_tags));
 
-        // Skip fixed parameters
-        if ieee_80211_frame[0] == packet_types::BEACON {
-            offset += 12
+        if archive_only && !archive_every_frame {
+            continue;
         }
 
-        let data: &[u8] = &ieee_80211_frame[offset..];
-
-        let found_tags = search_tagged_params(data, &tag_numbers);
-
-        if found_tags.len() > 0 {
-            let broadcast: Broadcast = Broadcast {
-                packet_type: ieee_80211_frame[0],
-                transmitter_mac_address: transmitter_mac_address.try_into().map_err(|e| io_error::new(std::io::ErrorKind::Other, e))?,
-                found_tags: found_tags
-            };
+        let broadcast: Broadcast = Broadcast {
+            packet_type: parsed_frame.packet_type(),
+            transmitter_mac_address: *parsed_frame.transmitter_mac_address,
+            receiver_mac_address: *parsed_frame.receiver_mac_address,
+            bssid: *parsed_frame.bssid,
+            found_tags: found_tags,
+            radiotap: parsed_frame.radiotap,
+            channel: channel,
+            timestamp: Duration::new(packet.header.ts.tv_sec as u64, packet.header.ts.tv_usec as u32 * 1_000),
+            received_at: SystemTime::now(),
+            received_monotonic: monotonic_now(),
+            original_length: packet.header.len,
+            raw_packet: packet.data.to_vec(),
+            archive_only: archive_only
+        };
+
+        // The latest snapshot is read for every frame so the position is never older than the last fix
+        let mut gps_data = match &shared_gps_data {
+            Some(shared_gps_data) => shared_gps_data.load(),
+            None => GpsDataDecoded::no_fix()
+        };
+
+        gps_data.has_position = fix_thresholds.accepts(&gps_data, SystemTime::now());
+
+        // Moves the position on from the last fix to when the frame was captured. Replayed frames are compared with the
+        // GPS time of the fix since the host time the fix was replayed at has nothing to do with the recording.
+        if gps_data.has_position {
+            let fix_time = if replay_clock.is_some() { gps_data.utc } else { gps_data.fix_received_at };
+            let frame_time = UNIX_EPOCH + broadcast.timestamp;
+
+            gps::estimate_position(&mut gps_data, fix_time, frame_time, &fix_thresholds);
+        }
 
-            let gps_data = global_gps_data.read().unwrap();
-            
-            mpsc_sender.send((broadcast, (*gps_data).clone())).unwrap();
+        // The writer only goes away if it has failed, so there is nothing left to capture for
+        if !sighting_sender.send((broadcast, gps_data)) {
+            break;
         }
     }
 
-    return Ok(());
+    // Replayed files dont have any
+    let capture_stats = capture.stats().ok().map(|stats| CaptureStats {received: stats.received, dropped: stats.dropped, interface_dropped: stats.if_dropped});
+
+    return Ok(capture_stats);
 }
 
-// [1..2] - start is inclusive, end is non inclusive
\ No newline at end of file
+// [1..2] - start is inclusive, end is non inclusive
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hidden_ssids() {
+        assert!(has_ssid(&HashMap::from([(tagged_params_ws::SSID, b"abc".to_vec())])));
+        assert!(!has_ssid(&HashMap::from([(tagged_params_ws::SSID, Vec::new())])));
+        assert!(!has_ssid(&HashMap::from([(tagged_params_ws::SSID, vec![0x00; 8])])));
+        assert!(!has_ssid(&HashMap::from([(tagged_params_ws::DS_PARAMETER, vec![0x06])])));
+    }
+
+    #[test]
+    fn position_is_null_without_a_fix() {
+        let mut gps_data = GpsDataDecoded::no_fix();
+        gps_data.lat = 51.5;
+        gps_data.lon = -0.125;
+        gps_data.utc = Some(UNIX_EPOCH + Duration::from_millis(1_714_582_989_250));
+
+        let json = serde_json::to_value(&gps_data).unwrap();
+        assert_eq!(json["lat"], serde_json::Value::Null);
+        assert_eq!(json["lon"], serde_json::Value::Null);
+        assert_eq!(json["utc"], "2024-05-01T17:03:09.250Z");
+        assert_eq!(json["fix_received_at"], serde_json::Value::Null);
+        assert_eq!(json["position_method"], "NO_POSITION");
+        assert!(json.get("next_fix").is_none());
+
+        gps_data.has_position = true;
+        gps_data.position_method = PositionMethod::Measured;
+
+        let json = serde_json::to_value(&gps_data).unwrap();
+        assert_eq!(json["lat"], 51.5);
+        assert_eq!(json["lon"], -0.125);
+        assert_eq!(json["position_method"], "MEASURED");
+    }
+}
@@ -0,0 +1,165 @@
+use serde::{Serialize, Deserialize};
+use std::{
+    thread,
+    time::Duration,
+    sync::{Arc, atomic::{AtomicU8, AtomicU64, Ordering::Relaxed}}
+};
+use crate::interface_manager::InterfaceManager;
+use crate::shutdown;
+
+// Adaptive mode never dwells longer than this many times a channel's configured dwell
+const MAX_ADAPTIVE_FACTOR: f64 = 3.0;
+// Weight of the newest dwell in the running probe request rate of a channel
+const ADAPTIVE_SMOOTHING: f64 = 0.3;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HopChannel {
+    pub channel: u8,
+    #[serde(default = "default_dwell_ms")]
+    pub dwell_ms: u64
+}
+
+fn default_dwell_ms() -> u64 {
+    250
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelHoppingConfig {
+    pub channels: Vec<HopChannel>,
+    // Dwell longer on channels that have been producing more probe requests
+    #[serde(default)]
+    pub adaptive: bool
+}
+
+// Shared between the hopper and the capture thread so every frame can be tagged with the channel the radio was on
+pub struct ChannelState {
+    current_channel: AtomicU8,
+    probe_requests: Vec<AtomicU64> // Probe requests seen since the last dwell on each channel. Index is the channel
+}
+
+impl ChannelState {
+    pub fn new(channel: u8) -> ChannelState {
+        ChannelState {current_channel: AtomicU8::new(channel), probe_requests: (0..=u8::MAX).map(|_| AtomicU64::new(0)).collect()}
+    }
+
+    pub fn current_channel(&self) -> u8 {
+        self.current_channel.load(Relaxed)
+    }
+
+    fn set_current_channel(&self, channel: u8) {
+        self.current_channel.store(channel, Relaxed);
+    }
+
+    pub fn record_probe_request(&self) {
+        self.probe_requests[self.current_channel() as usize].fetch_add(1, Relaxed);
+    }
+
+    fn take_probe_requests(&self, channel: u8) -> u64 {
+        self.probe_requests[channel as usize].swap(0, Relaxed)
+    }
+}
+
+// Cycles the interface through the configured channels until shutdown is requested
+pub fn start(interface_manager: Arc<dyn InterfaceManager>, interface: &str, hopping_config: &ChannelHoppingConfig, channel_state: Arc<ChannelState>) {
+    if hopping_config.channels.is_empty() {
+        return;
+    }
+
+    // Running probe requests per second of each channel in hopping_config.channels
+    let mut probe_request_rates: Vec<f64> = vec![0.0; hopping_config.channels.len()];
+
+    while !shutdown::requested() {
+        for (index, hop_channel) in hopping_config.channels.iter().enumerate() {
+            // The interface is about to be taken out of monitor mode
+            if shutdown::requested() {
+                return;
+            }
+
+            if let Err(e) = interface_manager.set_channel(interface, hop_channel.channel) {
+                eprintln!("Unable to set channel {}: {}", hop_channel.channel, e);
+
+                // Avoid spinning if every channel is failing
+                thread::sleep(Duration::from_millis(hop_channel.dwell_ms));
+
+                continue;
+            }
+
+            channel_state.set_current_channel(hop_channel.channel);
+            channel_state.take_probe_requests(hop_channel.channel);
+
+            let dwell = if hopping_config.adaptive {
+                adaptive_dwell(hop_channel.dwell_ms, probe_request_rates[index], &probe_request_rates)
+            } else {
+                Duration::from_millis(hop_channel.dwell_ms)
+            };
+
+            thread::sleep(dwell);
+
+            let probe_requests = channel_state.take_probe_requests(hop_channel.channel);
+            let rate = probe_requests as f64 / dwell.as_secs_f64().max(0.001);
+
+            probe_request_rates[index] = smoothed_rate(probe_request_rates[index], rate);
+        }
+    }
+}
+
+// Exponentially weighted so one quiet or busy dwell doesnt swing the next one too far
+fn smoothed_rate(previous_rate: f64, rate: f64) -> f64 {
+    ADAPTIVE_SMOOTHING * rate + (1.0 - ADAPTIVE_SMOOTHING) * previous_rate
+}
+
+// The busiest channel gets MAX_ADAPTIVE_FACTOR times its dwell and the quietest its configured dwell. If every channel is
+// as busy as the others there is nothing to favour, so they all keep their configured dwell.
+fn adaptive_dwell(dwell_ms: u64, rate: f64, all_rates: &[f64]) -> Duration {
+    let busiest_rate = all_rates.iter().cloned().fold(f64::MIN, f64::max);
+    let quietest_rate = all_rates.iter().cloned().fold(f64::MAX, f64::min);
+
+    if busiest_rate <= quietest_rate {
+        return Duration::from_millis(dwell_ms);
+    }
+
+    let factor = 1.0 + ((rate - quietest_rate) / (busiest_rate - quietest_rate)).clamp(0.0, 1.0) * (MAX_ADAPTIVE_FACTOR - 1.0);
+
+    return Duration::from_millis((dwell_ms as f64 * factor) as u64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_rates_keep_the_configured_dwell() {
+        assert_eq!(adaptive_dwell(250, 4.0, &[4.0, 4.0, 4.0]), Duration::from_millis(250));
+        assert_eq!(adaptive_dwell(250, 0.0, &[0.0, 0.0, 0.0]), Duration::from_millis(250));
+        assert_eq!(adaptive_dwell(250, 0.0, &[]), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn busier_channels_dwell_longer() {
+        let rates = [0.0, 5.0, 10.0];
+
+        assert_eq!(adaptive_dwell(200, 0.0, &rates), Duration::from_millis(200));
+        assert_eq!(adaptive_dwell(200, 5.0, &rates), Duration::from_millis(400));
+        assert_eq!(adaptive_dwell(200, 10.0, &rates), Duration::from_millis(600));
+    }
+
+    #[test]
+    fn dwell_is_clamped_to_three_times() {
+        // A rate that isnt one of the channels, e.g. from before the others were updated
+        assert_eq!(adaptive_dwell(100, 50.0, &[1.0, 10.0]), Duration::from_millis(300));
+        assert_eq!(adaptive_dwell(100, -1.0, &[1.0, 10.0]), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn rates_are_smoothed() {
+        assert_eq!(smoothed_rate(0.0, 10.0), 3.0);
+        assert_eq!(smoothed_rate(10.0, 10.0), 10.0);
+        assert!((smoothed_rate(10.0, 0.0) - 7.0).abs() < 1e-9);
+
+        // A channel that stays busy closes in on its rate
+        let rate = (0..50).fold(0.0, |rate, _| smoothed_rate(rate, 8.0));
+        assert!((rate - 8.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,384 @@
+use clap::{Args, Parser, Subcommand};
+use serde_json::{json, Map, Value};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(version, about = "Logs WiFi beacons and probe requests with the GPS position they were seen at")]
+pub struct Cli {
+    /// JSON or TOML config file to use instead of config.toml / config.json next to the executable. Relative sink paths are from its directory
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Without a command, captures or replays as the config file says
+    #[command(subcommand)]
+    pub command: Option<Command>
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Capture live from the configured interface, ignoring any pcap_file in the config
+    Capture {
+        #[command(flatten)]
+        overrides: ConfigOverrides
+    },
+    /// Replay a .pcap / .pcapng file captured elsewhere instead of capturing
+    Replay {
+        pcap_file: String,
+
+        #[command(flatten)]
+        overrides: ConfigOverrides
+    },
+    /// Convert a text log or SQLite database for other tools
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat
+    },
+    /// Print what is in a text log or SQLite database
+    Stats {
+        /// logged_packets.txt or a .sqlite database
+        log: PathBuf,
+
+        /// Session to read from a database. Defaults to the latest
+        #[arg(long)]
+        session: Option<i64>
+    },
+    /// List the interfaces that can be captured on
+    ListInterfaces,
+    /// Print the config with any overrides applied and check it can be used, without capturing
+    Check {
+        #[command(flatten)]
+        overrides: ConfigOverrides
+    }
+}
+
+#[derive(Subcommand)]
+pub enum ExportFormat {
+    /// Beacons with a position as a WiGLE CSV
+    Wigle {
+        /// logged_packets.txt or a .sqlite database
+        log: PathBuf,
+        output: PathBuf,
+
+        /// Session to read from a database. Defaults to the latest
+        #[arg(long)]
+        session: Option<i64>
+    },
+    /// Sightings and estimated transmitter locations as <output>.geojson and <output>.kml
+    Map {
+        /// logged_packets.txt or a .sqlite database
+        log: PathBuf,
+        output: String,
+
+        /// Session to read from a database. Defaults to the latest
+        #[arg(long)]
+        session: Option<i64>
+    }
+}
+
+// A flag for every config field. Anything given replaces the value from the config file.
+#[derive(Args, Default)]
+pub struct ConfigOverrides {
+    /// Wireless interface to put into monitor mode
+    #[arg(long)]
+    interface: Option<String>,
+    /// Channel to stay on when not hopping
+    #[arg(long)]
+    channel: Option<u8>,
+    #[arg(long, value_name = "DEVICE")]
+    gps_serial_location: Option<String>,
+    #[arg(long)]
+    gps_baud_rate: Option<u32>,
+    #[arg(long, value_name = "serial|gpsd|nmea-file|gpx")]
+    gps_backend: Option<String>,
+    #[arg(long, value_name = "HOST:PORT")]
+    gpsd_address: Option<String>,
+    /// NMEA log or GPX track for the nmea-file and gpx backends
+    #[arg(long, value_name = "PATH")]
+    gps_replay_file: Option<String>,
+    #[arg(long)]
+    replay_speed: Option<f64>,
+    /// BPF filter applied to the capture
+    #[arg(long, value_name = "FILTER")]
+    bpf_filter: Option<String>,
+    /// Tagged parameter numbers to keep, e.g. 0,48,221
+    #[arg(long, value_delimiter = ',', value_name = "TAGS")]
+    tags: Option<Vec<u8>>,
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_name = "BOOL")]
+    skip_hidden_ssids: Option<bool>,
+    #[arg(long, value_name = "MS")]
+    packet_timeout_ms: Option<i32>,
+    /// Directory relative sink paths and the session summaries are written to
+    #[arg(long, value_name = "PATH")]
+    output_directory: Option<String>,
+    /// Replaces the configured sinks. TYPE:PATH (e.g. csv:log.csv, pcapng:archive) or the JSON of a sink. Repeatable
+    #[arg(long = "sink", value_name = "TYPE:PATH")]
+    sinks: Vec<String>,
+    #[arg(long, value_name = "MINUTES")]
+    sink_rotate_minutes: Option<u64>,
+    /// Hop between these channels, e.g. 1,6,11
+    #[arg(long, value_delimiter = ',', value_name = "CHANNELS")]
+    hop_channels: Option<Vec<u8>>,
+    /// Dwell time on each of --hop-channels. Defaults to 250
+    #[arg(long, requires = "hop_channels", value_name = "MS")]
+    hop_dwell_ms: Option<u64>,
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_name = "BOOL")]
+    adaptive_hopping: Option<bool>,
+    /// Stay on --channel even if the config hops
+    #[arg(long, conflicts_with = "hop_channels")]
+    no_channel_hopping: bool,
+    #[arg(long, value_name = "nl80211|airmon-ng")]
+    interface_backend: Option<String>,
+    #[arg(long, value_name = "20-noht|20|40|80")]
+    channel_width: Option<String>,
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_name = "BOOL")]
+    require_valid_fix: Option<bool>,
+    #[arg(long)]
+    min_fix_quality: Option<u8>,
+    #[arg(long)]
+    min_satellites: Option<u8>,
+    #[arg(long)]
+    max_hdop: Option<f64>,
+    #[arg(long)]
+    max_pdop: Option<f64>,
+    #[arg(long, value_name = "MS")]
+    max_fix_age_ms: Option<u64>,
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_name = "BOOL")]
+    dead_reckoning: Option<bool>,
+    #[arg(long, value_name = "MS")]
+    max_dead_reckoning_ms: Option<u64>,
+    #[arg(long)]
+    queue_capacity: Option<usize>,
+    #[arg(long, value_name = "block|drop-newest|drop-oldest")]
+    drop_policy: Option<String>,
+    #[arg(long)]
+    flush_items: Option<usize>,
+    #[arg(long, value_name = "MS")]
+    flush_interval_ms: Option<u64>,
+    #[arg(long)]
+    monitor_mode_attempts: Option<u32>,
+    #[arg(long, value_name = "MS")]
+    monitor_mode_retry_delay_ms: Option<u64>,
+    #[arg(long)]
+    gps_attempts: Option<u32>,
+    #[arg(long, value_name = "MS")]
+    gps_retry_delay_ms: Option<u64>
+}
+
+// Sets config[path[0]][path[1]].. to value, creating objects on the way
+fn set(config: &mut Map<String, Value>, path: &[&str], value: Value) {
+    match path {
+        [] => {},
+        [field] => { config.insert(field.to_string(), value); },
+        [field, rest @ ..] => {
+            let child = config.entry(field.to_string()).or_insert_with(|| json!({}));
+
+            if !child.is_object() {
+                *child = json!({});
+            }
+
+            if let Some(child) = child.as_object_mut() {
+                set(child, rest, value);
+            }
+        }
+    }
+}
+
+// csv:log.csv is {"type": "csv", "path": "log.csv"}. pcapng takes a directory rather than a path.
+fn parse_sink(sink: &str) -> Result<Value, String> {
+    if sink.trim_start().starts_with('{') {
+        return serde_json::from_str(sink).map_err(|e| format!("Invalid sink {}: {}", sink, e));
+    }
+
+    match sink.split_once(':') {
+        Some(("pcapng", directory)) => Ok(json!({"type": "pcapng", "directory": directory})),
+        Some((sink_type, path)) => Ok(json!({"type": sink_type, "path": path})),
+        None => Err(format!("Invalid sink {}. Expected TYPE:PATH", sink))
+    }
+}
+
+impl ConfigOverrides {
+    // Works on the JSON form of the config so the overrides go through the same deserialization as the file does
+    pub fn apply(&self, config: &mut Value) -> Result<(), String> {
+        let config = config.as_object_mut().ok_or("The config has to be an object")?;
+
+        let fields: [(&[&str], Option<Value>); 34] = [
+            (&["interface"], self.interface.as_ref().map(|value| json!(value))),
+            (&["channel"], self.channel.map(|value| json!(value))),
+            (&["gps_serial_location"], self.gps_serial_location.as_ref().map(|value| json!(value))),
+            (&["gps_baud_rate"], self.gps_baud_rate.map(|value| json!(value))),
+            (&["gps_backend"], self.gps_backend.as_ref().map(|value| json!(value))),
+            (&["gpsd_address"], self.gpsd_address.as_ref().map(|value| json!(value))),
+            (&["gps_replay_file"], self.gps_replay_file.as_ref().map(|value| json!(value))),
+            (&["replay_speed"], self.replay_speed.map(|value| json!(value))),
+            (&["capture", "bpf_filter"], self.bpf_filter.as_ref().map(|value| json!(value))),
+            (&["capture", "tags"], self.tags.as_ref().map(|value| json!(value))),
+            (&["capture", "skip_hidden_ssids"], self.skip_hidden_ssids.map(|value| json!(value))),
+            (&["capture", "packet_timeout_ms"], self.packet_timeout_ms.map(|value| json!(value))),
+            (&["output_directory"], self.output_directory.as_ref().map(|value| json!(value))),
+            (&["sink_rotate_minutes"], self.sink_rotate_minutes.map(|value| json!(value))),
+            (&["interface_backend"], self.interface_backend.as_ref().map(|value| json!(value))),
+            (&["channel_width"], self.channel_width.as_ref().map(|value| json!(value))),
+            (&["fix_thresholds", "require_valid"], self.require_valid_fix.map(|value| json!(value))),
+            (&["fix_thresholds", "min_fix_quality"], self.min_fix_quality.map(|value| json!(value))),
+            (&["fix_thresholds", "min_satellites"], self.min_satellites.map(|value| json!(value))),
+            (&["fix_thresholds", "max_hdop"], self.max_hdop.map(|value| json!(value))),
+            (&["fix_thresholds", "max_pdop"], self.max_pdop.map(|value| json!(value))),
+            (&["fix_thresholds", "max_fix_age_ms"], self.max_fix_age_ms.map(|value| json!(value))),
+            (&["fix_thresholds", "dead_reckoning"], self.dead_reckoning.map(|value| json!(value))),
+            (&["fix_thresholds", "max_dead_reckoning_ms"], self.max_dead_reckoning_ms.map(|value| json!(value))),
+            (&["pipeline", "queue_capacity"], self.queue_capacity.map(|value| json!(value))),
+            (&["pipeline", "drop_policy"], self.drop_policy.as_ref().map(|value| json!(value))),
+            (&["pipeline", "flush_items"], self.flush_items.map(|value| json!(value))),
+            (&["pipeline", "flush_interval_ms"], self.flush_interval_ms.map(|value| json!(value))),
+            (&["retries", "monitor_mode_attempts"], self.monitor_mode_attempts.map(|value| json!(value))),
+            (&["retries", "monitor_mode_retry_delay_ms"], self.monitor_mode_retry_delay_ms.map(|value| json!(value))),
+            (&["retries", "gps_attempts"], self.gps_attempts.map(|value| json!(value))),
+            (&["retries", "gps_retry_delay_ms"], self.gps_retry_delay_ms.map(|value| json!(value))),
+            (&["channel_hopping", "adaptive"], self.adaptive_hopping.map(|value| json!(value))),
+            (&["channel_hopping", "channels"], self.hop_channels.as_ref().map(|channels| {
+                json!(channels.iter().map(|&channel| match self.hop_dwell_ms {
+                    Some(dwell_ms) => json!({"channel": channel, "dwell_ms": dwell_ms}),
+                    None => json!({"channel": channel})
+                }).collect::<Vec<Value>>())
+            }))
+        ];
+
+        for (path, value) in fields {
+            if let Some(value) = value {
+                set(config, path, value);
+            }
+        }
+
+        if !self.sinks.is_empty() {
+            let sinks = self.sinks.iter().map(|sink| parse_sink(sink)).collect::<Result<Vec<Value>, String>>()?;
+
+            set(config, &["sinks"], json!(sinks));
+        }
+
+        if self.no_channel_hopping {
+            set(config, &["channel_hopping"], Value::Null);
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, Config};
+    use crate::pipeline::DropPolicy;
+    use crate::sinks::SinkConfig;
+
+    fn base_config() -> Value {
+        json!({
+            "interface": "wlan1",
+            "channel": 1,
+            "gps_serial_location": "/dev/serial0",
+            "channel_hopping": {"channels": [{"channel": 1}, {"channel": 6, "dwell_ms": 500}]}
+        })
+    }
+
+    fn overrides(arguments: &[&str]) -> ConfigOverrides {
+        let cli = Cli::try_parse_from([&["wifi_logging", "check"], arguments].concat()).unwrap_or_else(|e| panic!("{}", e));
+
+        match cli.command {
+            Some(Command::Check {overrides}) => overrides,
+            _ => panic!("not a check command")
+        }
+    }
+
+    fn applied(arguments: &[&str]) -> Result<Config, Vec<String>> {
+        let mut config = base_config();
+        overrides(arguments).apply(&mut config).map_err(|e| vec![e])?;
+
+        config::from_value(config)
+    }
+
+    #[test]
+    fn nothing_given_leaves_the_config_alone() {
+        let mut config = base_config();
+        ConfigOverrides::default().apply(&mut config).unwrap();
+
+        assert_eq!(config, base_config());
+    }
+
+    #[test]
+    fn nested_fields_are_set_without_losing_their_siblings() {
+        let config = applied(&["--interface", "wlan2", "--max-hdop", "2.5", "--drop-policy", "drop-oldest", "--flush-items", "10"]).ok().unwrap();
+
+        assert_eq!(config.interface, "wlan2");
+        assert_eq!(config.fix_thresholds.max_hdop, 2.5);
+        assert_eq!(config.fix_thresholds.max_pdop, 20.0);
+        assert!(matches!(config.pipeline.drop_policy, DropPolicy::DropOldest));
+        assert_eq!(config.pipeline.flush_items, 10);
+
+        // An object is made for a field whose parent isnt one
+        let mut value = json!({"capture": null});
+        set(value.as_object_mut().unwrap(), &["capture", "bpf_filter"], json!("type mgt"));
+        assert_eq!(value, json!({"capture": {"bpf_filter": "type mgt"}}));
+    }
+
+    #[test]
+    fn sinks_replace_the_configured_ones() {
+        let config = applied(&["--sink", "csv:x.csv", "--sink", "pcapng:archive", "--sink", r#"{"type": "pcapng", "directory": "big", "rotate_megabytes": 512}"#]).ok().unwrap();
+
+        assert_eq!(config.sinks.len(), 3);
+        assert!(matches!(&config.sinks[0], SinkConfig::Csv {path} if path == "x.csv"));
+        assert!(matches!(&config.sinks[1], SinkConfig::Pcapng {directory, rotate_megabytes: 64} if directory == "archive"));
+        assert!(matches!(&config.sinks[2], SinkConfig::Pcapng {directory, rotate_megabytes: 512} if directory == "big"));
+    }
+
+    #[test]
+    fn parsed_sinks() {
+        assert_eq!(parse_sink("csv:x"), Ok(json!({"type": "csv", "path": "x"})));
+        assert_eq!(parse_sink("pcapng:dir"), Ok(json!({"type": "pcapng", "directory": "dir"})));
+        // Only the first colon separates the type
+        assert_eq!(parse_sink("text:C:/logs/log.txt"), Ok(json!({"type": "text", "path": "C:/logs/log.txt"})));
+        assert_eq!(parse_sink(r#" {"type": "sqlite", "path": "s.sqlite"}"#), Ok(json!({"type": "sqlite", "path": "s.sqlite"})));
+
+        assert!(parse_sink("csv").is_err());
+        assert!(parse_sink(r#"{"type": "csv""#).is_err());
+    }
+
+    #[test]
+    fn malformed_values_are_reported() {
+        assert!(applied(&["--sink", "csv"]).is_err());
+
+        let problems = applied(&["--drop-policy", "sometimes"]).err().unwrap();
+        assert!(problems[0].starts_with("pipeline.drop_policy: "), "{:?}", problems);
+
+        let problems = applied(&["--sink", "ftp:x"]).err().unwrap();
+        assert!(problems[0].starts_with("sinks[0]"), "{:?}", problems);
+
+        assert!(Cli::try_parse_from(["wifi_logging", "check", "--channel", "300"]).is_err());
+    }
+
+    #[test]
+    fn hop_channels_replace_the_configured_ones() {
+        let config = applied(&["--hop-channels", "1,6,11", "--hop-dwell-ms", "100", "--adaptive-hopping"]).ok().unwrap();
+        let channel_hopping = config.channel_hopping.unwrap();
+
+        assert_eq!(channel_hopping.channels.iter().map(|hop_channel| (hop_channel.channel, hop_channel.dwell_ms)).collect::<Vec<_>>(), vec![(1, 100), (6, 100), (11, 100)]);
+        assert!(channel_hopping.adaptive);
+
+        // Without --hop-dwell-ms the channels get the default dwell
+        let config = applied(&["--hop-channels", "36,40"]).ok().unwrap();
+        assert_eq!(config.channel_hopping.unwrap().channels.iter().map(|hop_channel| hop_channel.dwell_ms).collect::<Vec<_>>(), vec![250, 250]);
+    }
+
+    #[test]
+    fn hop_dwell_needs_hop_channels() {
+        assert!(Cli::try_parse_from(["wifi_logging", "check", "--hop-dwell-ms", "100"]).is_err());
+    }
+
+    #[test]
+    fn channel_hopping_can_be_turned_off() {
+        let config = applied(&["--no-channel-hopping", "--channel", "11"]).ok().unwrap();
+
+        assert!(config.channel_hopping.is_none());
+        assert_eq!(config.channel, 11);
+
+        assert!(Cli::try_parse_from(["wifi_logging", "check", "--no-channel-hopping", "--hop-channels", "1"]).is_err());
+    }
+}
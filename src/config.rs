@@ -0,0 +1,287 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::{
+    fs,
+    path::Path
+};
+use crate::capture::{self, CaptureConfig};
+use crate::channel_hopper::{ChannelHoppingConfig, HopChannel};
+use crate::gps::FixThresholds;
+use crate::gpsd;
+use crate::interface_manager::InterfaceBackend;
+use crate::location_source::GpsBackend;
+use crate::nl80211::{self, ChannelWidth};
+use crate::pipeline::PipelineConfig;
+use crate::sinks::{SinkConfig, default_sinks};
+use crate::tagged_params::tagged_params_ws;
+
+// Bumped whenever a field changes meaning or is removed. Files without config_version are version 1, the original
+// interface / channel / gps_serial_location layout, which reads as is because every field added in 2 has a default.
+pub const CONFIG_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "default_config_version")]
+    pub config_version: u32,
+    pub interface: String,
+    pub channel: u8,
+    pub gps_serial_location: String,
+    #[serde(default = "default_gps_baud_rate")]
+    pub gps_baud_rate: u32,
+    // "serial" (default), "gpsd", "nmea-file" or "gpx"
+    #[serde(default)]
+    pub gps_backend: GpsBackend,
+    #[serde(default = "default_gpsd_address")]
+    pub gpsd_address: String,
+    // NMEA log or GPX track played back by the "nmea-file" and "gpx" backends
+    #[serde(default)]
+    pub gps_replay_file: Option<String>,
+    // Playback speed of replayed locations and, when replaying both, of pcap_file. 1.0 is real time
+    #[serde(default = "default_replay_speed")]
+    pub replay_speed: f64,
+    // Read frames from this .pcap / .pcapng file instead of putting the interface into monitor mode
+    #[serde(default)]
+    pub pcap_file: Option<String>,
+    // BPF filter, the tagged parameters kept from each frame and whether hidden networks are logged
+    #[serde(default)]
+    pub capture: CaptureConfig,
+    // Relative sink paths and session_summaries.jsonl are from here. Defaults to the directory of the config file
+    #[serde(default)]
+    pub output_directory: Option<String>,
+    // Where sightings are written. Any number of text, csv, jsonl, sqlite and pcapng sinks can be used at once
+    #[serde(default = "default_sinks")]
+    pub sinks: Vec<SinkConfig>,
+    // Start new output files this often. pcapng archives also rotate on size
+    #[serde(default)]
+    pub sink_rotate_minutes: Option<u64>,
+    // Queue between the capture and the sinks, what happens when it fills and how often the sinks are flushed
+    #[serde(default)]
+    pub pipeline: PipelineConfig,
+    // Hop between these channels instead of staying on `channel`
+    #[serde(default)]
+    pub channel_hopping: Option<ChannelHoppingConfig>,
+    // "nl80211" (default) or "airmon-ng"
+    #[serde(default)]
+    pub interface_backend: InterfaceBackend,
+    // Only used by the nl80211 backend. "20-noht" (default), "20", "40" or "80"
+    #[serde(default)]
+    pub channel_width: ChannelWidth,
+    // Sightings are logged without a position unless the GPS fix meets all of these
+    #[serde(default)]
+    pub fix_thresholds: FixThresholds,
+    // How many times starting monitor mode and the location source are tried
+    #[serde(default)]
+    pub retries: RetryConfig
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    pub monitor_mode_attempts: u32,
+    pub monitor_mode_retry_delay_ms: u64,
+    pub gps_attempts: u32,
+    pub gps_retry_delay_ms: u64
+}
+
+impl Default for RetryConfig {
+    fn default() -> RetryConfig {
+        RetryConfig {monitor_mode_attempts: 5, monitor_mode_retry_delay_ms: 3_000, gps_attempts: 3, gps_retry_delay_ms: 3_000}
+    }
+}
+
+pub fn example_config() -> Config {
+    Config {
+        config_version: CONFIG_VERSION,
+        interface: "wlan1".to_string(),
+        channel: 1,
+        gps_serial_location: "/dev/serial0".to_string(),
+        gps_baud_rate: default_gps_baud_rate(),
+        gps_backend: GpsBackend::default(),
+        gpsd_address: default_gpsd_address(),
+        gps_replay_file: None,
+        replay_speed: default_replay_speed(),
+        pcap_file: None,
+        capture: CaptureConfig::default(),
+        output_directory: None,
+        sinks: default_sinks(),
+        sink_rotate_minutes: None,
+        pipeline: PipelineConfig::default(),
+        channel_hopping: Some(ChannelHoppingConfig {
+            channels: [1, 6, 11].iter().map(|&channel| HopChannel {channel: channel, dwell_ms: 250}).collect(),
+            adaptive: false
+        }),
+        interface_backend: InterfaceBackend::default(),
+        channel_width: ChannelWidth::default(),
+        fix_thresholds: FixThresholds::default(),
+        retries: RetryConfig::default()
+    }
+}
+
+fn default_config_version() -> u32 {
+    1
+}
+
+fn default_gps_baud_rate() -> u32 {
+    9_600
+}
+
+fn default_gpsd_address() -> String {
+    gpsd::DEFAULT_ADDRESS.to_string()
+}
+
+fn default_replay_speed() -> f64 {
+    1.0
+}
+
+// A .toml file is read as TOML, anything else as JSON. Both end up as the same JSON value so the command line overrides
+// and the deserialization work the same for either.
+pub fn read_config_file(path: &Path) -> Result<Value, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => toml::from_str(&contents).map_err(|e| format!("{} isn't valid TOML: {}", path.display(), e)),
+        _ => serde_json::from_str(&contents).map_err(|e| format!("{} isn't valid JSON: {}", path.display(), e))
+    }
+}
+
+// Errors name the field they are about, e.g. "fix_thresholds.max_hdop: invalid type: string, expected f64"
+pub fn from_value(config_value: Value) -> Result<Config, Vec<String>> {
+    let config: Config = serde_path_to_error::deserialize(config_value).map_err(|e| vec![match e.path().to_string().as_str() {
+        "." => e.inner().to_string(),
+        path => format!("{}: {}", path, e.inner())
+    }])?;
+
+    config.validate()?;
+
+    return Ok(config);
+}
+
+fn check_channel(problems: &mut Vec<String>, field: &str, channel: u8) {
+    if nl80211::channel_to_frequency(channel).is_none() {
+        problems.push(format!("{}: {} isn't a 2.4 or 5 GHz channel", field, channel));
+    }
+}
+
+fn check_positive(problems: &mut Vec<String>, field: &str, value: f64) {
+    if !(value.is_finite() && value > 0.0) {
+        problems.push(format!("{}: has to be more than 0, not {}", field, value));
+    }
+}
+
+impl Config {
+    // Everything serde cant check. Returns every problem rather than just the first.
+    fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems: Vec<String> = Vec::new();
+
+        if self.config_version > CONFIG_VERSION {
+            problems.push(format!("config_version: {} is newer than this build understands ({})", self.config_version, CONFIG_VERSION));
+        }
+
+        if self.pcap_file.is_none() && self.interface.trim().is_empty() {
+            problems.push("interface: cant be empty without a pcap_file".to_string());
+        }
+
+        check_channel(&mut problems, "channel", self.channel);
+
+        if self.gps_baud_rate == 0 {
+            problems.push("gps_baud_rate: cant be 0".to_string());
+        }
+
+        match self.gps_backend {
+            GpsBackend::NmeaFile | GpsBackend::Gpx if self.gps_replay_file.is_none() => {
+                problems.push("gps_replay_file: needed by the nmea-file and gpx backends".to_string());
+            },
+            _ => {}
+        }
+
+        check_positive(&mut problems, "replay_speed", self.replay_speed);
+
+        if let Err(e) = capture::check_bpf_filter(&self.capture.bpf_filter) {
+            problems.push(format!("capture.bpf_filter: {}", e));
+        }
+
+        if self.capture.tags.is_empty() {
+            problems.push("capture.tags: at least one tag number is needed, frames without any of them are skipped".to_string());
+        }
+
+        if self.capture.skip_hidden_ssids && !self.capture.tags.contains(&tagged_params_ws::SSID) {
+            problems.push(format!("capture.skip_hidden_ssids: needs the SSID ({}) in capture.tags", tagged_params_ws::SSID));
+        }
+
+        if self.capture.packet_timeout_ms <= 0 {
+            problems.push("capture.packet_timeout_ms: has to be more than 0".to_string());
+        }
+
+        if self.sinks.is_empty() {
+            problems.push("sinks: at least one sink is needed".to_string());
+        }
+
+        for (index, sink) in self.sinks.iter().enumerate() {
+            if let SinkConfig::Pcapng {rotate_megabytes: 0, ..} = sink {
+                problems.push(format!("sinks[{}].rotate_megabytes: cant be 0", index));
+            }
+        }
+
+        if self.sink_rotate_minutes == Some(0) {
+            problems.push("sink_rotate_minutes: cant be 0".to_string());
+        }
+
+        if self.pipeline.queue_capacity == 0 {
+            problems.push("pipeline.queue_capacity: cant be 0".to_string());
+        }
+
+        if self.pipeline.flush_items == 0 {
+            problems.push("pipeline.flush_items: cant be 0".to_string());
+        }
+
+        if let Some(channel_hopping) = &self.channel_hopping {
+            if channel_hopping.channels.is_empty() {
+                problems.push("channel_hopping.channels: at least one channel is needed. Leave channel_hopping out to stay on channel".to_string());
+            }
+
+            for (index, hop_channel) in channel_hopping.channels.iter().enumerate() {
+                check_channel(&mut problems, &format!("channel_hopping.channels[{}].channel", index), hop_channel.channel);
+
+                if hop_channel.dwell_ms == 0 {
+                    problems.push(format!("channel_hopping.channels[{}].dwell_ms: cant be 0", index));
+                }
+            }
+        }
+
+        check_positive(&mut problems, "fix_thresholds.max_hdop", self.fix_thresholds.max_hdop);
+        check_positive(&mut problems, "fix_thresholds.max_pdop", self.fix_thresholds.max_pdop);
+
+        if self.retries.monitor_mode_attempts == 0 {
+            problems.push("retries.monitor_mode_attempts: has to be at least 1".to_string());
+        }
+
+        if self.retries.gps_attempts == 0 {
+            problems.push("retries.gps_attempts: has to be at least 1".to_string());
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+
+        return Err(problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn version_1_config() -> Value {
+        json!({"interface": "wlan1", "channel": 6, "gps_serial_location": "/dev/serial0"})
+    }
+
+    #[test]
+    fn pcapng_sinks_have_to_rotate() {
+        let mut config_value = version_1_config();
+        config_value["sinks"] = json!([{"type": "text", "path": "log.txt"}, {"type": "pcapng", "directory": "archive", "rotate_megabytes": 0}]);
+
+        assert_eq!(from_value(config_value).err(), Some(vec!["sinks[1].rotate_megabytes: cant be 0".to_string()]));
+    }
+}
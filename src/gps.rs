@@ -1,47 +1,366 @@
 use serialport;
+use serde::{Serialize, Deserialize};
 use std::{
-    time::Duration,
-    collections::HashMap,
-    io::{Error as io_error, BufReader, BufRead},
-    sync::{Arc, atomic::{AtomicU64, Ordering::Release}}
+    time::{Duration, SystemTime, UNIX_EPOCH},
+    io::{Error as io_error, BufReader, BufRead}
 };
+use arc_swap::ArcSwap;
+use crate::capture::GpsDataDecoded;
+use crate::nmea::{self, Sentence, SentenceData, Rmc, Gga, Gsa, Vtg, Zda, Date};
 
-fn nema_coords_to_regular_coords(degree: &str, direction: &str) -> f64 {
-    let degree = match degree.parse::<f64>() {
-        Ok(degree) => degree,
-        Err(_e) => { return 0.0 }
+const METERS_PER_SECOND_PER_KNOT: f64 = 1_852.0 / 3_600.0;
+// Mean earth radius. Plenty for the few meters that are dead reckoned between fixes.
+const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+// Fixes further apart than this are too far apart to get a useful speed and course from
+const MAX_DERIVED_VELOCITY_SECONDS: f64 = 10.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PositionMethod {
+    Measured, // The fix itself
+    DeadReckoned, // Moved on from the fix using the speed and course the receiver reported
+    Extrapolated, // Moved on from the fix using the speed and course between the last two fixes
+    Interpolated, // Between the fix and the next one, which is only known when replaying
+    NoPosition // The fix didnt meet the thresholds
+}
+
+impl PositionMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PositionMethod::Measured => "MEASURED",
+            PositionMethod::DeadReckoned => "DEAD_RECKONED",
+            PositionMethod::Extrapolated => "EXTRAPOLATED",
+            PositionMethod::Interpolated => "INTERPOLATED",
+            PositionMethod::NoPosition => "NO_POSITION"
+        }
+    }
+}
+
+// A position and the GPS time it was recorded at
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fix {
+    pub lat: f64,
+    pub lon: f64,
+    pub utc: SystemTime
+}
+
+// Latest fix written by the location source. Every update swaps in a whole new snapshot so the capture thread never sees
+// fields from two different fixes, and reads are lock free.
+pub struct SharedGpsData {
+    snapshot: ArcSwap<GpsDataDecoded>
+}
+
+impl SharedGpsData {
+    pub fn new() -> SharedGpsData {
+        SharedGpsData {snapshot: ArcSwap::from_pointee(GpsDataDecoded::no_fix())}
+    }
+
+    pub fn load(&self) -> GpsDataDecoded {
+        let mut gps_data = (**self.snapshot.load()).clone();
+
+        // Only set per frame once the fix has been checked against the thresholds
+        gps_data.has_position = false;
+
+        return gps_data;
+    }
+
+    // Changes a copy of the current snapshot and swaps it in. rcu retries if another update lands in between.
+    fn update<F: Fn(&mut GpsDataDecoded)>(&self, change: F) {
+        self.snapshot.rcu(|current| {
+            let mut next = (**current).clone();
+            change(&mut next);
+
+            next
+        });
+    }
+
+    // utc is seconds since the unix epoch. Coordinates that are None keep the last ones so a lost fix doesn't jump to 0, 0
+    // speed is in meters per second and course in degrees true. If the source didnt report them they are worked out from
+    // the distance to the previous fix.
+    pub fn store_position(&self, lat: Option<f64>, lon: Option<f64>, valid: bool, utc: Option<f64>, speed: Option<f64>, course: Option<f64>) {
+        self.store_position_with_next_fix(lat, lon, valid, utc, speed, course, None);
+    }
+
+    // Replays know where the fix after this one is as (lat, lon, utc). It is stored in the same update as the position so
+    // a frame never sees the new position without it.
+    pub fn store_position_with_next_fix(&self, lat: Option<f64>, lon: Option<f64>, valid: bool, utc: Option<f64>, speed: Option<f64>, course: Option<f64>, next_fix: Option<(f64, f64, f64)>) {
+        let now = SystemTime::now();
+        let next_fix = next_fix.and_then(|(lat, lon, utc)| utc_to_system_time(Some(utc)).map(|utc| Fix {lat: lat, lon: lon, utc: utc}));
+
+        self.update(|gps_data| {
+            let utc = utc_to_system_time(utc);
+
+            match (speed, course) {
+                (Some(_), _) | (_, Some(_)) => {
+                    gps_data.speed = speed;
+                    gps_data.course = course;
+                    gps_data.velocity_measured = true;
+                },
+                _ => {
+                    let velocity = match (lat, lon) {
+                        (Some(lat), Some(lon)) if valid && gps_data.fix_valid => {
+                            // The GPS time is more accurate than when the sentence happened to be read
+                            let elapsed = match (gps_data.utc, utc) {
+                                (Some(previous_utc), Some(utc)) => utc.duration_since(previous_utc).ok(),
+                                _ => gps_data.fix_received_at.and_then(|previous| now.duration_since(previous).ok())
+                            };
+
+                            elapsed.and_then(|elapsed| derive_velocity(gps_data.lat, gps_data.lon, lat, lon, elapsed))
+                        },
+                        _ => None
+                    };
+
+                    gps_data.speed = velocity.map(|(speed, _)| speed);
+                    gps_data.course = velocity.map(|(_, course)| course);
+                    gps_data.velocity_measured = false;
+                }
+            }
+
+            if let (Some(lat), Some(lon)) = (lat, lon) {
+                gps_data.lat = lat;
+                gps_data.lon = lon;
+            }
+
+            gps_data.fix_valid = valid;
+            gps_data.utc = utc;
+            gps_data.fix_received_at = Some(now);
+            gps_data.next_fix = next_fix;
+        });
+    }
+
+    // VTG arrives separately from the RMC it belongs to
+    pub fn store_velocity(&self, speed: Option<f64>, course: Option<f64>) {
+        self.update(|gps_data| {
+            gps_data.speed = speed.or(gps_data.speed);
+            gps_data.course = course.or(gps_data.course);
+            gps_data.velocity_measured = true;
+        });
+    }
+
+    pub fn store_utc(&self, utc: Option<f64>) {
+        self.update(|gps_data| gps_data.utc = utc_to_system_time(utc));
+    }
+
+    // Same meaning as the GGA fix quality
+    pub fn store_fix_quality(&self, fix_quality: u8) {
+        self.update(|gps_data| gps_data.fix_quality = Some(fix_quality));
+    }
+
+    pub fn store_satellites_used(&self, satellites_used: Option<u8>) {
+        self.update(|gps_data| gps_data.satellites_used = satellites_used);
+    }
+
+    pub fn store_hdop(&self, hdop: Option<f64>) {
+        self.update(|gps_data| gps_data.hdop = hdop);
+    }
+
+    pub fn store_altitude(&self, altitude: Option<f64>) {
+        self.update(|gps_data| gps_data.altitude = altitude);
+    }
+
+    pub fn store_pdop(&self, pdop: Option<f64>) {
+        self.update(|gps_data| gps_data.pdop = pdop);
+    }
+
+    // Sentences that don't carry anything used here (GSV, VTG, ...) are ignored
+    pub fn store_sentence(&self, sentence: &Sentence) {
+        self.store_sentence_with_next_fix(sentence, None);
+    }
+
+    // next_fix is only used for RMC, as that is what the position comes from
+    pub fn store_sentence_with_next_fix(&self, sentence: &Sentence, next_fix: Option<(f64, f64, f64)>) {
+        match &sentence.data {
+            SentenceData::Rmc(rmc) => self.store_rmc(rmc, next_fix),
+            SentenceData::Gga(gga) => self.store_gga(gga),
+            SentenceData::Gsa(gsa) => self.store_gsa(gsa),
+            SentenceData::Zda(zda) => self.store_zda(zda),
+            SentenceData::Vtg(vtg) => self.store_vtg(vtg),
+            _ => {}
+        }
+    }
+
+    fn store_rmc(&self, rmc: &Rmc, next_fix: Option<(f64, f64, f64)>) {
+        // A void RMC keeps the last coordinates but marks them invalid
+        let speed = rmc.speed_knots.map(|speed_knots| speed_knots * METERS_PER_SECOND_PER_KNOT);
+
+        self.store_position_with_next_fix(rmc.latitude, rmc.longitude, rmc.valid, nmea_utc(rmc.date.as_ref(), rmc.time.as_ref()), speed, rmc.course, next_fix);
+    }
+
+    fn store_gga(&self, gga: &Gga) {
+        self.update(|gps_data| {
+            gps_data.fix_quality = Some(gga.fix_quality);
+            gps_data.satellites_used = gga.satellites_used;
+            gps_data.hdop = gga.hdop;
+            gps_data.altitude = gga.altitude.or(gps_data.altitude);
+        });
+    }
+
+    fn store_gsa(&self, gsa: &Gsa) {
+        self.store_pdop(gsa.pdop);
+    }
+
+    fn store_vtg(&self, vtg: &Vtg) {
+        let speed = vtg.speed_kmh.map(|speed_kmh| speed_kmh / 3.6).or(vtg.speed_knots.map(|speed_knots| speed_knots * METERS_PER_SECOND_PER_KNOT));
+
+        self.store_velocity(speed, vtg.course_true);
+    }
+
+    fn store_zda(&self, zda: &Zda) {
+        self.store_utc(nmea_utc(zda.date.as_ref(), zda.time.as_ref()));
+    }
+}
+
+// Speed in meters per second and course in degrees true to go from the first position to the second in elapsed
+fn derive_velocity(from_lat: f64, from_lon: f64, to_lat: f64, to_lon: f64, elapsed: Duration) -> Option<(f64, f64)> {
+    let seconds = elapsed.as_secs_f64();
+
+    if seconds <= 0.0 || seconds > MAX_DERIVED_VELOCITY_SECONDS {
+        return None;
+    }
+
+    // Equirectangular approximation. Fixes are close enough together that the curvature doesnt matter.
+    let north = (to_lat - from_lat).to_radians() * EARTH_RADIUS_METERS;
+    let east = (to_lon - from_lon).to_radians() * EARTH_RADIUS_METERS * ((from_lat + to_lat) / 2.0).to_radians().cos();
+
+    let course = east.atan2(north).to_degrees().rem_euclid(360.0);
+
+    return Some((north.hypot(east) / seconds, course));
+}
+
+// Sets position_method and moves lat / lon on by the time between fix_time and frame_time. Frames between the fix and a
+// known next fix are placed on the line between the two. Otherwise the fix is used as is if there is no speed and course
+// or the gap is outside what the thresholds allow.
+pub fn estimate_position(gps_data: &mut GpsDataDecoded, fix_time: Option<SystemTime>, frame_time: SystemTime, fix_thresholds: &FixThresholds) {
+    gps_data.position_method = PositionMethod::Measured;
+
+    if !fix_thresholds.dead_reckoning {
+        return;
+    }
+
+    if let (Some(fix_utc), Some(next_fix)) = (gps_data.utc, gps_data.next_fix) {
+        // Fixes further apart than a fix can be old before it is stale arent close enough to draw a line between
+        match (frame_time.duration_since(fix_utc), next_fix.utc.duration_since(fix_utc)) {
+            (Ok(elapsed), Ok(gap)) if elapsed <= gap && !gap.is_zero() && gap <= Duration::from_millis(fix_thresholds.max_fix_age_ms) => {
+                let fraction = elapsed.as_secs_f64() / gap.as_secs_f64();
+                // The short way round, in case the two fixes are either side of 180 degrees
+                let lon_difference = (next_fix.lon - gps_data.lon + 180.0).rem_euclid(360.0) - 180.0;
+
+                gps_data.lat += (next_fix.lat - gps_data.lat) * fraction;
+                gps_data.lon = (gps_data.lon + lon_difference * fraction + 180.0).rem_euclid(360.0) - 180.0;
+                gps_data.position_method = PositionMethod::Interpolated;
+
+                return;
+            },
+            _ => {}
+        }
+    }
+
+    let elapsed = match fix_time.and_then(|fix_time| frame_time.duration_since(fix_time).ok()) {
+        Some(elapsed) if elapsed <= Duration::from_millis(fix_thresholds.max_dead_reckoning_ms) => elapsed,
+        _ => return
+    };
+
+    let (speed, course) = match (gps_data.speed, gps_data.course) {
+        (Some(speed), Some(course)) => (speed, course),
+        _ => return
     };
 
-    let degrees = (degree / 100.0).floor();
-    let minutes = degree - degrees * 100.0;
-    let mut decimal = degrees + minutes / 60.0;
+    let distance = speed * elapsed.as_secs_f64();
+    let north = distance * course.to_radians().cos();
+    let east = distance * course.to_radians().sin();
+
+    // Both steps are from the fix. The length of a degree of longitude depends on the latitude it is measured at.
+    let fix_lat = gps_data.lat;
+
+    gps_data.lat = fix_lat + (north / EARTH_RADIUS_METERS).to_degrees();
+    gps_data.lon += (east / (EARTH_RADIUS_METERS * fix_lat.to_radians().cos())).to_degrees();
+
+    gps_data.position_method = if gps_data.velocity_measured { PositionMethod::DeadReckoned } else { PositionMethod::Extrapolated };
+}
+
+fn utc_to_system_time(utc: Option<f64>) -> Option<SystemTime> {
+    utc.filter(|utc| utc.is_finite() && *utc >= 0.0).map(|utc| UNIX_EPOCH + Duration::from_secs_f64(utc))
+}
 
-    if direction.contains("S") || direction.contains("W") {
-        decimal = -decimal;
+pub fn nmea_utc(date: Option<&Date>, time: Option<&nmea::Time>) -> Option<f64> {
+    match (date, time) {
+        (Some(date), Some(time)) => Some(nmea::utc_timestamp(date, time)),
+        _ => None
     }
+}
 
-    return decimal
+// Sightings whose fix doesn't meet every threshold are logged without a position. Values the receiver never reported
+// (e.g. no GSA means no PDOP) aren't checked.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FixThresholds {
+    pub require_valid: bool, // RMC status must be A
+    pub min_fix_quality: u8, // GGA quality. 1 is a plain GPS fix
+    pub min_satellites: u8,
+    pub max_hdop: f64,
+    pub max_pdop: f64,
+    pub max_fix_age_ms: u64, // How old the last RMC can be before its position is considered stale
+    pub dead_reckoning: bool, // Move each frame's position on from the last fix using the speed and course
+    pub max_dead_reckoning_ms: u64 // Frames further than this from the fix get the fix position as is
+}
+
+impl Default for FixThresholds {
+    fn default() -> Self {
+        FixThresholds {require_valid: true, min_fix_quality: 1, min_satellites: 3, max_hdop: 10.0, max_pdop: 20.0, max_fix_age_ms: 5_000, dead_reckoning: true, max_dead_reckoning_ms: 2_000}
+    }
 }
 
-fn parse_nema(nema_string: &str) -> HashMap<&str, f64> {
-    let nema_parts: Vec<&str> = nema_string.split(",").collect();
+impl FixThresholds {
+    pub fn accepts(&self, gps_data: &GpsDataDecoded, now: SystemTime) -> bool {
+        let fix_received_at = match gps_data.fix_received_at {
+            Some(fix_received_at) => fix_received_at,
+            None => return false
+        };
+
+        if now.duration_since(fix_received_at).unwrap_or_default() > Duration::from_millis(self.max_fix_age_ms) {
+            return false;
+        }
+
+        if self.require_valid && !gps_data.fix_valid {
+            return false;
+        }
+
+        if gps_data.fix_quality.is_some_and(|fix_quality| fix_quality < self.min_fix_quality) {
+            return false;
+        }
+
+        if gps_data.satellites_used.is_some_and(|satellites_used| satellites_used < self.min_satellites) {
+            return false;
+        }
 
-    let mut nema_parsed: HashMap<&str, f64> = HashMap::new();
+        if gps_data.hdop.is_some_and(|hdop| hdop > self.max_hdop) || gps_data.pdop.is_some_and(|pdop| pdop > self.max_pdop) {
+            return false;
+        }
 
-    if nema_parts[0] == "$GNGGA" {
-        nema_parsed.insert("time", nema_parts[1].parse().unwrap_or(0.0));
-        nema_parsed.insert("lat", nema_coords_to_regular_coords(nema_parts[2], nema_parts[3]));
-        nema_parsed.insert("lon", nema_coords_to_regular_coords(nema_parts[4], nema_parts[5]));
-    } else if nema_parts[0] == "$GNRMC" {
-        nema_parsed.insert("time", nema_parts[1].parse().unwrap_or(0.0));
-        nema_parsed.insert("lat", nema_coords_to_regular_coords(nema_parts[3], nema_parts[4]));
-        nema_parsed.insert("lon", nema_coords_to_regular_coords(nema_parts[5], nema_parts[6]));
+        return true;
     }
+}
+
+// ISO 8601 in UTC with milliseconds, e.g. 2024-05-01T17:03:09.250Z
+pub fn format_utc(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds_of_day = since_epoch.as_secs() % 86_400;
 
-    return nema_parsed
+    let date = Date::from_days_since_unix_epoch((since_epoch.as_secs() / 86_400) as i64);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        date.year,
+        date.month,
+        date.day,
+        seconds_of_day / 3_600,
+        seconds_of_day % 3_600 / 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
 }
 
-pub fn start_gps(serial_device: &str, baud_rate: u32, atomic_coords: [&Arc<AtomicU64>; 3]) -> Result<(), std::io::Error> {
+pub fn start_gps(serial_device: &str, baud_rate: u32, shared_gps_data: &SharedGpsData) -> Result<(), std::io::Error> {
     let serial = serialport::new(serial_device, baud_rate).timeout(Duration::from_millis(10)).open()?;
 
     let serial_reader = BufReader::new(serial);
@@ -49,15 +368,13 @@ pub fn start_gps(serial_device: &str, baud_rate: u32, atomic_coords: [&Arc<Atomi
     for line in serial_reader.lines() {
         match line {
             Ok(line) => {
-                // line.starts_with("$GNGGA") || (Not parsing for this one because the coords are the same for each pair of gngga and gnrmc)
-                
-                if line.starts_with("$GNRMC") {
-                    let parsed_nema = parse_nema(&line);
-
-                    atomic_coords[0].store(parsed_nema.get("time").unwrap_or(&0.0).to_bits(), Release);
-                    atomic_coords[1].store(parsed_nema.get("lat").unwrap_or(&0.0).to_bits(), Release);
-                    atomic_coords[2].store(parsed_nema.get("lon").unwrap_or(&0.0).to_bits(), Release);
-                }
+                // Corrupted lines fail their checksum and are dropped here rather than producing bogus coordinates
+                let sentence = match nmea::parse(&line) {
+                    Ok(sentence) => sentence,
+                    Err(_e) => continue
+                };
+
+                shared_gps_data.store_sentence(&sentence);
             },
             Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {
                 continue;
@@ -68,3 +385,209 @@ pub fn start_gps(serial_device: &str, baud_rate: u32, atomic_coords: [&Arc<Atomi
 
     Err(io_error::new(std::io::ErrorKind::Other, "Ended early."))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIX_UTC: f64 = 1_714_582_989.0;
+
+    fn at(seconds_after_fix: f64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs_f64(FIX_UTC + seconds_after_fix)
+    }
+
+    fn fix(lat: f64, lon: f64, speed: Option<f64>, course: Option<f64>) -> GpsDataDecoded {
+        let mut gps_data = GpsDataDecoded::no_fix();
+
+        gps_data.lat = lat;
+        gps_data.lon = lon;
+        gps_data.utc = Some(at(0.0));
+        gps_data.speed = speed;
+        gps_data.course = course;
+        gps_data.velocity_measured = true;
+
+        return gps_data;
+    }
+
+    fn assert_close(value: f64, expected: f64) {
+        assert!((value - expected).abs() < 1e-9, "{} isnt {}", value, expected);
+    }
+
+    #[test]
+    fn dead_reckoned_from_the_reported_velocity() {
+        let mut gps_data = fix(10.0, 20.0, Some(10.0), Some(0.0));
+
+        estimate_position(&mut gps_data, Some(at(0.0)), at(1.5), &FixThresholds::default());
+
+        assert_eq!(gps_data.position_method, PositionMethod::DeadReckoned);
+        assert_close(gps_data.lat, 10.0 + (15.0 / EARTH_RADIUS_METERS).to_degrees());
+        assert_close(gps_data.lon, 20.0);
+    }
+
+    #[test]
+    fn both_steps_are_from_the_fix_latitude() {
+        let mut gps_data = fix(60.0, 20.0, Some(1_000.0), Some(45.0));
+        gps_data.velocity_measured = false;
+
+        estimate_position(&mut gps_data, Some(at(0.0)), at(1.0), &FixThresholds::default());
+
+        let step = 1_000.0 * 45.0_f64.to_radians().cos();
+
+        assert_eq!(gps_data.position_method, PositionMethod::Extrapolated);
+        assert_close(gps_data.lat, 60.0 + (step / EARTH_RADIUS_METERS).to_degrees());
+        assert_close(gps_data.lon, 20.0 + (step / (EARTH_RADIUS_METERS * 60.0_f64.to_radians().cos())).to_degrees());
+    }
+
+    #[test]
+    fn fix_is_used_as_is_outside_the_thresholds() {
+        let thresholds = FixThresholds::default();
+
+        // No velocity
+        let mut gps_data = fix(10.0, 20.0, None, None);
+        estimate_position(&mut gps_data, Some(at(0.0)), at(1.0), &thresholds);
+        assert_eq!((gps_data.lat, gps_data.lon, gps_data.position_method), (10.0, 20.0, PositionMethod::Measured));
+
+        // Too long after the fix
+        let mut gps_data = fix(10.0, 20.0, Some(10.0), Some(0.0));
+        estimate_position(&mut gps_data, Some(at(0.0)), at(2.5), &thresholds);
+        assert_eq!((gps_data.lat, gps_data.lon, gps_data.position_method), (10.0, 20.0, PositionMethod::Measured));
+
+        // Before the fix
+        let mut gps_data = fix(10.0, 20.0, Some(10.0), Some(0.0));
+        estimate_position(&mut gps_data, Some(at(0.0)), at(-0.5), &thresholds);
+        assert_eq!((gps_data.lat, gps_data.lon, gps_data.position_method), (10.0, 20.0, PositionMethod::Measured));
+
+        // Turned off
+        let mut gps_data = fix(10.0, 20.0, Some(10.0), Some(0.0));
+        gps_data.next_fix = Some(Fix {lat: 11.0, lon: 21.0, utc: at(1.0)});
+        estimate_position(&mut gps_data, Some(at(0.0)), at(0.5), &FixThresholds {dead_reckoning: false, ..thresholds});
+        assert_eq!((gps_data.lat, gps_data.lon, gps_data.position_method), (10.0, 20.0, PositionMethod::Measured));
+    }
+
+    #[test]
+    fn interpolated_between_the_fix_and_the_next_one() {
+        let mut gps_data = fix(10.0, 20.0, Some(10.0), Some(0.0));
+        gps_data.next_fix = Some(Fix {lat: 11.0, lon: 22.0, utc: at(1.0)});
+
+        estimate_position(&mut gps_data, Some(at(0.0)), at(0.25), &FixThresholds::default());
+
+        assert_eq!(gps_data.position_method, PositionMethod::Interpolated);
+        assert_close(gps_data.lat, 10.25);
+        assert_close(gps_data.lon, 20.5);
+    }
+
+    #[test]
+    fn interpolated_the_short_way_across_180_degrees() {
+        let mut gps_data = fix(0.0, 179.0, None, None);
+        gps_data.next_fix = Some(Fix {lat: 0.0, lon: -179.0, utc: at(1.0)});
+
+        estimate_position(&mut gps_data, Some(at(0.0)), at(0.75), &FixThresholds::default());
+
+        assert_eq!(gps_data.position_method, PositionMethod::Interpolated);
+        assert_close(gps_data.lon, -179.5);
+    }
+
+    #[test]
+    fn dead_reckoned_when_the_next_fix_doesnt_help() {
+        let thresholds = FixThresholds::default();
+
+        // Frame after the next fix
+        let mut gps_data = fix(10.0, 20.0, Some(10.0), Some(0.0));
+        gps_data.next_fix = Some(Fix {lat: 11.0, lon: 22.0, utc: at(1.0)});
+        estimate_position(&mut gps_data, Some(at(0.0)), at(1.5), &thresholds);
+        assert_eq!(gps_data.position_method, PositionMethod::DeadReckoned);
+
+        // Fixes too far apart to interpolate between
+        let mut gps_data = fix(10.0, 20.0, Some(10.0), Some(0.0));
+        gps_data.next_fix = Some(Fix {lat: 11.0, lon: 22.0, utc: at(60.0)});
+        estimate_position(&mut gps_data, Some(at(0.0)), at(1.0), &thresholds);
+        assert_eq!(gps_data.position_method, PositionMethod::DeadReckoned);
+    }
+
+    #[test]
+    fn new_position_clears_the_next_fix() {
+        let shared_gps_data = SharedGpsData::new();
+
+        shared_gps_data.store_position_with_next_fix(Some(10.0), Some(20.0), true, Some(FIX_UTC), None, None, Some((11.0, 22.0, FIX_UTC + 1.0)));
+        assert_eq!(shared_gps_data.load().next_fix, Some(Fix {lat: 11.0, lon: 22.0, utc: at(1.0)}));
+
+        shared_gps_data.store_position(Some(11.0), Some(22.0), true, Some(FIX_UTC + 1.0), None, None);
+        assert_eq!(shared_gps_data.load().next_fix, None);
+    }
+
+    // A fix that passes the default thresholds, received at at(0.0)
+    fn good_fix() -> GpsDataDecoded {
+        let mut gps_data = fix(10.0, 20.0, None, None);
+
+        gps_data.fix_received_at = Some(at(0.0));
+        gps_data.fix_valid = true;
+        gps_data.fix_quality = Some(1);
+        gps_data.satellites_used = Some(8);
+        gps_data.hdop = Some(1.0);
+        gps_data.pdop = Some(2.0);
+
+        return gps_data;
+    }
+
+    #[test]
+    fn good_fixes_are_accepted() {
+        let thresholds = FixThresholds::default();
+
+        assert!(thresholds.accepts(&good_fix(), at(0.0)));
+        assert!(thresholds.accepts(&good_fix(), at(5.0)));
+        assert!(!thresholds.accepts(&GpsDataDecoded::no_fix(), at(0.0)));
+    }
+
+    #[test]
+    fn stale_fixes_are_rejected() {
+        let thresholds = FixThresholds::default();
+
+        assert!(!thresholds.accepts(&good_fix(), at(5.001)));
+        assert!(!thresholds.accepts(&good_fix(), at(9.0)));
+        assert!(FixThresholds {max_fix_age_ms: 10_000, ..thresholds}.accepts(&good_fix(), at(9.0)));
+    }
+
+    #[test]
+    fn invalid_fixes_are_rejected_if_required() {
+        let thresholds = FixThresholds::default();
+        let mut gps_data = good_fix();
+        gps_data.fix_valid = false;
+
+        assert!(!thresholds.accepts(&gps_data, at(0.0)));
+        assert!(FixThresholds {require_valid: false, ..thresholds}.accepts(&gps_data, at(0.0)));
+    }
+
+    #[test]
+    fn fixes_below_the_quality_limits_are_rejected() {
+        let thresholds = FixThresholds::default();
+
+        let mut gps_data = good_fix();
+        gps_data.fix_quality = Some(0);
+        assert!(!thresholds.accepts(&gps_data, at(0.0)));
+
+        let mut gps_data = good_fix();
+        gps_data.satellites_used = Some(2);
+        assert!(!thresholds.accepts(&gps_data, at(0.0)));
+
+        let mut gps_data = good_fix();
+        gps_data.hdop = Some(10.5);
+        assert!(!thresholds.accepts(&gps_data, at(0.0)));
+
+        let mut gps_data = good_fix();
+        gps_data.pdop = Some(25.0);
+        assert!(!thresholds.accepts(&gps_data, at(0.0)));
+    }
+
+    #[test]
+    fn unreported_values_arent_checked() {
+        let thresholds = FixThresholds {min_fix_quality: 2, min_satellites: 6, max_hdop: 1.0, max_pdop: 1.0, ..FixThresholds::default()};
+
+        let mut gps_data = good_fix();
+        gps_data.fix_quality = None;
+        gps_data.satellites_used = None;
+        gps_data.hdop = None;
+        gps_data.pdop = None;
+
+        assert!(thresholds.accepts(&gps_data, at(0.0)));
+    }
+}
@@ -0,0 +1,241 @@
+use serde::Deserialize;
+use std::{
+    net::TcpStream,
+    io::{Error as io_error, Write, BufReader, BufRead}
+};
+use crate::gps::SharedGpsData;
+use crate::nmea::Date;
+
+// https://gpsd.gitlab.io/gpsd/gpsd_json.html
+// After the WATCH command gpsd streams one JSON object per line. Only TPV (position) and SKY (satellites / DOP) are used.
+const WATCH_COMMAND: &[u8] = b"?WATCH={\"enable\":true,\"json\":true};\n";
+
+pub const DEFAULT_ADDRESS: &str = "localhost:2947";
+
+#[derive(Deserialize)]
+#[serde(tag = "class")]
+enum Report {
+    #[serde(rename = "TPV")]
+    Tpv(Tpv),
+    #[serde(rename = "SKY")]
+    Sky(Sky),
+    #[serde(other)]
+    Other
+}
+
+#[derive(Deserialize)]
+struct Tpv {
+    #[serde(default)]
+    mode: u8, // 0 - unknown, 1 - no fix, 2 - 2D, 3 - 3D
+    status: Option<u8>, // 1 - normal, 2 - DGPS, 3 - RTK fixed, 4 - RTK float, 5 - dead reckoning, ...
+    time: Option<String>, // ISO 8601 UTC
+    lat: Option<f64>,
+    lon: Option<f64>,
+    #[serde(rename = "altMSL")]
+    altitude_msl: Option<f64>, // gpsd 3.20+
+    alt: Option<f64>, // Older gpsd. Also above mean sea level
+    speed: Option<f64>, // Meters per second
+    track: Option<f64> // Degrees true
+}
+
+#[derive(Deserialize)]
+struct Sky {
+    hdop: Option<f64>,
+    pdop: Option<f64>,
+    #[serde(rename = "uSat")]
+    used_satellites: Option<u8>, // Newer gpsd only
+    satellites: Option<Vec<Satellite>>
+}
+
+#[derive(Deserialize)]
+struct Satellite {
+    #[serde(default)]
+    used: bool
+}
+
+// Converted to the GGA fix quality so the same thresholds work for both backends
+fn fix_quality(tpv: &Tpv) -> u8 {
+    if tpv.mode < 2 {
+        return 0;
+    }
+
+    match tpv.status {
+        Some(2) => 2,
+        Some(3) => 4,
+        Some(4) => 5,
+        Some(5) => 6,
+        _ => 1
+    }
+}
+
+// Seconds since the unix epoch from e.g. 2024-05-01T17:03:09.250Z
+pub fn parse_iso_utc(time: &str) -> Option<f64> {
+    let time = time.strip_suffix('Z')?;
+    let (date, time) = time.split_once('T')?;
+
+    let mut date_parts = date.splitn(3, '-');
+    let year: u16 = date_parts.next()?.parse().ok()?;
+    let month: u8 = date_parts.next()?.parse().ok()?;
+    let day: u8 = date_parts.next()?.parse().ok()?;
+
+    let mut time_parts = time.splitn(3, ':');
+    let hours: u8 = time_parts.next()?.parse().ok()?;
+    let minutes: u8 = time_parts.next()?.parse().ok()?;
+    let seconds: f64 = time_parts.next()?.parse().ok()?;
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || !(0.0..61.0).contains(&seconds) {
+        return None;
+    }
+
+    let days = Date {day: day, month: month, year: year}.days_since_unix_epoch();
+
+    return Some(days as f64 * 86_400.0 + hours as f64 * 3_600.0 + minutes as f64 * 60.0 + seconds);
+}
+
+fn store_tpv(tpv: &Tpv, shared_gps_data: &SharedGpsData) {
+    let valid = tpv.mode >= 2;
+
+    shared_gps_data.store_position(tpv.lat, tpv.lon, valid, tpv.time.as_deref().and_then(parse_iso_utc), tpv.speed, tpv.track);
+    shared_gps_data.store_fix_quality(fix_quality(tpv));
+
+    if let Some(altitude) = tpv.altitude_msl.or(tpv.alt) {
+        shared_gps_data.store_altitude(Some(altitude));
+    }
+}
+
+fn store_sky(sky: &Sky, shared_gps_data: &SharedGpsData) {
+    let satellites_used = sky.used_satellites.or_else(|| {
+        sky.satellites.as_ref().map(|satellites| satellites.iter().filter(|satellite| satellite.used).count().min(u8::MAX as usize) as u8)
+    });
+
+    // Some SKY reports only carry part of the data, so fields that are missing keep their last value
+    if satellites_used.is_some() {
+        shared_gps_data.store_satellites_used(satellites_used);
+    }
+
+    if sky.hdop.is_some() {
+        shared_gps_data.store_hdop(sky.hdop);
+    }
+
+    if sky.pdop.is_some() {
+        shared_gps_data.store_pdop(sky.pdop);
+    }
+}
+
+// Blocks for as long as gpsd keeps the connection open
+pub fn start(address: &str, shared_gps_data: &SharedGpsData) -> Result<(), io_error> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.write_all(WATCH_COMMAND)?;
+
+    let reader = BufReader::new(stream);
+
+    for line in reader.lines() {
+        let line = line?;
+
+        // VERSION, DEVICES, WATCH etc. are parsed as Other and ignored
+        let report: Report = match serde_json::from_str(&line) {
+            Ok(report) => report,
+            Err(_e) => continue
+        };
+
+        match report {
+            Report::Tpv(tpv) => store_tpv(&tpv, shared_gps_data),
+            Report::Sky(sky) => store_sky(&sky, shared_gps_data),
+            Report::Other => {}
+        }
+    }
+
+    Err(io_error::new(std::io::ErrorKind::UnexpectedEof, "gpsd closed the connection."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread, time::{Duration, UNIX_EPOCH}};
+
+    // Accepts one connection, checks for the WATCH command, sends the lines and hangs up
+    fn fake_gpsd(lines: &'static [&'static str]) -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let mut command = String::new();
+            BufReader::new(stream.try_clone().unwrap()).read_line(&mut command).unwrap();
+            assert_eq!(command.as_bytes(), WATCH_COMMAND);
+
+            for line in lines {
+                stream.write_all(line.as_bytes()).unwrap();
+                stream.write_all(b"\n").unwrap();
+            }
+        });
+
+        return (address, handle);
+    }
+
+    #[test]
+    fn reports_are_stored_until_gpsd_hangs_up() {
+        let (address, handle) = fake_gpsd(&[
+            r#"{"class":"VERSION","release":"3.25","rev":"3.25","proto_major":3,"proto_minor":15}"#,
+            r#"{"class":"DEVICES","devices":[{"class":"DEVICE","path":"/dev/ttyACM0"}]}"#,
+            "not json",
+            r#"{"class":"TPV","device":"/dev/ttyACM0","mode":3,"status":2,"time":"2024-05-01T17:03:09.250Z","lat":51.5,"lon":-0.125,"altMSL":35.5,"alt":80.0,"speed":1.5,"track":270.0}"#,
+            r#"{"class":"SKY","device":"/dev/ttyACM0","hdop":0.9,"pdop":1.6,"satellites":[{"PRN":1,"used":true},{"PRN":2,"used":false},{"PRN":3,"used":true}]}"#,
+            r#"{"class":"SKY","device":"/dev/ttyACM0"}"#
+        ]);
+
+        let shared_gps_data = SharedGpsData::new();
+        let error = start(&address, &shared_gps_data).unwrap_err();
+        handle.join().unwrap();
+
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        let gps_data = shared_gps_data.load();
+        assert!(gps_data.fix_valid);
+        assert_eq!(gps_data.lat, 51.5);
+        assert_eq!(gps_data.lon, -0.125);
+        assert_eq!(gps_data.fix_quality, Some(2));
+        assert_eq!(gps_data.altitude, Some(35.5));
+        assert_eq!(gps_data.speed, Some(1.5));
+        assert_eq!(gps_data.course, Some(270.0));
+        assert!(gps_data.velocity_measured);
+        assert_eq!(gps_data.utc.unwrap().duration_since(UNIX_EPOCH).unwrap(), Duration::from_millis(1_714_582_989_250));
+
+        // The second SKY without any data doesnt clear the first
+        assert_eq!(gps_data.hdop, Some(0.9));
+        assert_eq!(gps_data.pdop, Some(1.6));
+        assert_eq!(gps_data.satellites_used, Some(2));
+    }
+
+    #[test]
+    fn lost_fix_keeps_the_last_coordinates() {
+        let (address, handle) = fake_gpsd(&[
+            r#"{"class":"TPV","mode":2,"lat":10.0,"lon":20.0,"alt":5.0}"#,
+            r#"{"class":"TPV","mode":1}"#,
+            r#"{"class":"SKY","uSat":0,"satellites":[{"PRN":1,"used":true}]}"#
+        ]);
+
+        let shared_gps_data = SharedGpsData::new();
+        assert!(start(&address, &shared_gps_data).is_err());
+        handle.join().unwrap();
+
+        let gps_data = shared_gps_data.load();
+        assert!(!gps_data.fix_valid);
+        assert_eq!(gps_data.fix_quality, Some(0));
+        assert_eq!(gps_data.lat, 10.0);
+        assert_eq!(gps_data.lon, 20.0);
+        // Older gpsd only has alt
+        assert_eq!(gps_data.altitude, Some(5.0));
+        // uSat wins over counting the satellites
+        assert_eq!(gps_data.satellites_used, Some(0));
+    }
+
+    #[test]
+    fn iso_times() {
+        assert_eq!(parse_iso_utc("1970-01-01T00:00:00Z"), Some(0.0));
+        assert_eq!(parse_iso_utc("2024-05-01T17:03:09.250Z"), Some(1_714_582_989.25));
+        assert_eq!(parse_iso_utc("2024-05-01T17:03:09"), None);
+        assert_eq!(parse_iso_utc("2024-13-01T17:03:09Z"), None);
+    }
+}
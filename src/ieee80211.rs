@@ -0,0 +1,343 @@
+use std::{collections::HashMap, fmt};
+use crate::radiotap::{self, RadiotapHeader};
+use crate::types::packet_types;
+
+// https://howiwifi.com/2020/07/13/802-11-frame-types-and-formats/
+const MANAGEMENT_HEADER_LENGTH: usize = 24;
+const HT_CONTROL_LENGTH: usize = 4;
+const FCS_LENGTH: usize = 4;
+
+// Second byte of the frame control field
+const FLAG_ORDER: u8 = 0x80; // +HTC. An HT control field follows the sequence control field
+
+#[derive(Debug)]
+pub enum ParseError {
+    EmptyPacket,
+    BadRadiotapLength { radiotap_length: usize, packet_length: usize },
+    TruncatedHeader { needed: usize, available: usize },
+    TruncatedIe { tag_number: u8, position: usize }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::EmptyPacket => write!(f, "Empty packet"),
+            ParseError::BadRadiotapLength { radiotap_length, packet_length } => {
+                write!(f, "Bad radiotap length {} for a {} byte packet", radiotap_length, packet_length)
+            },
+            ParseError::TruncatedHeader { needed, available } => {
+                write!(f, "Truncated 802.11 header. Needed {} bytes but only {} available", needed, available)
+            },
+            ParseError::TruncatedIe { tag_number, position } => {
+                write!(f, "Truncated tagged parameter {} at offset {}", tag_number, position)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Borrows everything from the captured packet so nothing is copied until a frame is actually kept
+pub struct ParsedFrame<'a> {
+    pub radiotap: RadiotapHeader, // Empty if the packet had no radiotap header
+    pub frame_control: [u8; 2],
+    pub receiver_mac_address: &'a [u8; 6],
+    pub transmitter_mac_address: &'a [u8; 6],
+    pub bssid: &'a [u8; 6],
+    pub fixed_parameters: &'a [u8],
+    pub tagged_parameters: &'a [u8]
+}
+
+impl<'a> ParsedFrame<'a> {
+    // First byte in ieee 802.11 header
+    pub fn packet_type(&self) -> u8 {
+        self.frame_control[0]
+    }
+
+    // Capability information of a beacon. Other frames dont have it
+    pub fn capability(&self) -> Option<u16> {
+        self.fixed_parameters.get(10..12).map(|capability| u16::from_le_bytes([capability[0], capability[1]]))
+    }
+
+    pub fn tags(&self) -> TaggedParams<'a> {
+        TaggedParams {data: self.tagged_parameters, position: 0}
+    }
+}
+
+// Iterates over the tagged parameters (information elements) of a frame. Yields an error and then stops if an
+// element runs past the end of the frame.
+pub struct TaggedParams<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> Iterator for TaggedParams<'a> {
+    type Item = Result<(u8, &'a [u8]), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.data.len() {
+            return None;
+        }
+
+        let position = self.position;
+        let tag_number = self.data[position];
+
+        let tag_length = match self.data.get(position + 1) {
+            Some(&tag_length) => tag_length as usize,
+            None => {
+                self.position = self.data.len();
+                return Some(Err(ParseError::TruncatedIe {tag_number: tag_number, position: position}));
+            }
+        };
+
+        // +2 to account for the tag number and length
+        let tag_data = match self.data.get(position + 2..position + 2 + tag_length) {
+            Some(tag_data) => tag_data,
+            None => {
+                self.position = self.data.len();
+                return Some(Err(ParseError::TruncatedIe {tag_number: tag_number, position: position}));
+            }
+        };
+
+        self.position += tag_length + 2;
+
+        return Some(Ok((tag_number, tag_data)));
+    }
+}
+
+fn fixed_parameters_length(packet_type: u8) -> usize {
+    match packet_type {
+        // Timestamp (8), beacon interval (2), capability info (2)
+        packet_types::BEACON => 12,
+        _ => 0
+    }
+}
+
+fn mac_address_at(frame: &[u8], offset: usize) -> &[u8; 6] {
+    // Only called after the header length has been checked
+    frame[offset..offset + 6].try_into().unwrap()
+}
+
+// has_radiotap comes from the capture's link type. IEEE802_11 captures are the bare 802.11 frame.
+pub fn parse(packet: &[u8], has_radiotap: bool) -> Result<ParsedFrame<'_>, ParseError> {
+    if packet.is_empty() {
+        return Err(ParseError::EmptyPacket);
+    }
+
+    let mut ieee_802_11_frame_start: usize = 0;
+    let mut radiotap_header = RadiotapHeader::default();
+
+    if has_radiotap {
+        radiotap_header = match radiotap::parse(packet) {
+            Some(radiotap_header) => radiotap_header,
+            None => {
+                let radiotap_length = packet.get(2..4).map(|length| u16::from_le_bytes([length[0], length[1]]) as usize).unwrap_or(0);
+
+                return Err(ParseError::BadRadiotapLength {radiotap_length: radiotap_length, packet_length: packet.len()});
+            }
+        };
+
+        ieee_802_11_frame_start = radiotap_header.length as usize;
+    }
+
+    let mut ieee_80211_frame = &packet[ieee_802_11_frame_start..];
+
+    // Some drivers leave the frame check sequence on the end which would otherwise be read as a tagged parameter
+    if radiotap_header.flags.is_some_and(|flags| flags & radiotap::flags::FCS_AT_END != 0) {
+        if ieee_80211_frame.len() < FCS_LENGTH {
+            return Err(ParseError::TruncatedHeader {needed: FCS_LENGTH, available: ieee_80211_frame.len()});
+        }
+
+        ieee_80211_frame = &ieee_80211_frame[..ieee_80211_frame.len() - FCS_LENGTH];
+    }
+
+    if ieee_80211_frame.len() < MANAGEMENT_HEADER_LENGTH {
+        return Err(ParseError::TruncatedHeader {needed: MANAGEMENT_HEADER_LENGTH, available: ieee_80211_frame.len()});
+    }
+
+    let frame_control = [ieee_80211_frame[0], ieee_80211_frame[1]];
+
+    let mut offset = MANAGEMENT_HEADER_LENGTH;
+
+    if frame_control[1] & FLAG_ORDER != 0 {
+        offset += HT_CONTROL_LENGTH;
+    }
+
+    let fixed_parameters_end = offset + fixed_parameters_length(frame_control[0]);
+
+    if ieee_80211_frame.len() < fixed_parameters_end {
+        return Err(ParseError::TruncatedHeader {needed: fixed_parameters_end, available: ieee_80211_frame.len()});
+    }
+
+    return Ok(ParsedFrame {
+        radiotap: radiotap_header,
+        frame_control: frame_control,
+        receiver_mac_address: mac_address_at(ieee_80211_frame, 4),
+        transmitter_mac_address: mac_address_at(ieee_80211_frame, 10),
+        bssid: mac_address_at(ieee_80211_frame, 16),
+        fixed_parameters: &ieee_80211_frame[offset..fixed_parameters_end],
+        tagged_parameters: &ieee_80211_frame[fixed_parameters_end..]
+    });
+}
+
+// Copies out the tagged parameters whose numbers are in target_tag_numbers
+pub fn search_tagged_params(frame: &ParsedFrame, target_tag_numbers: &[u8]) -> Result<HashMap<u8, Vec<u8>>, ParseError> {
+    let mut tags: HashMap<u8, Vec<u8>> = HashMap::new();
+
+    for tag in frame.tags() {
+        let (tag_number, tag_data) = tag?;
+
+        if target_tag_numbers.contains(&tag_number) {
+            tags.insert(tag_number, tag_data.to_owned());
+        }
+    }
+
+    return Ok(tags);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tagged_params::tagged_params_ws;
+
+    const TRANSMITTER: [u8; 6] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55];
+    const BSSID: [u8; 6] = [0x02, 0xaa, 0xbb, 0xcc, 0xdd, 0xee];
+
+    // Radiotap header with only the flags field
+    fn radiotap_with_flags(flags: u8) -> Vec<u8> {
+        vec![0x00, 0x00, 0x09, 0x00, 0x02, 0x00, 0x00, 0x00, flags]
+    }
+
+    // Management header for a frame of packet_type with the given second frame control byte
+    fn management_header(packet_type: u8, frame_control_flags: u8) -> Vec<u8> {
+        let mut header = vec![packet_type, frame_control_flags, 0x00, 0x00];
+        header.extend_from_slice(&[0xff; 6]);
+        header.extend_from_slice(&TRANSMITTER);
+        header.extend_from_slice(&BSSID);
+        header.extend_from_slice(&[0x10, 0x00]);
+
+        return header;
+    }
+
+    fn beacon_body(tags: &[u8]) -> Vec<u8> {
+        // Timestamp, beacon interval and an ESS + privacy capability
+        let mut body = vec![0x00; 8];
+        body.extend_from_slice(&[0x64, 0x00, 0x11, 0x00]);
+        body.extend_from_slice(tags);
+
+        return body;
+    }
+
+    const SSID_TAGS: [u8; 8] = [0x00, 0x03, b'a', b'b', b'c', 0x03, 0x01, 0x06];
+
+    #[test]
+    fn beacon_with_radiotap() {
+        let mut packet = radiotap_with_flags(0x00);
+        packet.extend(management_header(packet_types::BEACON, 0x00));
+        packet.extend(beacon_body(&SSID_TAGS));
+
+        let frame = parse(&packet, true).unwrap();
+
+        assert_eq!(frame.packet_type(), packet_types::BEACON);
+        assert_eq!(frame.receiver_mac_address, &[0xff; 6]);
+        assert_eq!(frame.transmitter_mac_address, &TRANSMITTER);
+        assert_eq!(frame.bssid, &BSSID);
+        assert_eq!(frame.fixed_parameters, &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x64, 0x00, 0x11, 0x00]);
+
+        let tags = search_tagged_params(&frame, &[tagged_params_ws::SSID, tagged_params_ws::DS_PARAMETER]).unwrap();
+        assert_eq!(tags[&tagged_params_ws::SSID], b"abc");
+        assert_eq!(tags[&tagged_params_ws::DS_PARAMETER], [0x06]);
+    }
+
+    #[test]
+    fn fcs_is_trimmed_when_flagged() {
+        let mut packet = radiotap_with_flags(radiotap::flags::FCS_AT_END);
+        packet.extend(management_header(packet_types::PROBE_REQUEST, 0x00));
+        packet.extend_from_slice(&SSID_TAGS);
+        packet.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+
+        let frame = parse(&packet, true).unwrap();
+        let tags: Vec<(u8, &[u8])> = frame.tags().collect::<Result<_, _>>().unwrap();
+
+        assert_eq!(tags, vec![(tagged_params_ws::SSID, &b"abc"[..]), (tagged_params_ws::DS_PARAMETER, &[0x06][..])]);
+
+        // Without the flag the FCS is read as a tagged parameter that runs off the end
+        let mut packet = radiotap_with_flags(0x00);
+        packet.extend(management_header(packet_types::PROBE_REQUEST, 0x00));
+        packet.extend_from_slice(&SSID_TAGS);
+        packet.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+
+        assert!(matches!(search_tagged_params(&parse(&packet, true).unwrap(), &[0]), Err(ParseError::TruncatedIe {tag_number: 0xde, position: 8})));
+    }
+
+    #[test]
+    fn ht_control_field_is_skipped() {
+        // No radiotap header, straight into the 802.11 frame
+        let mut packet = management_header(packet_types::BEACON, FLAG_ORDER);
+        packet.extend_from_slice(&[0x01, 0x02, 0x03, 0x04]);
+        packet.extend(beacon_body(&SSID_TAGS));
+
+        let frame = parse(&packet, false).unwrap();
+
+        assert_eq!(frame.fixed_parameters.len(), 12);
+        assert_eq!(frame.fixed_parameters[8..], [0x64, 0x00, 0x11, 0x00]);
+        assert_eq!(frame.tags().next().unwrap().unwrap(), (tagged_params_ws::SSID, &b"abc"[..]));
+    }
+
+    #[test]
+    fn bare_frames_starting_with_zero_arent_read_as_radiotap() {
+        // An association request is frame control 0x00, the same first byte as a radiotap header
+        let mut packet = management_header(0x00, 0x00);
+        packet.extend_from_slice(&SSID_TAGS);
+
+        let frame = parse(&packet, false).unwrap();
+
+        assert_eq!(frame.packet_type(), 0x00);
+        assert_eq!(frame.radiotap.length, 0);
+        assert_eq!(frame.transmitter_mac_address, &TRANSMITTER);
+    }
+
+    #[test]
+    fn truncated_headers() {
+        assert!(matches!(parse(&[], true), Err(ParseError::EmptyPacket)));
+
+        // Radiotap length longer than the packet
+        assert!(matches!(parse(&[0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00], true), Err(ParseError::BadRadiotapLength {radiotap_length: 64, packet_length: 8})));
+
+        let mut packet = radiotap_with_flags(0x00);
+        packet.extend(&management_header(packet_types::PROBE_REQUEST, 0x00)[..20]);
+        assert!(matches!(parse(&packet, true), Err(ParseError::TruncatedHeader {needed: 24, available: 20})));
+
+        // A beacon needs its 12 bytes of fixed parameters
+        let mut packet = management_header(packet_types::BEACON, 0x00);
+        packet.extend_from_slice(&[0x00; 6]);
+        assert!(matches!(parse(&packet, false), Err(ParseError::TruncatedHeader {needed: 36, available: 30})));
+
+        // +HTC needs 4 more bytes
+        let packet = management_header(packet_types::PROBE_REQUEST, FLAG_ORDER);
+        assert!(matches!(parse(&packet, false), Err(ParseError::TruncatedHeader {needed: 28, available: 24})));
+
+        // Too short for the FCS the flags promise
+        assert!(matches!(parse(&radiotap_with_flags(radiotap::flags::FCS_AT_END), true), Err(ParseError::TruncatedHeader {needed: 4, available: 0})));
+    }
+
+    #[test]
+    fn truncated_ies() {
+        // SSID says 5 bytes but only 3 follow
+        let mut packet = management_header(packet_types::PROBE_REQUEST, 0x00);
+        packet.extend_from_slice(&[0x03, 0x01, 0x06, 0x00, 0x05, b'a', b'b', b'c']);
+
+        let frame = parse(&packet, false).unwrap();
+        let mut tags = frame.tags();
+
+        assert_eq!(tags.next().unwrap().unwrap(), (tagged_params_ws::DS_PARAMETER, &[0x06][..]));
+        assert!(matches!(tags.next(), Some(Err(ParseError::TruncatedIe {tag_number: 0, position: 3}))));
+        assert!(tags.next().is_none());
+
+        // A tag number with no length byte
+        let mut packet = management_header(packet_types::PROBE_REQUEST, 0x00);
+        packet.push(0xdd);
+
+        assert!(matches!(search_tagged_params(&parse(&packet, false).unwrap(), &[0xdd]), Err(ParseError::TruncatedIe {tag_number: 0xdd, position: 0})));
+    }
+}
@@ -0,0 +1,322 @@
+use serde::{Serialize, Deserialize};
+use std::{thread, time::Duration, sync::Arc};
+use crate::capture;
+use crate::airmon_ng::AirmonNg;
+use crate::nl80211::{Nl80211, ChannelWidth};
+
+pub trait InterfaceManager: Send + Sync {
+    fn list_interfaces(&self) -> Result<Vec<String>, String> {
+        match capture::get_interfaces() {
+            Ok(interfaces) => Ok(interfaces.into_iter().map(|interface| interface.name).collect()),
+            Err(e) => Err(e.to_string())
+        }
+    }
+
+    // Returns the name of the monitor interface if the backend knows it. Otherwise the caller has to work it out.
+    fn start_monitor_mode(&self, interface_name: &str) -> Result<Option<String>, String>;
+    fn stop_monitor_mode(&self, interface_name: &str) -> Result<(), String>;
+    fn set_channel(&self, interface: &str, channel: u8) -> Result<(), String>;
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum InterfaceBackend {
+    #[default]
+    Nl80211,
+    AirmonNg
+}
+
+// Falls back to airmon-ng if nl80211 isn't available
+pub fn new_interface_manager(backend: InterfaceBackend, channel_width: ChannelWidth) -> Arc<dyn InterfaceManager> {
+    match backend {
+        InterfaceBackend::Nl80211 => match Nl80211::new(channel_width) {
+            Ok(nl80211) => Arc::new(nl80211),
+            Err(e) => {
+                eprintln!("Unable to use nl80211, falling back to airmon-ng: {}", e);
+
+                Arc::new(AirmonNg)
+            }
+        },
+        InterfaceBackend::AirmonNg => Arc::new(AirmonNg)
+    }
+}
+
+// Names in changed_interfaces that weren't in original_interfaces
+pub fn get_changed_interfaces(original_interfaces: &[String], changed_interfaces: &[String]) -> Vec<String> {
+    changed_interfaces.iter().filter(|changed| !original_interfaces.contains(changed)).cloned().collect()
+}
+
+// Puts interface_name into monitor mode and returns the name of the interface to capture on. Between attempts whatever
+// was started is stopped again.
+pub fn start_monitor_interface(interface_manager: &dyn InterfaceManager, interface_name: &str, max_attempts: u32, retry_delay: Duration) -> Result<String, String> {
+    let mut attempts_to_start = 0;
+
+    loop {
+        if attempts_to_start >= 1 {
+            println!("Attempted to start {} times.", attempts_to_start);
+
+            if attempts_to_start >= max_attempts {
+                return Err("Too many attempts to start.".to_string());
+            }
+
+            thread::sleep(retry_delay);
+
+            // Failing to stop usually just means there was nothing to stop
+            if let Err(e) = interface_manager.stop_monitor_mode(interface_name) {
+                println!("{}", e);
+            }
+        }
+
+        attempts_to_start += 1;
+
+        // Determine which interface to use based of which one changed. Hacky method....
+        // Could also just predict that the name would be wlan1"mon"
+        let original_interfaces = match interface_manager.list_interfaces() {
+            Ok(interfaces) => interfaces,
+            Err(e) => { println!("Error {}", e); continue; }
+        };
+
+        match interface_manager.start_monitor_mode(interface_name) {
+            // The backend created the monitor interface itself so there is nothing to work out
+            Ok(Some(monitor_interface)) => return Ok(monitor_interface),
+            Ok(None) => {},
+            Err(e) => { println!("Error {}", e); continue; }
+        };
+
+        let possible_changed_interfaces = match interface_manager.list_interfaces() {
+            Ok(interfaces) => interfaces,
+            Err(e) => { println!("Error {}", e); continue; }
+        };
+
+        let changed_interfaces = get_changed_interfaces(&original_interfaces, &possible_changed_interfaces);
+
+        if changed_interfaces.len() < 1 {
+            println!("No interface found.");
+
+            continue;
+        }
+
+        return Ok(changed_interfaces[0].clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Operation {
+        ListInterfaces,
+        StartMonitorMode,
+        StopMonitorMode,
+        SetChannel
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum InterfaceCall {
+        ListInterfaces,
+        StartMonitorMode(String),
+        StopMonitorMode(String),
+        SetChannel(String, u8)
+    }
+
+    // In memory stand in for tests. Records every call, behaves like airmon-ng by adding and removing "<interface>mon"
+    // from its interface list, and can be scripted to fail.
+    struct FakeInterfaceManager {
+        calls: Mutex<Vec<InterfaceCall>>,
+        interfaces: Mutex<Vec<String>>,
+        failures: Mutex<Vec<(Operation, String)>>,
+        report_monitor_interface: bool // Return the monitor interface name from start_monitor_mode like nl80211 does
+    }
+
+    impl FakeInterfaceManager {
+        fn new(interfaces: &[&str], report_monitor_interface: bool) -> FakeInterfaceManager {
+            FakeInterfaceManager {
+                calls: Mutex::new(Vec::new()),
+                interfaces: Mutex::new(interfaces.iter().map(|interface| interface.to_string()).collect()),
+                failures: Mutex::new(Vec::new()),
+                report_monitor_interface: report_monitor_interface
+            }
+        }
+
+        // Queues a failure for the next call of operation. Failures for the same operation are used in order.
+        fn fail_next(&self, operation: Operation, error: &str) {
+            self.failures.lock().unwrap().push((operation, error.to_string()));
+        }
+
+        fn calls(&self) -> Vec<InterfaceCall> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        fn interfaces(&self) -> Vec<String> {
+            self.interfaces.lock().unwrap().clone()
+        }
+
+        fn record(&self, call: InterfaceCall, operation: Operation) -> Result<(), String> {
+            self.calls.lock().unwrap().push(call);
+
+            let mut failures = self.failures.lock().unwrap();
+
+            match failures.iter().position(|(failing_operation, _)| *failing_operation == operation) {
+                Some(position) => Err(failures.remove(position).1),
+                None => Ok(())
+            }
+        }
+    }
+
+    impl InterfaceManager for FakeInterfaceManager {
+        fn list_interfaces(&self) -> Result<Vec<String>, String> {
+            self.record(InterfaceCall::ListInterfaces, Operation::ListInterfaces)?;
+
+            Ok(self.interfaces())
+        }
+
+        fn start_monitor_mode(&self, interface_name: &str) -> Result<Option<String>, String> {
+            self.record(InterfaceCall::StartMonitorMode(interface_name.to_string()), Operation::StartMonitorMode)?;
+
+            let monitor_interface_name = interface_name.to_owned() + "mon";
+            let mut interfaces = self.interfaces.lock().unwrap();
+
+            if !interfaces.contains(&monitor_interface_name) {
+                interfaces.push(monitor_interface_name.clone());
+            }
+
+            Ok(if self.report_monitor_interface { Some(monitor_interface_name) } else { None })
+        }
+
+        fn stop_monitor_mode(&self, interface_name: &str) -> Result<(), String> {
+            self.record(InterfaceCall::StopMonitorMode(interface_name.to_string()), Operation::StopMonitorMode)?;
+
+            let monitor_interface_name = interface_name.to_owned() + "mon";
+            self.interfaces.lock().unwrap().retain(|interface| *interface != monitor_interface_name);
+
+            Ok(())
+        }
+
+        fn set_channel(&self, interface: &str, channel: u8) -> Result<(), String> {
+            self.record(InterfaceCall::SetChannel(interface.to_string(), channel), Operation::SetChannel)
+        }
+    }
+
+    fn start(fake: &FakeInterfaceManager, max_attempts: u32) -> Result<String, String> {
+        start_monitor_interface(fake, "wlan1", max_attempts, Duration::ZERO)
+    }
+
+    fn count(fake: &FakeInterfaceManager, call: &InterfaceCall) -> usize {
+        fake.calls().iter().filter(|made| *made == call).count()
+    }
+
+    #[test]
+    fn reported_monitor_interface_is_used() {
+        let fake = FakeInterfaceManager::new(&["lo", "wlan1"], true);
+
+        assert_eq!(start(&fake, 3), Ok("wlan1mon".to_string()));
+        assert_eq!(fake.calls(), vec![InterfaceCall::ListInterfaces, InterfaceCall::StartMonitorMode("wlan1".to_string())]);
+    }
+
+    #[test]
+    fn new_monitor_interface_is_found_by_listing() {
+        let fake = FakeInterfaceManager::new(&["lo", "wlan1"], false);
+
+        assert_eq!(start(&fake, 3), Ok("wlan1mon".to_string()));
+        assert_eq!(fake.calls(), vec![
+            InterfaceCall::ListInterfaces,
+            InterfaceCall::StartMonitorMode("wlan1".to_string()),
+            InterfaceCall::ListInterfaces
+        ]);
+    }
+
+    #[test]
+    fn leftover_monitor_interface_is_stopped_and_picked_up_again() {
+        // wlan1mon is already there from an earlier run, so starting doesnt change the list the first time
+        let fake = FakeInterfaceManager::new(&["lo", "wlan1", "wlan1mon"], false);
+
+        assert_eq!(start(&fake, 3), Ok("wlan1mon".to_string()));
+        assert_eq!(count(&fake, &InterfaceCall::StopMonitorMode("wlan1".to_string())), 1);
+        assert_eq!(count(&fake, &InterfaceCall::StartMonitorMode("wlan1".to_string())), 2);
+        assert_eq!(fake.interfaces(), vec!["lo", "wlan1", "wlan1mon"]);
+    }
+
+    #[test]
+    fn failed_list_is_retried() {
+        let fake = FakeInterfaceManager::new(&["wlan1"], false);
+        fake.fail_next(Operation::ListInterfaces, "netlink went away");
+
+        assert_eq!(start(&fake, 3), Ok("wlan1mon".to_string()));
+        assert_eq!(fake.calls(), vec![
+            InterfaceCall::ListInterfaces,
+            InterfaceCall::StopMonitorMode("wlan1".to_string()),
+            InterfaceCall::ListInterfaces,
+            InterfaceCall::StartMonitorMode("wlan1".to_string()),
+            InterfaceCall::ListInterfaces
+        ]);
+    }
+
+    #[test]
+    fn repeated_list_failures_use_up_attempts() {
+        let fake = FakeInterfaceManager::new(&["wlan1"], false);
+        fake.fail_next(Operation::ListInterfaces, "netlink went away");
+        fake.fail_next(Operation::ListInterfaces, "still gone");
+
+        assert_eq!(start(&fake, 2), Err("Too many attempts to start.".to_string()));
+
+        // With a third attempt the list works again
+        let fake = FakeInterfaceManager::new(&["wlan1"], false);
+        fake.fail_next(Operation::ListInterfaces, "netlink went away");
+        fake.fail_next(Operation::ListInterfaces, "still gone");
+
+        assert_eq!(start(&fake, 3), Ok("wlan1mon".to_string()));
+        assert_eq!(count(&fake, &InterfaceCall::StopMonitorMode("wlan1".to_string())), 2);
+    }
+
+    #[test]
+    fn failed_start_is_retried() {
+        let fake = FakeInterfaceManager::new(&["wlan1"], true);
+        fake.fail_next(Operation::StartMonitorMode, "device busy");
+
+        assert_eq!(start(&fake, 3), Ok("wlan1mon".to_string()));
+        assert_eq!(fake.calls(), vec![
+            InterfaceCall::ListInterfaces,
+            InterfaceCall::StartMonitorMode("wlan1".to_string()),
+            InterfaceCall::StopMonitorMode("wlan1".to_string()),
+            InterfaceCall::ListInterfaces,
+            InterfaceCall::StartMonitorMode("wlan1".to_string())
+        ]);
+    }
+
+    #[test]
+    fn failed_stop_still_retries_the_start() {
+        let fake = FakeInterfaceManager::new(&["wlan1"], true);
+        fake.fail_next(Operation::StartMonitorMode, "device busy");
+        fake.fail_next(Operation::StopMonitorMode, "nothing to stop");
+
+        assert_eq!(start(&fake, 2), Ok("wlan1mon".to_string()));
+        assert_eq!(count(&fake, &InterfaceCall::StartMonitorMode("wlan1".to_string())), 2);
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let fake = FakeInterfaceManager::new(&["wlan1"], true);
+
+        for _attempt in 0..5 {
+            fake.fail_next(Operation::StartMonitorMode, "device busy");
+        }
+
+        assert_eq!(start(&fake, 3), Err("Too many attempts to start.".to_string()));
+        assert_eq!(count(&fake, &InterfaceCall::StartMonitorMode("wlan1".to_string())), 3);
+        assert_eq!(count(&fake, &InterfaceCall::StopMonitorMode("wlan1".to_string())), 2);
+    }
+
+    #[test]
+    fn gives_up_when_no_interface_appears() {
+        // A backend that reports nothing and doesnt change the list never gives an interface to use
+        let fake = FakeInterfaceManager::new(&["wlan1", "wlan1mon"], false);
+
+        for _attempt in 0..3 {
+            fake.fail_next(Operation::StopMonitorMode, "cant stop");
+        }
+
+        assert_eq!(start(&fake, 3), Err("Too many attempts to start.".to_string()));
+    }
+}
@@ -0,0 +1,487 @@
+use serde::{Serialize, Deserialize};
+use std::{
+    fs,
+    sync::Arc,
+    time::Duration,
+    io::{Error as io_error, ErrorKind}
+};
+use crate::gps::{self, SharedGpsData};
+use crate::gpsd;
+use crate::nmea::{self, Sentence, SentenceData, Date, Time};
+use crate::replay_clock::ReplayClock;
+
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum GpsBackend {
+    #[default]
+    Serial, // Read NMEA straight from the serial port
+    Gpsd, // Use a running gpsd, which stops the two fighting over the serial port
+    NmeaFile, // Replay a recorded NMEA log
+    Gpx // Replay a GPX track
+}
+
+// Anything that can keep the shared position up to date. run blocks until the source ends or fails.
+pub trait LocationSource: Send + Sync {
+    fn run(&self, shared_gps_data: &SharedGpsData) -> Result<(), io_error>;
+
+    // Whether a failed run is retried until shutdown rather than only retries.gps_attempts times
+    fn reconnects(&self) -> bool {
+        false
+    }
+}
+
+pub struct SerialNmea {
+    pub serial_device: String,
+    pub baud_rate: u32
+}
+
+impl LocationSource for SerialNmea {
+    fn run(&self, shared_gps_data: &SharedGpsData) -> Result<(), io_error> {
+        gps::start_gps(&self.serial_device, self.baud_rate, shared_gps_data)
+    }
+}
+
+pub struct Gpsd {
+    pub address: String
+}
+
+impl LocationSource for Gpsd {
+    fn run(&self, shared_gps_data: &SharedGpsData) -> Result<(), io_error> {
+        gpsd::start(&self.address, shared_gps_data)
+    }
+
+    // gpsd restarting or the network dropping shouldnt end the logging
+    fn reconnects(&self) -> bool {
+        true
+    }
+}
+
+struct ReplaySentence {
+    recorded_time: Option<f64>, // Seconds since the unix epoch once a date has been seen in the log
+    time_of_day: Option<f64>, // Seconds since midnight of the first day in the log, so it keeps counting past midnight
+    sentence: Sentence,
+    next_fix: Option<(f64, f64, f64)> // Latitude, longitude and recorded time of the next valid RMC, on RMCs only
+}
+
+// Plays a recorded NMEA log back, pacing it by the sentence times
+pub struct NmeaReplay {
+    sentences: Vec<ReplaySentence>,
+    replay_clock: Arc<ReplayClock>
+}
+
+fn sentence_date(sentence: &Sentence) -> Option<Date> {
+    match &sentence.data {
+        SentenceData::Rmc(rmc) => rmc.date,
+        SentenceData::Zda(zda) => zda.date,
+        _ => None
+    }
+}
+
+// Position of a valid RMC that can be placed in time
+fn sentence_fix(replay_sentence: &ReplaySentence) -> Option<(f64, f64, f64)> {
+    match &replay_sentence.sentence.data {
+        SentenceData::Rmc(rmc) if rmc.valid => Some((rmc.latitude?, rmc.longitude?, replay_sentence.recorded_time?)),
+        _ => None
+    }
+}
+
+fn sentence_time(sentence: &Sentence) -> Option<Time> {
+    match &sentence.data {
+        SentenceData::Rmc(rmc) => rmc.time,
+        SentenceData::Gga(gga) => gga.time,
+        SentenceData::Gll(gll) => gll.time,
+        SentenceData::Zda(zda) => zda.time,
+        _ => None
+    }
+}
+
+// Sentence times are only the time of day, so a big step backwards is the log going past midnight
+fn crossed_midnight(last_time_of_day: f64, time_of_day: f64) -> bool {
+    last_time_of_day - time_of_day > SECONDS_PER_DAY / 2.0
+}
+
+impl NmeaReplay {
+    // The clock is anchored to the first dated sentence so a pcap replay on the same clock lines up with it
+    pub fn open(path: &str, replay_clock: Arc<ReplayClock>) -> Result<NmeaReplay, io_error> {
+        let log = fs::read_to_string(path)?;
+
+        let mut sentences: Vec<ReplaySentence> = Vec::new();
+        let mut date: Option<Date> = None;
+        let mut last_time_of_day: Option<f64> = None;
+        let mut midnights_crossed = 0;
+
+        for line in log.lines() {
+            // Corrupted lines are dropped the same way they are from a live receiver
+            let sentence = match nmea::parse(line) {
+                Ok(sentence) => sentence,
+                Err(_e) => continue
+            };
+
+            let time = sentence_time(&sentence);
+            let time_of_day = time.map(|time| time.seconds_since_midnight());
+
+            match (last_time_of_day, time_of_day) {
+                (Some(last_time_of_day), Some(time_of_day)) if crossed_midnight(last_time_of_day, time_of_day) => {
+                    midnights_crossed += 1;
+
+                    // Only RMC and ZDA have the date, so a GGA just after midnight would otherwise get the day before
+                    date = date.map(|date| Date::from_days_since_unix_epoch(date.days_since_unix_epoch() + 1));
+                },
+                _ => {}
+            }
+
+            last_time_of_day = time_of_day.or(last_time_of_day);
+            date = sentence_date(&sentence).or(date);
+
+            let recorded_time = match (date, time) {
+                (Some(date), Some(time)) => Some(nmea::utc_timestamp(&date, &time)),
+                _ => None
+            };
+
+            sentences.push(ReplaySentence {
+                recorded_time: recorded_time,
+                time_of_day: time_of_day.map(|time_of_day| time_of_day + midnights_crossed as f64 * SECONDS_PER_DAY),
+                sentence: sentence,
+                next_fix: None
+            });
+        }
+
+        if sentences.is_empty() {
+            return Err(io_error::new(ErrorKind::InvalidData, format!("No valid NMEA sentences in {}", path)));
+        }
+
+        // Working backwards so every RMC knows the fix after it, which frames between the two are interpolated with
+        let mut next_fix: Option<(f64, f64, f64)> = None;
+
+        for replay_sentence in sentences.iter_mut().rev() {
+            if let SentenceData::Rmc(_) = replay_sentence.sentence.data {
+                replay_sentence.next_fix = next_fix;
+                next_fix = sentence_fix(replay_sentence).or(next_fix);
+            }
+        }
+
+        if let Some(first_recorded_time) = sentences.iter().find_map(|replay_sentence| replay_sentence.recorded_time) {
+            replay_clock.anchor(first_recorded_time);
+        }
+
+        return Ok(NmeaReplay {sentences: sentences, replay_clock: replay_clock});
+    }
+}
+
+impl LocationSource for NmeaReplay {
+    fn run(&self, shared_gps_data: &SharedGpsData) -> Result<(), io_error> {
+        let mut last_time_of_day: Option<f64> = None;
+
+        for replay_sentence in &self.sentences {
+            match (replay_sentence.recorded_time, replay_sentence.time_of_day, last_time_of_day) {
+                (Some(recorded_time), _, _) => self.replay_clock.wait_until(recorded_time),
+                // Logs without RMC / ZDA have no date, so only the gaps between sentence times can be kept
+                (None, Some(time_of_day), Some(last_time_of_day)) if time_of_day > last_time_of_day => {
+                    self.replay_clock.sleep(Duration::from_secs_f64(time_of_day - last_time_of_day));
+                },
+                _ => {}
+            }
+
+            last_time_of_day = replay_sentence.time_of_day.or(last_time_of_day);
+
+            shared_gps_data.store_sentence_with_next_fix(&replay_sentence.sentence, replay_sentence.next_fix);
+        }
+
+        return Ok(());
+    }
+}
+
+// https://www.topografix.com/GPX/1/1/#type_wptType
+struct GpxPoint {
+    lat: f64,
+    lon: f64,
+    recorded_time: Option<f64>,
+    elevation: Option<f64>, // Meters
+    fix: Option<String>, // none, 2d, 3d, dgps or pps
+    satellites: Option<u8>,
+    hdop: Option<f64>,
+    pdop: Option<f64>
+}
+
+// Plays the track points of a GPX file back, pacing them by their <time>
+pub struct GpxReplay {
+    points: Vec<GpxPoint>,
+    replay_clock: Arc<ReplayClock>
+}
+
+// Value of name="..." or name='...' inside an opening tag
+fn xml_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    for quote in ['"', '\''] {
+        let pattern = format!("{}={}", name, quote);
+
+        for (start, _) in tag.match_indices(&pattern) {
+            // Attributes can be split over lines or by tabs. Anything else in front is part of another name, e.g. lat in xlat
+            if !tag[..start].ends_with(|character: char| character.is_ascii_whitespace()) {
+                continue;
+            }
+
+            let value = &tag[start + pattern.len()..];
+
+            return value.find(quote).map(|end| &value[..end]);
+        }
+    }
+
+    None
+}
+
+// Text of the first <name>...</name> inside body
+fn xml_element_text<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    let start = body.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = body[start..].find(&format!("</{}>", name))? + start;
+
+    Some(body[start..end].trim())
+}
+
+fn parse_gpx(gpx: &str) -> Vec<GpxPoint> {
+    let mut points: Vec<GpxPoint> = Vec::new();
+    let mut remaining = gpx;
+
+    while let Some(start) = remaining.find("<trkpt") {
+        remaining = &remaining[start..];
+
+        let tag_end = match remaining.find('>') {
+            Some(tag_end) => tag_end,
+            None => break
+        };
+
+        let tag = &remaining[..tag_end];
+
+        // <trkpt lat=".." lon=".."/> has no children
+        let (body, next) = if tag.ends_with('/') {
+            ("", tag_end + 1)
+        } else {
+            match remaining.find("</trkpt>") {
+                Some(body_end) => (&remaining[tag_end + 1..body_end], body_end + "</trkpt>".len()),
+                None => break
+            }
+        };
+
+        let lat = xml_attribute(tag, "lat").and_then(|lat| lat.parse().ok());
+        let lon = xml_attribute(tag, "lon").and_then(|lon| lon.parse().ok());
+
+        if let (Some(lat), Some(lon)) = (lat, lon) {
+            points.push(GpxPoint {
+                lat: lat,
+                lon: lon,
+                recorded_time: xml_element_text(body, "time").and_then(gpsd::parse_iso_utc),
+                elevation: xml_element_text(body, "ele").and_then(|elevation| elevation.parse().ok()),
+                fix: xml_element_text(body, "fix").map(|fix| fix.to_string()),
+                satellites: xml_element_text(body, "sat").and_then(|satellites| satellites.parse().ok()),
+                hdop: xml_element_text(body, "hdop").and_then(|hdop| hdop.parse().ok()),
+                pdop: xml_element_text(body, "pdop").and_then(|pdop| pdop.parse().ok())
+            });
+        }
+
+        remaining = &remaining[next..];
+    }
+
+    return points;
+}
+
+impl GpxReplay {
+    // The clock is anchored to the first timed point so a pcap replay on the same clock lines up with it
+    pub fn open(path: &str, replay_clock: Arc<ReplayClock>) -> Result<GpxReplay, io_error> {
+        let points = parse_gpx(&fs::read_to_string(path)?);
+
+        if points.is_empty() {
+            return Err(io_error::new(ErrorKind::InvalidData, format!("No track points in {}", path)));
+        }
+
+        if let Some(first_recorded_time) = points.iter().find_map(|point| point.recorded_time) {
+            replay_clock.anchor(first_recorded_time);
+        }
+
+        return Ok(GpxReplay {points: points, replay_clock: replay_clock});
+    }
+}
+
+impl LocationSource for GpxReplay {
+    fn run(&self, shared_gps_data: &SharedGpsData) -> Result<(), io_error> {
+        for (index, point) in self.points.iter().enumerate() {
+            match point.recorded_time {
+                Some(recorded_time) => self.replay_clock.wait_until(recorded_time),
+                // Untimed tracks are played at one point per second
+                None if index > 0 => self.replay_clock.sleep(Duration::from_secs(1)),
+                None => {}
+            }
+
+            // GPX doesn't have a fix quality, so the fix type is mapped onto the GGA one
+            let fix_quality = match point.fix.as_deref() {
+                Some("none") => 0,
+                Some("dgps") => 2,
+                _ => 1
+            };
+
+            // Frames up to the next point are interpolated towards it
+            let next_fix = match self.points.get(index + 1) {
+                Some(GpxPoint {lat, lon, recorded_time: Some(recorded_time), fix, ..}) if fix.as_deref() != Some("none") => Some((*lat, *lon, *recorded_time)),
+                _ => None
+            };
+
+            shared_gps_data.store_position_with_next_fix(Some(point.lat), Some(point.lon), fix_quality > 0, point.recorded_time, None, None, next_fix);
+            shared_gps_data.store_fix_quality(fix_quality);
+            shared_gps_data.store_altitude(point.elevation);
+            shared_gps_data.store_satellites_used(point.satellites);
+            shared_gps_data.store_hdop(point.hdop);
+            shared_gps_data.store_pdop(point.pdop);
+        }
+
+        return Ok(());
+    }
+}
+
+pub fn new_location_source(backend: GpsBackend, serial_device: &str, baud_rate: u32, gpsd_address: &str, replay_file: Option<&str>, replay_clock: Arc<ReplayClock>) -> Result<Box<dyn LocationSource>, String> {
+    let location_source: Box<dyn LocationSource> = match backend {
+        GpsBackend::Serial => Box::new(SerialNmea {serial_device: serial_device.to_string(), baud_rate: baud_rate}),
+        GpsBackend::Gpsd => Box::new(Gpsd {address: gpsd_address.to_string()}),
+        GpsBackend::NmeaFile | GpsBackend::Gpx => {
+            let replay_file = replay_file.ok_or("gps_replay_file has to be set to replay a location log.")?;
+
+            match backend {
+                GpsBackend::NmeaFile => Box::new(NmeaReplay::open(replay_file, replay_clock).map_err(|e| format!("Unable to load {}: {}", replay_file, e))?),
+                _ => Box::new(GpxReplay::open(replay_file, replay_clock).map_err(|e| format!("Unable to load {}: {}", replay_file, e))?)
+            }
+        }
+    };
+
+    return Ok(location_source);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{path::PathBuf, process, time::UNIX_EPOCH};
+
+    fn sentence(data: &str) -> String {
+        format!("${}*{:02X}", data, data.bytes().fold(0, |checksum, byte| checksum ^ byte))
+    }
+
+    fn write_log(name: &str, lines: &[String]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("wifi_logging_{}_{}", process::id(), name));
+        fs::write(&path, lines.join("\r\n")).unwrap();
+
+        return path;
+    }
+
+    fn open_replay(name: &str, lines: &[String], replay_clock: Arc<ReplayClock>) -> NmeaReplay {
+        let path = write_log(name, lines);
+        let replay = NmeaReplay::open(path.to_str().unwrap(), replay_clock).unwrap();
+        fs::remove_file(path).unwrap();
+
+        return replay;
+    }
+
+    fn utc(day: u8, month: u8, year: u16, seconds_since_midnight: f64) -> f64 {
+        nmea::utc_timestamp(&Date {day: day, month: month, year: year}, &Time {hours: 0, minutes: 0, seconds: seconds_since_midnight})
+    }
+
+    #[test]
+    fn dated_log_rolls_over_midnight() {
+        let replay = open_replay("dated.nmea", &[
+            sentence("GPRMC,235959.00,A,4807.038,N,01131.000,E,,,311224,,"),
+            sentence("GPGGA,235959.50,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,"),
+            sentence("GPGGA,000000.50,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,"),
+            sentence("GPRMC,000001.00,A,4807.038,N,01131.000,E,,,010125,,")
+        ], Arc::new(ReplayClock::new(1.0)));
+
+        let recorded_times: Vec<Option<f64>> = replay.sentences.iter().map(|replay_sentence| replay_sentence.recorded_time).collect();
+
+        assert_eq!(recorded_times, vec![
+            Some(utc(31, 12, 2024, 86_399.0)),
+            Some(utc(31, 12, 2024, 86_399.5)),
+            // Still carrying the date from the first RMC, which has to move on to the next day
+            Some(utc(1, 1, 2025, 0.5)),
+            Some(utc(1, 1, 2025, 1.0))
+        ]);
+    }
+
+    #[test]
+    fn undated_log_keeps_counting_past_midnight() {
+        let replay = open_replay("undated.nmea", &[
+            sentence("GPGGA,235958,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,"),
+            sentence("GPGSA,A,3,04,05,,09,12,,,24,,,,,2.5,1.3,2.1"),
+            sentence("GPGGA,235959,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,"),
+            sentence("GPGGA,000001,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,"),
+            // A sentence slightly out of order isnt another day
+            sentence("GPGGA,000000,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,")
+        ], Arc::new(ReplayClock::new(1.0)));
+
+        let times_of_day: Vec<Option<f64>> = replay.sentences.iter().map(|replay_sentence| replay_sentence.time_of_day).collect();
+
+        assert_eq!(times_of_day, vec![Some(86_398.0), None, Some(86_399.0), Some(86_401.0), Some(86_400.0)]);
+        assert!(replay.sentences.iter().all(|replay_sentence| replay_sentence.recorded_time.is_none()));
+    }
+
+    #[test]
+    fn attributes_after_any_whitespace() {
+        assert_eq!(xml_attribute("<trkpt lat=\"1.5\" lon='2.5'", "lat"), Some("1.5"));
+        assert_eq!(xml_attribute("<trkpt lat=\"1.5\" lon='2.5'", "lon"), Some("2.5"));
+        assert_eq!(xml_attribute("<trkpt\n    lat=\"1.5\"\n\tlon=\"2.5\"", "lat"), Some("1.5"));
+        assert_eq!(xml_attribute("<trkpt\n    lat=\"1.5\"\n\tlon=\"2.5\"", "lon"), Some("2.5"));
+        assert_eq!(xml_attribute("<trkpt xlat=\"9\" lat=\"1.5\"", "lat"), Some("1.5"));
+        assert_eq!(xml_attribute("<trkpt xlat=\"9\"", "lat"), None);
+    }
+
+    #[test]
+    fn gpx_track_points() {
+        let points = parse_gpx(concat!(
+            "<gpx><trk><trkseg>\n",
+            "<trkpt\n  lat=\"51.5\"\n  lon=\"-0.125\">\n",
+            "  <ele>35.5</ele><time>2024-05-01T17:03:09Z</time><fix>dgps</fix><sat>7</sat><hdop>0.9</hdop>\n",
+            "</trkpt>\n",
+            "<trkpt\tlat='51.6'\tlon='-0.126'/>\n",
+            "<trkpt lon=\"1.0\"></trkpt>\n",
+            "</trkseg></trk></gpx>"
+        ));
+
+        assert_eq!(points.len(), 2);
+
+        assert_eq!((points[0].lat, points[0].lon), (51.5, -0.125));
+        assert_eq!(points[0].elevation, Some(35.5));
+        assert_eq!(points[0].recorded_time, Some(1_714_582_989.0));
+        assert_eq!(points[0].fix.as_deref(), Some("dgps"));
+        assert_eq!(points[0].satellites, Some(7));
+        assert_eq!(points[0].hdop, Some(0.9));
+        assert_eq!(points[0].pdop, None);
+
+        assert_eq!((points[1].lat, points[1].lon), (51.6, -0.126));
+        assert_eq!(points[1].recorded_time, None);
+    }
+
+    // The capture waits on the same clock for each frame, so the fix it reads is the last one recorded before the frame
+    #[test]
+    fn replayed_fixes_line_up_with_replayed_frames() {
+        // Fast enough that run() doesnt wait between the fixes
+        let replay = open_replay("handoff.nmea", &[
+            sentence("GPRMC,120000,A,1000.000,N,02000.000,E,,,010524,,"),
+            sentence("GPRMC,120010,A,1100.000,N,02000.000,E,,,010524,,"),
+            sentence("GPRMC,120020,A,1200.000,N,02000.000,E,,,010524,,")
+        ], Arc::new(ReplayClock::new(1e9)));
+
+        let first_fix = utc(1, 5, 2024, 12.0 * 3_600.0);
+        let shared_gps_data = SharedGpsData::new();
+
+        // What a frame recorded halfway between the second and third fix sees, i.e. everything up to it has been stored
+        for replay_sentence in replay.sentences.iter().filter(|replay_sentence| replay_sentence.recorded_time <= Some(first_fix + 15.0)) {
+            shared_gps_data.store_sentence_with_next_fix(&replay_sentence.sentence, replay_sentence.next_fix);
+        }
+
+        let gps_data = shared_gps_data.load();
+        assert_eq!(gps_data.lat, 11.0);
+        assert_eq!(gps_data.utc.unwrap().duration_since(UNIX_EPOCH).unwrap().as_secs_f64(), first_fix + 10.0);
+        assert_eq!(gps_data.next_fix.map(|next_fix| (next_fix.lat, next_fix.utc.duration_since(UNIX_EPOCH).unwrap().as_secs_f64())), Some((12.0, first_fix + 20.0)));
+
+        replay.run(&shared_gps_data).unwrap();
+
+        let gps_data = shared_gps_data.load();
+        assert_eq!(gps_data.lat, 12.0);
+        assert_eq!(gps_data.next_fix, None);
+    }
+}
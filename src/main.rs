@@ -1,63 +1,146 @@
 mod airmon_ng;
+mod interface_manager;
 mod capture;
+mod channel_hopper;
+mod cli;
+mod config;
+mod ieee80211;
+mod radiotap;
 mod tagged_params;
+mod tag_decoders;
 mod types;
 mod gps;
-
-use gps::start_gps;
-use tagged_params::tagged_params_ws;
-use airmon_ng::{start_monitor_mode, stop_monitor_mode, set_channel};
-
-use serde::{Serialize, Deserialize};
+mod gpsd;
+mod location_source;
+mod map_export;
+mod nl80211;
+mod nmea;
+mod pcapng;
+mod pipeline;
+mod sinks;
+mod shutdown;
+mod sqlite;
+mod replay_clock;
+mod session;
+mod wigle;
+
+use cli::{Cli, Command, ExportFormat, ConfigOverrides};
+use config::Config;
+use gps::SharedGpsData;
+use location_source::{GpsBackend, new_location_source};
+use replay_clock::ReplayClock;
+use pipeline::Sighting;
+use session::{SessionTally, SessionSummary};
+use sinks::{SinkConfig, OutputSink, new_sink};
+use channel_hopper::ChannelState;
+use interface_manager::{new_interface_manager, start_monitor_interface};
+
+use clap::Parser;
 
 use std::{
     fs,
     env,
     thread,
     process,
-    time::Duration,
-    fs::OpenOptions,
-    io::{Write, BufWriter},
-    sync::{Arc, mpsc, Mutex, atomic::{AtomicU64}}
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+    io::Write,
+    net::{TcpStream, ToSocketAddrs},
+    sync::{Arc, atomic::{AtomicU64, Ordering::Relaxed}}
 };
 
-// do channels 1, 6 and 11
+// Longest wait between gpsd reconnects. The wait doubles from retries.gps_retry_delay_ms up to this
+const MAX_GPS_RECONNECT_DELAY_MS: u64 = 60_000;
+
 // There seems to be an issue where sometimes stuff is not being printed to console. cant actually figure out why this is. Assuming it wont be a problem when i start writing to file...
 // create better error handling so that the system doesnt halt for one malformated packet
 // more carefully analyze what is being passed into functions i.e. referendes or copies or moving ownership
 
-#[derive(Serialize, Deserialize)]
-struct Config {
-    interface: String,
-    channel: u8,
-    gps_serial_location: String
-}
-
 fn main() {
+    let cli = Cli::parse();
+
     // Relative path
     let exe_path = env::current_exe().expect("Failed to get exe path.");
     let rel_path = exe_path.parent().expect("Failed to find exe parent directory.").to_path_buf();
 
-    // Get config from config.json
-    let config: Config = match fs::read_to_string(rel_path.join("config.json")) {
-        Ok(json_data) => {
-            match serde_json::from_str(&json_data) {
-                Ok(config) => config,
-                Err(e) => {
-                    eprintln!("Json was invalid: {}\n\nCreating an example json file.", e);
+    // Commands that work on existing logs without capturing anything don't need a config
+    let (overrides, pcap_file) = match cli.command {
+        Some(Command::Export {format}) => export(format),
+        Some(Command::Stats {log, session}) => stats(&log, session),
+        Some(Command::ListInterfaces) => list_interfaces(),
+        Some(Command::Check {overrides}) => {
+            let (config, base_directory) = load_config(cli.config.as_deref(), &rel_path, &overrides, None);
 
-                    let example_config = serde_json::to_string_pretty(&Config {interface: "wlan1".to_string(), channel: 1, gps_serial_location: "/dev/serial0".to_string()}).unwrap();
-                    let mut file = fs::File::create(rel_path.join("example_config.json")).unwrap();
-                    file.write_all(example_config.as_bytes()).unwrap();
+            check(&config, &base_directory)
+        },
+        Some(Command::Capture {overrides}) => (overrides, Some(None)),
+        Some(Command::Replay {pcap_file, overrides}) => (overrides, Some(Some(pcap_file))),
+        None => (ConfigOverrides::default(), None)
+    };
 
-                    process::exit(0);
-                }
+    let (config, base_directory) = load_config(cli.config.as_deref(), &rel_path, &overrides, pcap_file);
+
+    run(config, base_directory);
+}
+
+// Reads the config file and applies the command line overrides to it. pcap_file replaces the one in the file if given.
+// Returns the config and the directory relative output paths are from.
+fn load_config(config_path: Option<&Path>, rel_path: &Path, overrides: &ConfigOverrides, pcap_file: Option<Option<String>>) -> (Config, PathBuf) {
+    // Without --config, config.toml or config.json next to the executable
+    let config_file = match config_path {
+        Some(config_path) => Some(config_path.to_path_buf()),
+        None => ["config.toml", "config.json"].iter().map(|name| rel_path.join(name)).find(|path| path.is_file())
+    };
+
+    let config_directory = match &config_file {
+        Some(config_file) => config_file.parent().map(Path::to_path_buf).unwrap_or_default(),
+        None => rel_path.to_path_buf()
+    };
+
+    // Without a config file the command line has to give every required field
+    let mut config_value = match &config_file {
+        Some(config_file) => match config::read_config_file(config_file) {
+            Ok(config_value) => config_value,
+            Err(e) => {
+                eprintln!("{}", e);
+
+                process::exit(1);
             }
         },
-        Err(e) => {
-            eprintln!("'config.json' not found in program root directory.{}\n\nCreating an example config.json...", e);
+        None => serde_json::json!({})
+    };
+
+    if let Err(e) = overrides.apply(&mut config_value) {
+        eprintln!("{}", e);
+
+        process::exit(1);
+    }
+
+    if let (Some(pcap_file), Some(config_object)) = (pcap_file, config_value.as_object_mut()) {
+        config_object.insert("pcap_file".to_string(), serde_json::json!(pcap_file));
+    }
+
+    let config = match (config::from_value(config_value), &config_file) {
+        (Ok(config), _) => config,
+        (Err(problems), Some(config_file)) => {
+            eprintln!("{} can't be used:", config_file.display());
 
-            let example_config = serde_json::to_string_pretty(&Config {interface: "wlan1".to_string(), channel: 1, gps_serial_location: "/dev/serial0".to_string()}).unwrap();
+            for problem in problems {
+                eprintln!("    {}", problem);
+            }
+
+            process::exit(1);
+        },
+        (Err(problems), None) => {
+            eprintln!("No config.toml or config.json in the program root directory and the command line doesn't make up a config:");
+
+            for problem in problems {
+                eprintln!("    {}", problem);
+            }
+
+            eprintln!("\nCreating an example config.json...");
+
+            let example_config = serde_json::to_string_pretty(&config::example_config()).unwrap();
             let mut file = fs::File::create(rel_path.join("config.json")).unwrap();
             file.write_all(example_config.as_bytes()).unwrap();
 
@@ -65,85 +148,258 @@ fn main() {
         }
     };
 
-    let time_a_u64 = Arc::new(AtomicU64::new(0));
-    let lat_a_u64 = Arc::new(AtomicU64::new(0));
-    let lon_a_u64 = Arc::new(AtomicU64::new(0));
+    let base_directory = match &config.output_directory {
+        Some(output_directory) => config_directory.join(output_directory),
+        None => config_directory
+    };
+
+    return (config, base_directory);
+}
+
+fn export(format: ExportFormat) -> ! {
+    let exported = match format {
+        ExportFormat::Wigle {log, output, session} => session::read_session(&log, session)
+            .and_then(|sightings| wigle::export(&sightings, &output))
+            .map(|written| format!("Wrote {} beacons to {}", written, output.display())),
+        ExportFormat::Map {log, output, session} => {
+            let geojson_path = PathBuf::from(format!("{}.geojson", output));
+            let kml_path = PathBuf::from(format!("{}.kml", output));
+
+            session::read_session(&log, session)
+                .and_then(|sightings| map_export::export(&sightings, &geojson_path, &kml_path))
+                .map(|(sightings, transmitters)| format!("Wrote {} sightings and {} transmitters to {} and {}", sightings, transmitters, geojson_path.display(), kml_path.display()))
+        }
+    };
+
+    match exported {
+        Ok(message) => println!("{}", message),
+        Err(e) => {
+            eprintln!("Export failed. {}", e);
 
-    let interface: String;
-    let mut attempts_to_start = 0;
+            process::exit(1);
+        }
+    }
 
-    loop {
-        if attempts_to_start >= 1 {
-            println!("Attempted to start {} times.", attempts_to_start);
+    process::exit(0);
+}
 
-            if attempts_to_start >= 5 {
-                eprintln!("Exiting. Too many attempts to start.");
+fn stats(log: &Path, session_id: Option<i64>) -> ! {
+    match session::read_session(log, session_id) {
+        Ok(sightings) => session::print_stats(&sightings),
+        Err(e) => {
+            eprintln!("{}", e);
 
-                process::exit(0);
-            }
+            process::exit(1);
+        }
+    }
 
-            thread::sleep(Duration::from_secs(3));
+    process::exit(0);
+}
 
-            match stop_monitor_mode(&config.interface) {
-                Ok(_) => {},
-                Err(e) => { println!("{}", e); continue; }
-            };
+fn list_interfaces() -> ! {
+    let interfaces = match capture::get_interfaces() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            eprintln!("Unable to list interfaces: {}", e);
+
+            process::exit(1);
         }
+    };
 
-        attempts_to_start += 1;
+    for interface in interfaces {
+        let mut flags: Vec<&str> = Vec::new();
 
-        // Determine which interface to use based of which one changed. Hacky method....
-        // Could also just predict that the name would be wlan1"mon"
-        let original_interfaces = match capture::get_interfaces() {
-            Ok(interfaces) => interfaces,
-            Err(e) => { println!("Error {}", e); continue; }
-        };
+        if interface.flags.is_wireless() {
+            flags.push("wireless");
+        }
 
-        match start_monitor_mode(&config.interface) {
-            Ok(_) => {},
-            Err(e) => { println!("Error {}", e); continue; }
-        };
+        if interface.flags.is_up() {
+            flags.push("up");
+        }
 
-        let possible_changed_interfaces = match capture::get_interfaces() {
-            Ok(interfaces) => interfaces,
-            Err(e) => { println!("Error {}", e); continue; }
-        };
+        println!("{:<16} {:<16} {}", interface.name, flags.join(","), interface.desc.unwrap_or_default());
+    }
 
-        let changed_interfaces = capture::get_changed_interfaces(original_interfaces, possible_changed_interfaces);
+    process::exit(0);
+}
 
-        if changed_interfaces.len() < 1 {
-            println!("No interface found.");
+// Prints the config that would be used and checks what can be checked without capturing
+fn check(config: &Config, base_directory: &Path) -> ! {
+    println!("{}\n", serde_json::to_string_pretty(config).unwrap());
 
-            continue;
+    let mut problems: Vec<String> = Vec::new();
+
+    match &config.pcap_file {
+        Some(pcap_file) if !Path::new(pcap_file).is_file() => problems.push(format!("pcap_file {} doesn't exist", pcap_file)),
+        Some(_) => {},
+        None => match new_interface_manager(config.interface_backend, config.channel_width).list_interfaces() {
+            Ok(interfaces) if !interfaces.contains(&config.interface) => problems.push(format!("interface {} wasn't found. Found {}", config.interface, interfaces.join(", "))),
+            Ok(_) => {},
+            Err(e) => problems.push(format!("Unable to list interfaces: {}", e))
         }
+    }
 
-        interface = changed_interfaces[0].name.clone();
+    match config.gps_backend {
+        GpsBackend::Serial if !Path::new(&config.gps_serial_location).exists() => problems.push(format!("gps_serial_location {} doesn't exist", config.gps_serial_location)),
+        GpsBackend::Gpsd => {
+            let reachable = config.gpsd_address.to_socket_addrs().ok()
+                .and_then(|mut addresses| addresses.next())
+                .is_some_and(|address| TcpStream::connect_timeout(&address, Duration::from_secs(2)).is_ok());
+
+            if !reachable {
+                problems.push(format!("gpsd isn't reachable at {}", config.gpsd_address));
+            }
+        },
+        // Opening a replay source loads and checks the whole file
+        GpsBackend::NmeaFile | GpsBackend::Gpx => {
+            let replay_clock = Arc::new(ReplayClock::new(config.replay_speed));
 
-        break;
+            if let Err(e) = new_location_source(config.gps_backend, &config.gps_serial_location, config.gps_baud_rate, &config.gpsd_address, config.gps_replay_file.as_deref(), replay_clock) {
+                problems.push(e);
+            }
+        },
+        GpsBackend::Serial => {}
     }
 
-    println!("\nUsing interface: {}", &interface);
+    if !base_directory.as_os_str().is_empty() && !base_directory.is_dir() {
+        problems.push(format!("Output directory {} doesn't exist", base_directory.display()));
+    }
 
-    // Clone Arc AtomicU64s
-    let time_a_u64_clone = Arc::clone(&time_a_u64);
-    let lat_a_u64_clone = Arc::clone(&lat_a_u64);
-    let lon_a_u64_clone = Arc::clone(&lon_a_u64);
+    for sink_config in &config.sinks {
+        println!("Sink: {}", new_sink(sink_config, base_directory).name());
+    }
+
+    if problems.is_empty() {
+        println!("Config OK");
+
+        process::exit(0);
+    }
+
+    for problem in &problems {
+        eprintln!("Problem: {}", problem);
+    }
+
+    process::exit(1);
+}
+
+fn run(config: Config, base_directory: PathBuf) {
+    let started_at = SystemTime::now();
+
+    // Ctrl-C or a systemd stop lets the writer finish and the interface be restored. A second one exits immediately.
+    if let Err(e) = shutdown::install() {
+        eprintln!("Unable to handle signals, stopping will lose unwritten sightings: {}", e);
+    }
+
+    let shared_gps_data = Arc::new(SharedGpsData::new());
+
+    // Set for a live capture so the interface can be put back into managed mode and hopping stopped first at the end
+    let mut monitor_interface_manager = None;
+    let mut channel_hopper_thread = None;
+
+    let (capture_source, channel_state) = match &config.pcap_file {
+        Some(pcap_file) => {
+            println!("\nReplaying capture file: {}", pcap_file);
+
+            (capture::CaptureSource::File(pcap_file.into()), None)
+        },
+        None => {
+            let interface_manager = new_interface_manager(config.interface_backend, config.channel_width);
+
+            let interface = match start_monitor_interface(interface_manager.as_ref(), &config.interface, config.retries.monitor_mode_attempts, Duration::from_millis(config.retries.monitor_mode_retry_delay_ms)) {
+                Ok(interface) => interface,
+                Err(e) => {
+                    eprintln!("Exiting. {}", e);
+
+                    process::exit(0);
+                }
+            };
+
+            println!("\nUsing interface: {}", &interface);
+
+            // Set channel
+            match interface_manager.set_channel(&interface, config.channel) {
+                Ok(_) => println!("Successfully switched channel to channel {}", config.channel),
+                Err(e) => eprintln!("Unable to set channel: {}", e)
+            }
+
+            let channel_state = Arc::new(ChannelState::new(config.channel));
+
+            if let Some(channel_hopping) = config.channel_hopping.clone() {
+                let interface_manager_clone = Arc::clone(&interface_manager);
+                let interface_clone = interface.clone();
+                let channel_state_clone = Arc::clone(&channel_state);
+
+                channel_hopper_thread = Some(thread::spawn(move || channel_hopper::start(interface_manager_clone, &interface_clone, &channel_hopping, channel_state_clone)));
+            }
+
+            monitor_interface_manager = Some(interface_manager);
+
+            (capture::CaptureSource::Interface(interface), Some(channel_state))
+        }
+    };
+
+    // Recorded against the SQLite session
+    let session_source = match &capture_source {
+        capture::CaptureSource::Interface(interface) => interface.clone(),
+        capture::CaptureSource::File(path) => path.display().to_string()
+    };
+
+    let session_source_summary = session_source.clone();
+
+    let replay_clock = Arc::new(ReplayClock::new(config.replay_speed));
+
+    let location_source = match new_location_source(config.gps_backend, &config.gps_serial_location, config.gps_baud_rate, &config.gpsd_address, config.gps_replay_file.as_deref(), Arc::clone(&replay_clock)) {
+        Ok(location_source) => location_source,
+        Err(e) => {
+            eprintln!("Exiting. {}", e);
+
+            process::exit(0);
+        }
+    };
+
+    // A replayed capture only needs pacing when there is a replayed location log for it to line up with
+    let capture_replay_clock = match (&config.pcap_file, config.gps_backend) {
+        (Some(_), GpsBackend::NmeaFile | GpsBackend::Gpx) => Some(replay_clock),
+        _ => None
+    };
+
+    let shared_gps_data_clone = Arc::clone(&shared_gps_data);
+    let retries = config.retries.clone();
 
     // Start gps receving
     thread::spawn(move || {
         let mut attempts_to_start = 0;
+        let mut retry_delay_ms = retries.gps_retry_delay_ms;
 
         loop {
-            match start_gps(&config.gps_serial_location, 9_600, [&time_a_u64_clone, &lat_a_u64_clone, &lon_a_u64_clone]) {
-                Ok(_) => {println!("Successfully started GPS"); break;},
+            let started_at = Instant::now();
+
+            match location_source.run(&shared_gps_data_clone) {
+                Ok(_) => {println!("Location source finished"); break;},
                 Err(e) => eprintln!("Error starting GPS: {}", e)
             };
 
             attempts_to_start += 1;
 
-            thread::sleep(Duration::from_secs(3));
+            if location_source.reconnects() {
+                // A connection that stayed up for a while starts the backoff again
+                if started_at.elapsed() >= Duration::from_millis(MAX_GPS_RECONNECT_DELAY_MS) {
+                    retry_delay_ms = retries.gps_retry_delay_ms;
+                }
+
+                thread::sleep(Duration::from_millis(retry_delay_ms));
+                retry_delay_ms = (retry_delay_ms * 2).clamp(1, MAX_GPS_RECONNECT_DELAY_MS);
 
-            if attempts_to_start >= 3 {
+                if shutdown::requested() {
+                    break;
+                }
+
+                continue;
+            }
+
+            thread::sleep(Duration::from_millis(retries.gps_retry_delay_ms));
+
+            if attempts_to_start >= retries.gps_attempts {
                 eprintln!("GPS was not started!");
 
                 break;
@@ -151,101 +407,150 @@ fn main() {
         }
     });
 
-    // Set channel
-    match set_channel(&interface, config.channel) {
-        Ok(_) => println!("Successfully switched channel to channel 1"),
-        Err(e) => eprintln!("Unable to set channel: {}", e)
-    }
+    let capture_config = config.capture.clone();
 
-    let mut tagged_params_filter: Vec<u8> = Vec::new();
-    tagged_params_filter.push(tagged_params_ws::SSID);
+    let pipeline = config.pipeline.clone();
 
-    let (capture_thread_tx, capture_thread_rx): (mpsc::Sender<(capture::Broadcast, capture::GpsDataDecoded)>, mpsc::Receiver<(capture::Broadcast, capture::GpsDataDecoded)>) = mpsc::channel();
-    let capture_thread_tx_clone = capture_thread_tx.clone();
+    // The capture thread holds the only sender, so the writer knows it has everything once the capture ends
+    let (sighting_sender, sighting_receiver) = pipeline::sighting_channel(pipeline.queue_capacity, pipeline.drop_policy);
 
-    // Clone Arc AtomicU64s
-    let time_a_u64_clone = Arc::clone(&time_a_u64);
-    let lat_a_u64_clone = Arc::clone(&lat_a_u64);
-    let lon_a_u64_clone = Arc::clone(&lon_a_u64);
+    let fix_thresholds = config.fix_thresholds.clone();
 
-    thread::spawn(move || {
-        match capture::start(&interface, &tagged_params_filter, capture_thread_tx_clone, Some([time_a_u64_clone, lat_a_u64_clone, lon_a_u64_clone])) {
-            Ok(_) => println!("Successfully started capture thread"),
-            Err(e) => panic!("Error starting capture thread: {}", e)
-        };
+    // Malformed or truncated frames are skipped by the capture thread and counted here
+    let discarded_frames = Arc::new(AtomicU64::new(0));
+    let discarded_frames_clone = Arc::clone(&discarded_frames);
+
+    // Frames that arent sightings are only worth sending to the writer when there is an archive to put them in
+    let archive_every_frame = config.sinks.iter().any(|sink_config| matches!(sink_config, SinkConfig::Pcapng {..}));
+
+    let capture_thread = thread::spawn(move || {
+        match capture::start(&capture_source, &capture_config, sighting_sender, archive_every_frame, Some(shared_gps_data), fix_thresholds, discarded_frames_clone, channel_state, capture_replay_clock) {
+            Ok(capture_stats) => {
+                println!("Capture finished");
+
+                capture_stats
+            },
+            Err(e) => {
+                eprintln!("Error starting capture thread: {}", e);
+
+                None
+            }
+        }
     });
 
+    let interface_name = config.interface.clone();
+    let summary_path = base_directory.join("session_summaries.jsonl");
+    let discarded_frames_summary = Arc::clone(&discarded_frames);
 
-    // Setup a thread to take items out of the rx.
-    // Not using rx as a buffer since I don't think there is a way to tell how many items in it unless I kept track of that through another shared variable
+    let writer_thread = thread::spawn(move || {
+        let mut tally = SessionTally::default();
+        let mut last_discarded_frames = 0;
+        let mut last_dropped_sightings = 0;
 
-    let logged_packet_dump = Arc::new(Mutex::new(Vec::<(capture::Broadcast, capture::GpsDataDecoded)>::new()));
+        // A sink that fails to open is left out rather than stopping the others
+        let mut sinks: Vec<Box<dyn OutputSink>> = Vec::new();
 
-    let logged_packet_dump_clone = Arc::clone(&logged_packet_dump);
+        for sink_config in &config.sinks {
+            let mut sink = new_sink(sink_config, &base_directory);
 
-    thread::spawn(move || {
-        // Probably want to switch to sqlite3 at some point
-        let output_logged_packets_file = OpenOptions::new().write(true).append(true).create(true).open(rel_path.join("logged_packets.txt")).unwrap();
+            match sink.open(&session_source) {
+                Ok(_) => sinks.push(sink),
+                Err(e) => eprintln!("Unable to open {}: {}", sink.name(), e)
+            }
+        }
+
+        let rotate_interval = config.sink_rotate_minutes.map(|minutes| Duration::from_secs(minutes * 60));
+        let mut last_rotation = Instant::now();
+
+        let flush_interval = Duration::from_millis(pipeline.flush_interval_ms);
 
         loop {
-            /*
-                TODO: do some math so that the dump at number changes based on how many are being dumpped per. dump start at 20
+            // Returns with flush_items sightings, or whatever arrived within flush_interval
+            let (received, open) = sighting_receiver.recv_batch(pipeline.flush_items, flush_interval);
+
+            // The pcapng archive gets every captured frame, hidden networks and frames without any of the tags included
+            if !received.is_empty() {
+                for sink in sinks.iter_mut().filter(|sink| sink.archives_every_frame()) {
+                    sink.write_batch(&received).unwrap_or_else(|e| eprintln!("Unable to write to {}: {}", sink.name(), e));
+                    sink.flush().unwrap_or_else(|e| eprintln!("Unable to flush {}: {}", sink.name(), e));
+                }
+            }
 
-                The reason that I create a separate mpsc channel is because to keep track of how many items are in the original could possibly be
-                troublesome or at least I assume so. It feels like there could be too much complexity there and spots for failure but I don't actually know. 
-            */
+            let batch: Vec<Sighting> = received.into_iter()
+                .filter(|(broadcast, _gps_data)| !broadcast.archive_only)
+                .collect();
 
-            thread::sleep(Duration::from_secs(1));
+            // Every other sink gets the same batch of sightings
+            for (broadcast, gps_data) in &batch {
+                tally.add(broadcast, gps_data);
+            }
 
-            let mut logged_packet_dump_locked = logged_packet_dump_clone.lock().unwrap();
+            if !batch.is_empty() {
+                for sink in sinks.iter_mut().filter(|sink| !sink.archives_every_frame()) {
+                    sink.write_batch(&batch).unwrap_or_else(|e| eprintln!("Unable to write to {}: {}", sink.name(), e));
+                    sink.flush().unwrap_or_else(|e| eprintln!("Unable to flush {}: {}", sink.name(), e));
+                }
+            }
 
-            if logged_packet_dump_locked.len() >= 20 {
-                let to_dump_packets = std::mem::take(&mut *logged_packet_dump_locked);
-                drop(logged_packet_dump_locked);
+            let current_discarded_frames = discarded_frames.load(Relaxed);
 
-                let mut output_logged_packets_file_writer = BufWriter::new(&output_logged_packets_file);
+            if current_discarded_frames != last_discarded_frames {
+                println!("Discarded {} malformed frames so far.", current_discarded_frames);
+                last_discarded_frames = current_discarded_frames;
+            }
 
-                for packet in to_dump_packets {
-                    let (broadcast, gps_data) = packet;
+            let current_dropped_sightings = sighting_receiver.dropped();
 
-                    // Unchecked because the logged packet should not make it to this thread if it didn't have 0x00 (ssid)
-                    let ssid = unsafe { broadcast.found_tags.get(&0x00).unwrap_unchecked() };
+            if current_dropped_sightings != last_dropped_sightings {
+                println!("Dropped {} sightings so far because the writer fell behind.", current_dropped_sightings);
+                last_dropped_sightings = current_dropped_sightings;
+            }
 
-                    // "{} packet recvd for {} from {} at {:.6}, {:.6}, {}",
+            // The capture has ended and everything it sent has been written
+            if !open {
+                break;
+            }
 
-                    writeln!(
-                        output_logged_packets_file_writer,
-                        "{}\t{}\t{}\t{:.6}\t{:.6}\t{}",
-                        value_to_type!(broadcast.packet_type),
-                        &String::from_utf8_lossy(&ssid),
-                        capture::mac_address_to_string(&broadcast.transmitter_mac_address),
-                        gps_data.lat,
-                        gps_data.lon,
-                        gps_data.time
-                    ).unwrap();
+            if rotate_interval.is_some_and(|rotate_interval| last_rotation.elapsed() >= rotate_interval) {
+                for sink in &mut sinks {
+                    sink.rotate().unwrap_or_else(|e| eprintln!("Unable to rotate {}: {}", sink.name(), e));
                 }
+
+                last_rotation = Instant::now();
             }
         }
+
+        for sink in &mut sinks {
+            sink.close().unwrap_or_else(|e| eprintln!("Unable to close {}: {}", sink.name(), e));
+        }
+
+        (tally, sighting_receiver.dropped())
     });
 
+    // The writer finishes once the capture has stopped and everything queued has been written and the sinks closed
+    let (tally, dropped_sightings) = writer_thread.join().unwrap();
+    let capture_stats = capture_thread.join().unwrap_or(None);
 
-    // Not the most proud of this soultion. Might switch to a different method later if i give it some more though.
-    let mut temp_logged_packet_holder: Vec<(capture::Broadcast, capture::GpsDataDecoded)> = Vec::new();
-    
-    loop {
-        thread::sleep(Duration::from_micros(500));
-        let (broadcast, gps_data) = capture_thread_rx.recv().unwrap();
+    let ended_by = if shutdown::signalled() { "signal" } else { "capture finished" };
 
-        if let Some(ssid) = broadcast.found_tags.get(&0x00) {
-            if !ssid.is_empty() && !ssid.iter().all(|&x| x == 0) {
-                temp_logged_packet_holder.push((broadcast, gps_data));
-                
-                //println!("", value_to_type!(broadcast.packet_type), &String::from_utf8_lossy(&ssid), capture::mac_address_to_string(&broadcast.transmitter_mac_address), gps_data.lat, gps_data.lon);
+    // Stops the channel hopper when the capture ended by itself
+    shutdown::request();
 
-                if temp_logged_packet_holder.len() >= 20 {
-                    logged_packet_dump.lock().unwrap().append(&mut temp_logged_packet_holder);
-                }
-            }
+    if let Some(channel_hopper_thread) = channel_hopper_thread {
+        channel_hopper_thread.join().unwrap_or_else(|_e| eprintln!("Channel hopper panicked"));
+    }
+
+    if let Some(interface_manager) = monitor_interface_manager {
+        match interface_manager.stop_monitor_mode(&interface_name) {
+            Ok(_) => println!("Restored {} to managed mode", interface_name),
+            Err(e) => eprintln!("Unable to restore {} to managed mode: {}", interface_name, e)
         }
     }
+
+    let summary = SessionSummary::new(&session_source_summary, started_at, ended_by, &tally, discarded_frames_summary.load(Relaxed), dropped_sightings, capture_stats.as_ref());
+    summary.print();
+
+    if let Err(e) = summary.append_to(&summary_path) {
+        eprintln!("Unable to write the session summary to {}: {}", summary_path.display(), e);
+    }
 }
@@ -0,0 +1,333 @@
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::Path,
+    time::SystemTime,
+    io::{Write, BufWriter}
+};
+use crate::gps::format_utc;
+use crate::session::LoggedSighting;
+
+// Everything seen from one MAC address. Its location is estimated from the sightings that had a position.
+struct Transmitter {
+    packet_type: String,
+    mac: String,
+    ssids: Vec<String>, // Probe requests can ask for any number of networks
+    first_seen: SystemTime,
+    last_seen: SystemTime,
+    best_signal_dbm: Option<i32>,
+    positioned_sightings: usize,
+    weighted_position: (f64, f64, f64), // Sums of lat * weight, lon * weight and weight over sightings with a signal
+    position_sum: (f64, f64) // Sums of lat and lon over all sightings with a position
+}
+
+impl Transmitter {
+    fn new(sighting: &LoggedSighting) -> Transmitter {
+        Transmitter {
+            packet_type: sighting.packet_type.clone(),
+            mac: sighting.mac.clone(),
+            ssids: Vec::new(),
+            first_seen: sighting.seen,
+            last_seen: sighting.seen,
+            best_signal_dbm: None,
+            positioned_sightings: 0,
+            weighted_position: (0.0, 0.0, 0.0),
+            position_sum: (0.0, 0.0)
+        }
+    }
+
+    fn add(&mut self, sighting: &LoggedSighting) {
+        if !sighting.ssid.is_empty() && !self.ssids.contains(&sighting.ssid) {
+            self.ssids.push(sighting.ssid.clone());
+        }
+
+        self.first_seen = self.first_seen.min(sighting.seen);
+        self.last_seen = self.last_seen.max(sighting.seen);
+        self.best_signal_dbm = self.best_signal_dbm.max(sighting.signal_dbm);
+
+        if let Some((lat, lon)) = sighting.position {
+            self.positioned_sightings += 1;
+            self.position_sum = (self.position_sum.0 + lat, self.position_sum.1 + lon);
+
+            // Weighted by received power in milliwatts, so the closest sightings count for far more than the ones at the edge
+            // of range
+            if let Some(signal_dbm) = sighting.signal_dbm {
+                let weight = 10f64.powf(signal_dbm as f64 / 10.0);
+
+                self.weighted_position = (
+                    self.weighted_position.0 + lat * weight,
+                    self.weighted_position.1 + lon * weight,
+                    self.weighted_position.2 + weight
+                );
+            }
+        }
+    }
+
+    // Falls back to the plain average if the log didnt have signal strengths
+    fn estimated_position(&self) -> Option<(f64, f64)> {
+        let (weighted_lat, weighted_lon, total_weight) = self.weighted_position;
+
+        if total_weight > 0.0 {
+            return Some((weighted_lat / total_weight, weighted_lon / total_weight));
+        }
+
+        if self.positioned_sightings > 0 {
+            let count = self.positioned_sightings as f64;
+
+            return Some((self.position_sum.0 / count, self.position_sum.1 / count));
+        }
+
+        None
+    }
+}
+
+fn transmitters(sightings: &[LoggedSighting]) -> Vec<Transmitter> {
+    let mut transmitters: BTreeMap<&str, Transmitter> = BTreeMap::new();
+
+    for sighting in sightings {
+        transmitters.entry(&sighting.mac).or_insert_with(|| Transmitter::new(sighting)).add(sighting);
+    }
+
+    return transmitters.into_values().filter(|transmitter| transmitter.positioned_sightings > 0).collect();
+}
+
+// Properties shared by both kinds of feature, in the order they are written to the KML
+fn sighting_properties(sighting: &LoggedSighting) -> Vec<(&'static str, Value)> {
+    vec![
+        ("kind", json!("sighting")),
+        ("ssid", json!(sighting.ssid)),
+        ("mac", json!(sighting.mac)),
+        ("type", json!(sighting.packet_type)),
+        ("first_seen", json!(format_utc(sighting.seen))),
+        ("last_seen", json!(format_utc(sighting.seen))),
+        ("best_rssi", json!(sighting.signal_dbm)),
+        ("channel", json!(sighting.channel))
+    ]
+}
+
+fn transmitter_properties(transmitter: &Transmitter) -> Vec<(&'static str, Value)> {
+    vec![
+        ("kind", json!("transmitter")),
+        ("ssid", json!(transmitter.ssids.join(", "))),
+        ("ssids", json!(transmitter.ssids)),
+        ("mac", json!(transmitter.mac)),
+        ("type", json!(transmitter.packet_type)),
+        ("first_seen", json!(format_utc(transmitter.first_seen))),
+        ("last_seen", json!(format_utc(transmitter.last_seen))),
+        ("best_rssi", json!(transmitter.best_signal_dbm)),
+        ("sightings", json!(transmitter.positioned_sightings))
+    ]
+}
+
+// https://datatracker.ietf.org/doc/html/rfc7946
+fn geojson_feature(lat: f64, lon: f64, properties: Vec<(&'static str, Value)>) -> Value {
+    let properties: serde_json::Map<String, Value> = properties.into_iter().map(|(name, value)| (name.to_string(), value)).collect();
+
+    json!({
+        "type": "Feature",
+        "geometry": {"type": "Point", "coordinates": [lon, lat]},
+        "properties": properties
+    })
+}
+
+// SSIDs can contain anything, including control characters XML 1.0 doesnt allow at all
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if (c as u32) < 0x20 => escaped.push('\u{FFFD}'),
+            c => escaped.push(c)
+        }
+    }
+
+    return escaped;
+}
+
+// https://developers.google.com/kml/documentation/kmlreference#placemark
+fn kml_placemark(lat: f64, lon: f64, time: Option<SystemTime>, properties: &[(&'static str, Value)]) -> String {
+    let value_text = |value: &Value| match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        value => value.to_string()
+    };
+
+    let name = properties.iter().find(|(name, _value)| *name == "ssid").map(|(_name, value)| value_text(value)).unwrap_or_default();
+    let mac = properties.iter().find(|(name, _value)| *name == "mac").map(|(_name, value)| value_text(value)).unwrap_or_default();
+
+    let mut placemark = format!("<Placemark><name>{}</name>", xml_escape(if name.is_empty() { &mac } else { &name }));
+
+    if let Some(time) = time {
+        placemark.push_str(&format!("<TimeStamp><when>{}</when></TimeStamp>", format_utc(time)));
+    }
+
+    placemark.push_str("<ExtendedData>");
+
+    // The ssids array is already joined into ssid
+    for (name, value) in properties.iter().filter(|(name, _value)| *name != "ssids") {
+        placemark.push_str(&format!("<Data name=\"{}\"><value>{}</value></Data>", name, xml_escape(&value_text(value))));
+    }
+
+    placemark.push_str(&format!("</ExtendedData><Point><coordinates>{:.6},{:.6}</coordinates></Point></Placemark>", lon, lat));
+
+    return placemark;
+}
+
+// Writes every sighting with a position and every transmitter at its estimated location as GeoJSON and KML.
+// Returns how many sightings and transmitters were written.
+pub fn export(sightings: &[LoggedSighting], geojson_path: &Path, kml_path: &Path) -> Result<(usize, usize), String> {
+    let positioned: Vec<&LoggedSighting> = sightings.iter().filter(|sighting| sighting.position.is_some()).collect();
+    let transmitters = transmitters(sightings);
+
+    let mut features: Vec<Value> = Vec::new();
+    let mut sighting_placemarks: Vec<String> = Vec::new();
+    let mut transmitter_placemarks: Vec<String> = Vec::new();
+
+    for sighting in &positioned {
+        let (lat, lon) = sighting.position.unwrap_or_default();
+        let properties = sighting_properties(sighting);
+
+        sighting_placemarks.push(kml_placemark(lat, lon, Some(sighting.seen), &properties));
+        features.push(geojson_feature(lat, lon, properties));
+    }
+
+    for transmitter in &transmitters {
+        let (lat, lon) = transmitter.estimated_position().unwrap_or_default();
+        let properties = transmitter_properties(transmitter);
+
+        transmitter_placemarks.push(kml_placemark(lat, lon, None, &properties));
+        features.push(geojson_feature(lat, lon, properties));
+    }
+
+    let geojson_file = File::create(geojson_path).map_err(|e| format!("Unable to create {}: {}", geojson_path.display(), e))?;
+    let mut writer = BufWriter::new(geojson_file);
+
+    serde_json::to_writer(&mut writer, &json!({"type": "FeatureCollection", "features": features})).map_err(|e| e.to_string())?;
+    writer.flush().map_err(|e| e.to_string())?;
+
+    let kml_file = File::create(kml_path).map_err(|e| format!("Unable to create {}: {}", kml_path.display(), e))?;
+    let mut writer = BufWriter::new(kml_file);
+
+    writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").map_err(|e| e.to_string())?;
+    writeln!(writer, "<kml xmlns=\"http://www.opengis.net/kml/2.2\"><Document><name>Sightings</name>").map_err(|e| e.to_string())?;
+
+    for (folder, placemarks) in [("Sightings", &sighting_placemarks), ("Transmitters", &transmitter_placemarks)] {
+        writeln!(writer, "<Folder><name>{}</name>", folder).map_err(|e| e.to_string())?;
+
+        for placemark in placemarks {
+            writeln!(writer, "{}", placemark).map_err(|e| e.to_string())?;
+        }
+
+        writeln!(writer, "</Folder>").map_err(|e| e.to_string())?;
+    }
+
+    writeln!(writer, "</Document></kml>").map_err(|e| e.to_string())?;
+    writer.flush().map_err(|e| e.to_string())?;
+
+    return Ok((positioned.len(), transmitters.len()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, process, time::{Duration, UNIX_EPOCH}};
+
+    fn sighting(mac: &str, ssid: &str, position: Option<(f64, f64)>, signal_dbm: Option<i32>) -> LoggedSighting {
+        LoggedSighting {
+            packet_type: "BEACON".to_string(),
+            ssid: ssid.to_string(),
+            mac: mac.to_string(),
+            position: position,
+            altitude: None,
+            hdop: None,
+            signal_dbm: signal_dbm,
+            channel: Some(6),
+            seen: UNIX_EPOCH + Duration::from_secs(1_714_582_989),
+            auth_mode: None
+        }
+    }
+
+    fn assert_close(value: (f64, f64), expected: (f64, f64), tolerance: f64) {
+        assert!((value.0 - expected.0).abs() < tolerance && (value.1 - expected.1).abs() < tolerance, "{:?} isnt {:?}", value, expected);
+    }
+
+    #[test]
+    fn estimate_is_pulled_towards_the_strongest_sighting() {
+        let transmitters = transmitters(&[
+            sighting("aa:bb:cc:dd:ee:ff", "Home", Some((10.0, 20.0)), Some(-40)),
+            sighting("aa:bb:cc:dd:ee:ff", "Home", Some((11.0, 21.0)), Some(-80)),
+            sighting("aa:bb:cc:dd:ee:ff", "Home", Some((12.0, 22.0)), Some(-90))
+        ]);
+
+        assert_eq!(transmitters.len(), 1);
+        assert_eq!(transmitters[0].positioned_sightings, 3);
+        assert_eq!(transmitters[0].best_signal_dbm, Some(-40));
+
+        // -40 dBm is 10000 times the power of -80 dBm
+        assert_close(transmitters[0].estimated_position().unwrap(), (10.0, 20.0), 0.001);
+    }
+
+    #[test]
+    fn estimate_is_the_mean_without_signal_strengths() {
+        let transmitters = transmitters(&[
+            sighting("aa:bb:cc:dd:ee:ff", "Home", Some((10.0, 20.0)), None),
+            sighting("aa:bb:cc:dd:ee:ff", "Home", Some((12.0, 23.0)), None)
+        ]);
+
+        assert_close(transmitters[0].estimated_position().unwrap(), (11.0, 21.5), 1e-9);
+    }
+
+    #[test]
+    fn transmitters_without_a_position_are_left_out() {
+        let transmitters = transmitters(&[
+            sighting("aa:bb:cc:dd:ee:ff", "Home", Some((10.0, 20.0)), Some(-60)),
+            // Sightings without a position still count towards the SSIDs and times, but not the position
+            sighting("aa:bb:cc:dd:ee:ff", "Home 5G", None, Some(-30)),
+            sighting("11:22:33:44:55:66", "Away", None, Some(-50))
+        ]);
+
+        assert_eq!(transmitters.len(), 1);
+        assert_eq!(transmitters[0].mac, "aa:bb:cc:dd:ee:ff");
+        assert_eq!(transmitters[0].ssids, vec!["Home", "Home 5G"]);
+        assert_close(transmitters[0].estimated_position().unwrap(), (10.0, 20.0), 1e-9);
+    }
+
+    #[test]
+    fn ssids_are_escaped_for_xml() {
+        assert_eq!(xml_escape("Tom & \"Jerry\" <3>"), "Tom &amp; &quot;Jerry&quot; &lt;3&gt;");
+        assert_eq!(xml_escape("a\u{0}b\u{1b}c\td\ne"), "a\u{FFFD}b\u{FFFD}c\td\ne");
+
+        let placemark = kml_placemark(10.0, 20.0, None, &sighting_properties(&sighting("aa:bb:cc:dd:ee:ff", "<b>&", Some((10.0, 20.0)), None)));
+        assert!(placemark.starts_with("<Placemark><name>&lt;b&gt;&amp;</name>"));
+        assert!(placemark.contains("<Data name=\"ssid\"><value>&lt;b&gt;&amp;</value></Data>"));
+    }
+
+    #[test]
+    fn coordinates_are_longitude_first() {
+        let feature = geojson_feature(10.5, 20.25, Vec::new());
+        assert_eq!(feature["geometry"]["coordinates"], json!([20.25, 10.5]));
+
+        let placemark = kml_placemark(10.5, 20.25, None, &[]);
+        assert!(placemark.ends_with("<Point><coordinates>20.250000,10.500000</coordinates></Point></Placemark>"));
+
+        let geojson_path = std::env::temp_dir().join(format!("wifi_logging_{}_map.geojson", process::id()));
+        let kml_path = std::env::temp_dir().join(format!("wifi_logging_{}_map.kml", process::id()));
+
+        let written = export(&[sighting("aa:bb:cc:dd:ee:ff", "Home", Some((10.5, 20.25)), Some(-60))], &geojson_path, &kml_path);
+
+        let geojson: Value = serde_json::from_str(&fs::read_to_string(&geojson_path).unwrap()).unwrap();
+        fs::remove_file(&geojson_path).unwrap();
+        fs::remove_file(&kml_path).unwrap();
+
+        assert_eq!(written, Ok((1, 1)));
+        assert_eq!(geojson["features"][0]["geometry"]["coordinates"], json!([20.25, 10.5]));
+        assert_eq!(geojson["features"][1]["properties"]["kind"], json!("transmitter"));
+        assert_eq!(geojson["features"][1]["geometry"]["coordinates"], json!([20.25, 10.5]));
+    }
+}
@@ -0,0 +1,501 @@
+use serde::{Serialize, Deserialize};
+use std::{
+    mem,
+    ffi::CString,
+    sync::Mutex,
+    io::Error as io_error
+};
+use crate::interface_manager::InterfaceManager;
+
+// https://git.kernel.org/pub/scm/linux/kernel/git/torvalds/linux.git/tree/include/uapi/linux/nl80211.h
+// https://git.kernel.org/pub/scm/linux/kernel/git/torvalds/linux.git/tree/include/uapi/linux/genetlink.h
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+const NL80211_CMD_SET_WIPHY: u8 = 2;
+const NL80211_CMD_GET_INTERFACE: u8 = 5;
+const NL80211_CMD_NEW_INTERFACE: u8 = 7;
+const NL80211_CMD_DEL_INTERFACE: u8 = 8;
+
+const NL80211_ATTR_WIPHY: u16 = 1;
+const NL80211_ATTR_IFINDEX: u16 = 3;
+const NL80211_ATTR_IFNAME: u16 = 4;
+const NL80211_ATTR_IFTYPE: u16 = 5;
+const NL80211_ATTR_WIPHY_FREQ: u16 = 38;
+const NL80211_ATTR_CHANNEL_WIDTH: u16 = 159;
+const NL80211_ATTR_CENTER_FREQ1: u16 = 160;
+
+const NL80211_IFTYPE_MONITOR: u32 = 6;
+
+const NLMSG_HEADER_LENGTH: usize = 16;
+const GENL_HEADER_LENGTH: usize = 4;
+const NLA_HEADER_LENGTH: usize = 4;
+
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 1;
+const NLM_F_ACK: u16 = 4;
+
+// Longest interface name the kernel accepts (IFNAMSIZ - 1)
+const MAX_INTERFACE_NAME_LENGTH: usize = 15;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub enum ChannelWidth {
+    #[default]
+    #[serde(rename = "20-noht")]
+    NoHt20,
+    #[serde(rename = "20")]
+    Ht20,
+    #[serde(rename = "40")]
+    Ht40,
+    #[serde(rename = "80")]
+    Vht80
+}
+
+impl ChannelWidth {
+    // enum nl80211_chan_width
+    fn nl80211_value(&self) -> u32 {
+        match self {
+            ChannelWidth::NoHt20 => 0,
+            ChannelWidth::Ht20 => 1,
+            ChannelWidth::Ht40 => 2,
+            ChannelWidth::Vht80 => 3
+        }
+    }
+}
+
+pub fn channel_to_frequency(channel: u8) -> Option<u32> {
+    let frequency = match channel {
+        1..=13 => 2407 + 5 * channel as u32,
+        14 => 2484,
+        32..=177 => 5000 + 5 * channel as u32,
+        _ => return None
+    };
+
+    return Some(frequency);
+}
+
+// Center frequency of the whole channel for widths wider than 20 MHz
+fn center_frequency(channel: u8, frequency: u32, width: ChannelWidth) -> Result<u32, String> {
+    match width {
+        ChannelWidth::NoHt20 | ChannelWidth::Ht20 => Ok(frequency),
+        ChannelWidth::Ht40 => {
+            // On 2.4 GHz the secondary channel is above (HT40+) unless that would leave the band. On 5 GHz the 40 MHz
+            // channels are fixed pairs (36+40, 44+48, ..., 149+153, ...) and the lower channel of each pair is HT40+.
+            let first_paired_channel = match channel {
+                1..=7 => return Ok(frequency + 10),
+                8..=14 => return Ok(frequency - 10),
+                36..=144 => 36,
+                149..=177 => 149,
+                _ => return Err(format!("Channel {} is not part of a 40 MHz channel", channel))
+            };
+
+            match (channel - first_paired_channel) % 8 {
+                0 => Ok(frequency + 10),
+                4 => Ok(frequency - 10),
+                _ => Err(format!("Channel {} is not part of a 40 MHz channel", channel))
+            }
+        },
+        ChannelWidth::Vht80 => {
+            // 80 MHz blocks in the 5 GHz band as (first channel, center channel)
+            let blocks: [(u8, u8); 6] = [(36, 42), (52, 58), (100, 106), (116, 122), (132, 138), (149, 155)];
+
+            match blocks.iter().find(|(first, _)| channel >= *first && channel < first + 16) {
+                Some((_, center)) => Ok(5000 + 5 * *center as u32),
+                None => Err(format!("Channel {} is not part of an 80 MHz channel", channel))
+            }
+        }
+    }
+}
+
+fn push_attribute(message: &mut Vec<u8>, attribute_type: u16, value: &[u8]) {
+    message.extend_from_slice(&((NLA_HEADER_LENGTH + value.len()) as u16).to_ne_bytes());
+    message.extend_from_slice(&attribute_type.to_ne_bytes());
+    message.extend_from_slice(value);
+    message.resize((message.len() + 3) & !3, 0);
+}
+
+// (type, value) of each netlink attribute in data
+fn parse_attributes(data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attributes = Vec::new();
+    let mut position = 0;
+
+    while position + NLA_HEADER_LENGTH <= data.len() {
+        let length = u16::from_ne_bytes([data[position], data[position + 1]]) as usize;
+        let attribute_type = u16::from_ne_bytes([data[position + 2], data[position + 3]]) & 0x3fff; // Strip the nested / byte order flags
+
+        if length < NLA_HEADER_LENGTH || position + length > data.len() {
+            break;
+        }
+
+        attributes.push((attribute_type, &data[position + NLA_HEADER_LENGTH..position + length]));
+        position += (length + 3) & !3;
+    }
+
+    return attributes;
+}
+
+fn attribute_u32(attributes: &[(u16, &[u8])], attribute_type: u16) -> Option<u32> {
+    let (_, value) = attributes.iter().find(|(existing, _)| *existing == attribute_type)?;
+
+    Some(u32::from_ne_bytes(value.get(..4)?.try_into().ok()?))
+}
+
+fn interface_index(interface_name: &str) -> Result<u32, String> {
+    let name = CString::new(interface_name).map_err(|e| e.to_string())?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+
+    if index == 0 {
+        return Err(format!("Interface {}: {}", interface_name, io_error::last_os_error()));
+    }
+
+    return Ok(index);
+}
+
+// Brings an interface up or down the same way `ip link set <interface> up/down` does
+fn set_interface_up(interface_name: &str, up: bool) -> Result<(), String> {
+    if interface_name.len() > MAX_INTERFACE_NAME_LENGTH {
+        return Err(format!("Interface name {} is too long", interface_name));
+    }
+
+    unsafe {
+        let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+
+        if socket < 0 {
+            return Err(format!("Unable to open ioctl socket: {}", io_error::last_os_error()));
+        }
+
+        let mut request: libc::ifreq = mem::zeroed();
+
+        for (index, byte) in interface_name.bytes().enumerate() {
+            request.ifr_name[index] = byte as libc::c_char;
+        }
+
+        let mut result = libc::ioctl(socket, libc::SIOCGIFFLAGS as _, &mut request);
+
+        if result >= 0 {
+            if up {
+                request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+            } else {
+                request.ifr_ifru.ifru_flags &= !(libc::IFF_UP as libc::c_short);
+            }
+
+            result = libc::ioctl(socket, libc::SIOCSIFFLAGS as _, &mut request);
+        }
+
+        let error = io_error::last_os_error();
+        libc::close(socket);
+
+        if result < 0 {
+            return Err(format!("Unable to set {} {}: {}", interface_name, if up { "up" } else { "down" }, error));
+        }
+    }
+
+    return Ok(());
+}
+
+// A generic netlink socket talking to the nl80211 family
+struct NetlinkSocket {
+    fd: libc::c_int,
+    sequence: u32
+}
+
+impl NetlinkSocket {
+    fn open() -> Result<NetlinkSocket, String> {
+        unsafe {
+            let fd = libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_GENERIC);
+
+            if fd < 0 {
+                return Err(format!("Unable to open netlink socket: {}", io_error::last_os_error()));
+            }
+
+            let mut address: libc::sockaddr_nl = mem::zeroed();
+            address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+
+            if libc::bind(fd, &address as *const libc::sockaddr_nl as *const libc::sockaddr, mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t) < 0 {
+                let error = io_error::last_os_error();
+                libc::close(fd);
+
+                return Err(format!("Unable to bind netlink socket: {}", error));
+            }
+
+            return Ok(NetlinkSocket {fd: fd, sequence: 1});
+        }
+    }
+
+    // Sends a request and collects the payload (after the generic netlink header) of every reply until the kernel acks
+    fn request(&mut self, family: u16, command: u8, attributes: &[u8]) -> Result<Vec<Vec<u8>>, io_error> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let sequence = self.sequence;
+        let length = NLMSG_HEADER_LENGTH + GENL_HEADER_LENGTH + attributes.len();
+
+        let mut message: Vec<u8> = Vec::with_capacity(length);
+        message.extend_from_slice(&(length as u32).to_ne_bytes());
+        message.extend_from_slice(&family.to_ne_bytes());
+        message.extend_from_slice(&(NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+        message.extend_from_slice(&sequence.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes()); // Port id. Filled in by the kernel
+        message.extend_from_slice(&[command, 0, 0, 0]); // Command, version, reserved
+        message.extend_from_slice(attributes);
+
+        if unsafe { libc::send(self.fd, message.as_ptr() as *const libc::c_void, message.len(), 0) } < 0 {
+            return Err(io_error::last_os_error());
+        }
+
+        let mut replies: Vec<Vec<u8>> = Vec::new();
+        let mut buffer = vec![0u8; 32_768];
+
+        loop {
+            let received = unsafe { libc::recv(self.fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len(), 0) };
+
+            if received < 0 {
+                return Err(io_error::last_os_error());
+            }
+
+            let data = &buffer[..received as usize];
+            let mut position = 0;
+
+            while position + NLMSG_HEADER_LENGTH <= data.len() {
+                let message_length = u32::from_ne_bytes(data[position..position + 4].try_into().unwrap()) as usize;
+                let message_type = u16::from_ne_bytes([data[position + 4], data[position + 5]]);
+                let message_sequence = u32::from_ne_bytes(data[position + 8..position + 12].try_into().unwrap());
+
+                if message_length < NLMSG_HEADER_LENGTH || position + message_length > data.len() {
+                    return Err(io_error::new(std::io::ErrorKind::InvalidData, "Truncated netlink message"));
+                }
+
+                let payload = &data[position + NLMSG_HEADER_LENGTH..position + message_length];
+                position += (message_length + 3) & !3;
+
+                if message_sequence != sequence {
+                    continue;
+                }
+
+                match message_type {
+                    NLMSG_ERROR => {
+                        // 0 is the ack, anything else is a negative errno
+                        let error = payload.get(..4).map(|error| i32::from_ne_bytes(error.try_into().unwrap())).unwrap_or(0);
+
+                        if error != 0 {
+                            return Err(io_error::from_raw_os_error(-error));
+                        }
+
+                        return Ok(replies);
+                    },
+                    NLMSG_DONE => return Ok(replies),
+                    _ => {
+                        if payload.len() >= GENL_HEADER_LENGTH {
+                            replies.push(payload[GENL_HEADER_LENGTH..].to_vec());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for NetlinkSocket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd); }
+    }
+}
+
+// Creates a monitor interface next to the managed one and tunes it directly through nl80211 without needing aircrack-ng
+pub struct Nl80211 {
+    socket: Mutex<NetlinkSocket>, // Locked per request so replies from the hopper and main threads can't interleave
+    family: u16,
+    channel_width: ChannelWidth
+}
+
+impl Nl80211 {
+    pub fn new(channel_width: ChannelWidth) -> Result<Nl80211, String> {
+        let mut socket = NetlinkSocket::open()?;
+
+        let mut attributes: Vec<u8> = Vec::new();
+        push_attribute(&mut attributes, CTRL_ATTR_FAMILY_NAME, b"nl80211\0");
+
+        let replies = socket.request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, &attributes)
+            .map_err(|e| format!("Unable to find the nl80211 family (is cfg80211 loaded?): {}", e))?;
+
+        let family = replies.iter()
+            .find_map(|reply| {
+                let attributes = parse_attributes(reply);
+                let (_, family_id) = attributes.iter().find(|(attribute_type, _)| *attribute_type == CTRL_ATTR_FAMILY_ID)?;
+
+                family_id.get(..2).map(|family_id| u16::from_ne_bytes([family_id[0], family_id[1]]))
+            })
+            .ok_or("nl80211 family id missing from the kernel's reply".to_string())?;
+
+        return Ok(Nl80211 {socket: Mutex::new(socket), family: family, channel_width: channel_width});
+    }
+
+    fn request(&self, command: u8, attributes: &[u8]) -> Result<Vec<Vec<u8>>, io_error> {
+        self.socket.lock().unwrap().request(self.family, command, attributes)
+    }
+
+    fn wiphy(&self, interface_index: u32) -> Result<u32, String> {
+        let mut attributes: Vec<u8> = Vec::new();
+        push_attribute(&mut attributes, NL80211_ATTR_IFINDEX, &interface_index.to_ne_bytes());
+
+        let replies = self.request(NL80211_CMD_GET_INTERFACE, &attributes)
+            .map_err(|e| format!("nl80211 GET_INTERFACE failed: {}", e))?;
+
+        return replies.iter()
+            .find_map(|reply| attribute_u32(&parse_attributes(reply), NL80211_ATTR_WIPHY))
+            .ok_or("nl80211 GET_INTERFACE reply had no wiphy".to_string());
+    }
+}
+
+impl InterfaceManager for Nl80211 {
+    fn start_monitor_mode(&self, interface_name: &str) -> Result<Option<String>, String> {
+        let monitor_interface_name = interface_name.to_owned() + "mon";
+
+        if monitor_interface_name.len() > MAX_INTERFACE_NAME_LENGTH {
+            return Err(format!("Monitor interface name {} is too long", monitor_interface_name));
+        }
+
+        let wiphy = self.wiphy(interface_index(interface_name)?)?;
+
+        let mut attributes: Vec<u8> = Vec::new();
+        push_attribute(&mut attributes, NL80211_ATTR_WIPHY, &wiphy.to_ne_bytes());
+        push_attribute(&mut attributes, NL80211_ATTR_IFNAME, CString::new(monitor_interface_name.as_str()).map_err(|e| e.to_string())?.as_bytes_with_nul());
+        push_attribute(&mut attributes, NL80211_ATTR_IFTYPE, &NL80211_IFTYPE_MONITOR.to_ne_bytes());
+
+        self.request(NL80211_CMD_NEW_INTERFACE, &attributes)
+            .map_err(|e| format!("nl80211 NEW_INTERFACE {} failed: {}", monitor_interface_name, e))?;
+
+        // Keep the managed interface from scanning and changing the channel underneath the monitor interface
+        set_interface_up(interface_name, false)?;
+        set_interface_up(&monitor_interface_name, true)?;
+
+        return Ok(Some(monitor_interface_name));
+    }
+
+    fn stop_monitor_mode(&self, interface_name: &str) -> Result<(), String> {
+        let monitor_interface_name = interface_name.to_owned() + "mon";
+
+        let mut attributes: Vec<u8> = Vec::new();
+        push_attribute(&mut attributes, NL80211_ATTR_IFINDEX, &interface_index(&monitor_interface_name)?.to_ne_bytes());
+
+        self.request(NL80211_CMD_DEL_INTERFACE, &attributes)
+            .map_err(|e| format!("nl80211 DEL_INTERFACE {} failed: {}", monitor_interface_name, e))?;
+
+        set_interface_up(interface_name, true)
+    }
+
+    fn set_channel(&self, interface: &str, channel: u8) -> Result<(), String> {
+        let frequency = channel_to_frequency(channel).ok_or(format!("Unknown channel {}", channel))?;
+        let center_frequency = center_frequency(channel, frequency, self.channel_width)?;
+
+        let mut attributes: Vec<u8> = Vec::new();
+        push_attribute(&mut attributes, NL80211_ATTR_IFINDEX, &interface_index(interface)?.to_ne_bytes());
+        push_attribute(&mut attributes, NL80211_ATTR_WIPHY_FREQ, &frequency.to_ne_bytes());
+        push_attribute(&mut attributes, NL80211_ATTR_CHANNEL_WIDTH, &self.channel_width.nl80211_value().to_ne_bytes());
+        push_attribute(&mut attributes, NL80211_ATTR_CENTER_FREQ1, &center_frequency.to_ne_bytes());
+
+        self.request(NL80211_CMD_SET_WIPHY, &attributes)
+            .map_err(|e| format!("nl80211 SET_WIPHY {} MHz ({:?}) on {} failed: {}", frequency, self.channel_width, interface, e))?;
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_to_frequencies() {
+        assert_eq!(channel_to_frequency(1), Some(2412));
+        assert_eq!(channel_to_frequency(6), Some(2437));
+        assert_eq!(channel_to_frequency(13), Some(2472));
+        assert_eq!(channel_to_frequency(14), Some(2484));
+        assert_eq!(channel_to_frequency(36), Some(5180));
+        assert_eq!(channel_to_frequency(165), Some(5825));
+
+        assert_eq!(channel_to_frequency(0), None);
+        assert_eq!(channel_to_frequency(15), None);
+        assert_eq!(channel_to_frequency(200), None);
+    }
+
+    fn center(channel: u8, width: ChannelWidth) -> Result<u32, String> {
+        center_frequency(channel, channel_to_frequency(channel).unwrap(), width)
+    }
+
+    #[test]
+    fn twenty_mhz_is_centered_on_the_channel() {
+        assert_eq!(center(6, ChannelWidth::NoHt20), Ok(2437));
+        assert_eq!(center(36, ChannelWidth::Ht20), Ok(5180));
+    }
+
+    #[test]
+    fn forty_mhz_pairs() {
+        // 2.4 GHz
+        assert_eq!(center(1, ChannelWidth::Ht40), Ok(2422));
+        assert_eq!(center(7, ChannelWidth::Ht40), Ok(2452));
+        assert_eq!(center(8, ChannelWidth::Ht40), Ok(2437));
+        assert_eq!(center(13, ChannelWidth::Ht40), Ok(2462));
+
+        // 5 GHz pairs share a center, channel 38 for 36+40 and so on
+        assert_eq!(center(36, ChannelWidth::Ht40), Ok(5190));
+        assert_eq!(center(40, ChannelWidth::Ht40), Ok(5190));
+        assert_eq!(center(44, ChannelWidth::Ht40), Ok(5230));
+        assert_eq!(center(48, ChannelWidth::Ht40), Ok(5230));
+        assert_eq!(center(64, ChannelWidth::Ht40), Ok(5310));
+        assert_eq!(center(100, ChannelWidth::Ht40), Ok(5510));
+        assert_eq!(center(108, ChannelWidth::Ht40), Ok(5550));
+        assert_eq!(center(149, ChannelWidth::Ht40), Ok(5755));
+        assert_eq!(center(153, ChannelWidth::Ht40), Ok(5755));
+        assert_eq!(center(161, ChannelWidth::Ht40), Ok(5795));
+
+        assert!(center(38, ChannelWidth::Ht40).is_err());
+        assert!(center(32, ChannelWidth::Ht40).is_err());
+    }
+
+    #[test]
+    fn eighty_mhz_blocks() {
+        assert_eq!(center(36, ChannelWidth::Vht80), Ok(5210));
+        assert_eq!(center(48, ChannelWidth::Vht80), Ok(5210));
+        assert_eq!(center(157, ChannelWidth::Vht80), Ok(5775));
+
+        assert!(center(6, ChannelWidth::Vht80).is_err());
+        assert!(center(165, ChannelWidth::Vht80).is_err());
+    }
+
+    #[test]
+    fn attributes_are_parsed() {
+        let mut data: Vec<u8> = Vec::new();
+        push_attribute(&mut data, NL80211_ATTR_IFINDEX, &7u32.to_ne_bytes());
+        push_attribute(&mut data, NL80211_ATTR_IFNAME, b"wlan0\0"); // Padded to 8 bytes
+        push_attribute(&mut data, NL80211_ATTR_WIPHY | 0x8000, &[]); // Nested flag is stripped
+
+        let attributes = parse_attributes(&data);
+
+        assert_eq!(attributes.len(), 3);
+        assert_eq!(attributes[0], (NL80211_ATTR_IFINDEX, &7u32.to_ne_bytes()[..]));
+        assert_eq!(attributes[1], (NL80211_ATTR_IFNAME, &b"wlan0\0"[..]));
+        assert_eq!(attributes[2], (NL80211_ATTR_WIPHY, &[][..]));
+        assert_eq!(attribute_u32(&attributes, NL80211_ATTR_IFINDEX), Some(7));
+        assert_eq!(attribute_u32(&attributes, NL80211_ATTR_IFTYPE), None);
+    }
+
+    #[test]
+    fn truncated_attributes_are_dropped() {
+        let mut data: Vec<u8> = Vec::new();
+        push_attribute(&mut data, NL80211_ATTR_IFINDEX, &7u32.to_ne_bytes());
+        push_attribute(&mut data, NL80211_ATTR_IFTYPE, &NL80211_IFTYPE_MONITOR.to_ne_bytes());
+
+        // Cut into the second attribute's value
+        assert_eq!(parse_attributes(&data[..10]), vec![(NL80211_ATTR_IFINDEX, &7u32.to_ne_bytes()[..])]);
+
+        // A length shorter than the header stops parsing
+        let mut bad_length = data.clone();
+        bad_length[8..10].copy_from_slice(&2u16.to_ne_bytes());
+        assert_eq!(parse_attributes(&bad_length).len(), 1);
+
+        assert!(parse_attributes(&[]).is_empty());
+        assert!(parse_attributes(&data[..3]).is_empty());
+    }
+}
@@ -0,0 +1,794 @@
+use std::{fmt, str::FromStr};
+
+// https://gpsd.gitlab.io/gpsd/NMEA.html
+// Every sentence looks like $<talker><type>,<field>,<field>,...*<checksum>
+
+#[derive(Debug, PartialEq)]
+pub enum NmeaError {
+    MissingStart,
+    MissingChecksum,
+    BadChecksum { expected: u8, calculated: u8 },
+    UnknownTalker(String),
+    UnsupportedSentence(String),
+    MissingField { sentence: &'static str, field: &'static str },
+    InvalidField { sentence: &'static str, field: &'static str, value: String }
+}
+
+impl fmt::Display for NmeaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NmeaError::MissingStart => write!(f, "Sentence does not start with '$'"),
+            NmeaError::MissingChecksum => write!(f, "Sentence has no checksum"),
+            NmeaError::BadChecksum { expected, calculated } => write!(f, "Checksum was {:02X} but the sentence adds up to {:02X}", expected, calculated),
+            NmeaError::UnknownTalker(talker) => write!(f, "Unknown talker id {}", talker),
+            NmeaError::UnsupportedSentence(sentence) => write!(f, "Unsupported sentence {}", sentence),
+            NmeaError::MissingField { sentence, field } => write!(f, "{} is missing {}", sentence, field),
+            NmeaError::InvalidField { sentence, field, value } => write!(f, "{} has an invalid {}: '{}'", sentence, field, value)
+        }
+    }
+}
+
+impl std::error::Error for NmeaError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Talker {
+    Gps, // GP
+    Glonass, // GL
+    Galileo, // GA
+    Beidou, // GB or BD
+    Qzss, // GQ
+    Gnss // GN - Combined fix from more than one system
+}
+
+impl Talker {
+    fn from_id(id: &str) -> Option<Talker> {
+        let talker = match id {
+            "GP" => Talker::Gps,
+            "GL" => Talker::Glonass,
+            "GA" => Talker::Galileo,
+            "GB" | "BD" => Talker::Beidou,
+            "GQ" => Talker::Qzss,
+            "GN" => Talker::Gnss,
+            _ => return None
+        };
+
+        return Some(talker);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Time {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: f64
+}
+
+impl Time {
+    pub fn seconds_since_midnight(&self) -> f64 {
+        self.hours as f64 * 3_600.0 + self.minutes as f64 * 60.0 + self.seconds
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Date {
+    pub day: u8,
+    pub month: u8,
+    pub year: u16 // Full year. RMC only has two digits which are taken as 1980 - 2079
+}
+
+impl Date {
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    pub fn days_since_unix_epoch(&self) -> i64 {
+        let year = self.year as i64 - if self.month <= 2 { 1 } else { 0 };
+        let month = self.month as i64;
+
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+        return era * 146_097 + day_of_era - 719_468;
+    }
+
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    pub fn from_days_since_unix_epoch(days: i64) -> Date {
+        let days = days + 719_468;
+
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        return Date {day: day as u8, month: month as u8, year: year as u16};
+    }
+}
+
+// Seconds since the unix epoch, including the fractional seconds of the sentence time. NMEA times are always UTC.
+pub fn utc_timestamp(date: &Date, time: &Time) -> f64 {
+    date.days_since_unix_epoch() as f64 * 86_400.0 + time.seconds_since_midnight()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gga {
+    pub time: Option<Time>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub fix_quality: u8, // 0 - invalid, 1 - GPS, 2 - DGPS, 4 - RTK fixed, 5 - RTK float, 6 - estimated
+    pub satellites_used: Option<u8>,
+    pub hdop: Option<f64>,
+    pub altitude: Option<f64>, // Meters above mean sea level
+    pub geoid_separation: Option<f64> // Meters
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rmc {
+    pub time: Option<Time>,
+    pub valid: bool, // Status A (active) or V (void)
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub speed_knots: Option<f64>,
+    pub course: Option<f64>, // Degrees true
+    pub date: Option<Date>,
+    pub magnetic_variation: Option<f64>, // Degrees, negative is west
+    pub mode: Option<char> // NMEA 2.3+. A - autonomous, D - differential, E - estimated, N - not valid
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gsa {
+    pub automatic: bool, // Selection mode A (automatic) or M (manual)
+    pub fix_type: u8, // 1 - no fix, 2 - 2D, 3 - 3D
+    pub satellite_ids: Vec<u8>,
+    pub pdop: Option<f64>,
+    pub hdop: Option<f64>,
+    pub vdop: Option<f64>,
+    pub system_id: Option<u8> // NMEA 4.1+
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SatelliteInView {
+    pub prn: u8,
+    pub elevation: Option<u8>, // Degrees
+    pub azimuth: Option<u16>, // Degrees true
+    pub snr: Option<u8> // dB-Hz
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gsv {
+    pub total_messages: u8,
+    pub message_number: u8,
+    pub satellites_in_view: u8,
+    pub satellites: Vec<SatelliteInView> // Up to 4 per message
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vtg {
+    pub course_true: Option<f64>,
+    pub course_magnetic: Option<f64>,
+    pub speed_knots: Option<f64>,
+    pub speed_kmh: Option<f64>,
+    pub mode: Option<char>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gll {
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub time: Option<Time>,
+    pub valid: bool,
+    pub mode: Option<char>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Zda {
+    pub time: Option<Time>,
+    pub date: Option<Date>,
+    pub local_zone_hours: Option<i8>,
+    pub local_zone_minutes: Option<u8>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SentenceData {
+    Gga(Gga),
+    Rmc(Rmc),
+    Gsa(Gsa),
+    Gsv(Gsv),
+    Vtg(Vtg),
+    Gll(Gll),
+    Zda(Zda)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sentence {
+    pub talker: Talker,
+    pub data: SentenceData
+}
+
+// Fields of a sentence after the address. Anything past the end reads as an empty field.
+struct Fields<'a> {
+    sentence: &'static str,
+    fields: Vec<&'a str>
+}
+
+impl<'a> Fields<'a> {
+    fn get(&self, index: usize) -> &'a str {
+        self.fields.get(index).copied().unwrap_or("")
+    }
+
+    fn require_length(&self, length: usize, field: &'static str) -> Result<(), NmeaError> {
+        if self.fields.len() < length {
+            return Err(NmeaError::MissingField {sentence: self.sentence, field: field});
+        }
+
+        return Ok(());
+    }
+
+    fn invalid(&self, field: &'static str, value: &str) -> NmeaError {
+        NmeaError::InvalidField {sentence: self.sentence, field: field, value: value.to_string()}
+    }
+
+    fn optional<T: FromStr>(&self, index: usize, field: &'static str) -> Result<Option<T>, NmeaError> {
+        let value = self.get(index);
+
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        value.parse::<T>().map(Some).map_err(|_| self.invalid(field, value))
+    }
+
+    fn optional_char(&self, index: usize) -> Option<char> {
+        self.get(index).chars().next()
+    }
+
+    fn time(&self, index: usize) -> Result<Option<Time>, NmeaError> {
+        let value = self.get(index);
+
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        let parse = || -> Option<Time> {
+            let hours: u8 = value.get(0..2)?.parse().ok()?;
+            let minutes: u8 = value.get(2..4)?.parse().ok()?;
+            let seconds: f64 = value.get(4..)?.parse().ok()?;
+
+            if hours > 23 || minutes > 59 || !(0.0..61.0).contains(&seconds) {
+                return None;
+            }
+
+            Some(Time {hours: hours, minutes: minutes, seconds: seconds})
+        };
+
+        parse().map(Some).ok_or(self.invalid("time", value))
+    }
+
+    // ddmmyy as used by RMC
+    fn date(&self, index: usize) -> Result<Option<Date>, NmeaError> {
+        let value = self.get(index);
+
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        let parse = || -> Option<Date> {
+            if value.len() != 6 {
+                return None;
+            }
+
+            let day: u8 = value.get(0..2)?.parse().ok()?;
+            let month: u8 = value.get(2..4)?.parse().ok()?;
+            let year: u16 = value.get(4..6)?.parse().ok()?;
+
+            // GPS didnt exist before 1980
+            valid_date(day, month, if year >= 80 { 1900 + year } else { 2000 + year })
+        };
+
+        parse().map(Some).ok_or(self.invalid("date", value))
+    }
+
+    // ddmm.mmmm / dddmm.mmmm followed by a N/S/E/W field. limit is 90 for latitudes and 180 for longitudes
+    fn coordinate(&self, index: usize, field: &'static str, limit: f64) -> Result<Option<f64>, NmeaError> {
+        let value = self.get(index);
+        let direction = self.get(index + 1);
+
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        let degree = value.parse::<f64>().map_err(|_| self.invalid(field, value))?;
+
+        let degrees = (degree / 100.0).floor();
+        let minutes = degree - degrees * 100.0;
+        let mut decimal = degrees + minutes / 60.0;
+
+        if minutes >= 60.0 || decimal > limit {
+            return Err(self.invalid(field, value));
+        }
+
+        match direction {
+            "N" | "E" => {},
+            "S" | "W" => decimal = -decimal,
+            _ => return Err(self.invalid(field, direction))
+        }
+
+        return Ok(Some(decimal));
+    }
+}
+
+fn valid_date(day: u8, month: u8, year: u16) -> Option<Date> {
+    if day == 0 || day > 31 || month == 0 || month > 12 {
+        return None;
+    }
+
+    Some(Date {day: day, month: month, year: year})
+}
+
+fn calculate_checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |checksum, byte| checksum ^ byte)
+}
+
+pub fn parse(line: &str) -> Result<Sentence, NmeaError> {
+    let line = line.trim_end_matches(['\r', '\n']);
+
+    let body = line.strip_prefix('$').ok_or(NmeaError::MissingStart)?;
+    let (data, checksum) = body.rsplit_once('*').ok_or(NmeaError::MissingChecksum)?;
+
+    let expected = u8::from_str_radix(checksum.trim(), 16).map_err(|_| NmeaError::MissingChecksum)?;
+    let calculated = calculate_checksum(data);
+
+    if expected != calculated {
+        return Err(NmeaError::BadChecksum {expected: expected, calculated: calculated});
+    }
+
+    let mut parts = data.split(',');
+    let address = parts.next().unwrap_or("");
+
+    if address.len() < 5 || !address.is_ascii() {
+        return Err(NmeaError::UnsupportedSentence(address.to_string()));
+    }
+
+    let (talker_id, sentence_type) = address.split_at(address.len() - 3);
+    let talker = Talker::from_id(talker_id).ok_or(NmeaError::UnknownTalker(talker_id.to_string()))?;
+
+    let sentence: &'static str = match sentence_type {
+        "GGA" => "GGA",
+        "RMC" => "RMC",
+        "GSA" => "GSA",
+        "GSV" => "GSV",
+        "VTG" => "VTG",
+        "GLL" => "GLL",
+        "ZDA" => "ZDA",
+        _ => return Err(NmeaError::UnsupportedSentence(address.to_string()))
+    };
+
+    let fields = Fields {sentence: sentence, fields: parts.collect()};
+
+    let data = match sentence {
+        "GGA" => SentenceData::Gga(parse_gga(&fields)?),
+        "RMC" => SentenceData::Rmc(parse_rmc(&fields)?),
+        "GSA" => SentenceData::Gsa(parse_gsa(&fields)?),
+        "GSV" => SentenceData::Gsv(parse_gsv(&fields)?),
+        "VTG" => SentenceData::Vtg(parse_vtg(&fields)?),
+        "GLL" => SentenceData::Gll(parse_gll(&fields)?),
+        _ => SentenceData::Zda(parse_zda(&fields)?)
+    };
+
+    return Ok(Sentence {talker: talker, data: data});
+}
+
+// time, lat, N/S, lon, E/W, quality, satellites, hdop, altitude, M, geoid separation, M, dgps age, dgps station
+fn parse_gga(fields: &Fields) -> Result<Gga, NmeaError> {
+    fields.require_length(9, "altitude")?;
+
+    Ok(Gga {
+        time: fields.time(0)?,
+        latitude: fields.coordinate(1, "latitude", 90.0)?,
+        longitude: fields.coordinate(3, "longitude", 180.0)?,
+        fix_quality: fields.optional(5, "fix quality")?.unwrap_or(0),
+        satellites_used: fields.optional(6, "satellites used")?,
+        hdop: fields.optional(7, "hdop")?,
+        altitude: fields.optional(8, "altitude")?,
+        geoid_separation: fields.optional(10, "geoid separation")?
+    })
+}
+
+// time, status, lat, N/S, lon, E/W, speed, course, date, magnetic variation, E/W, mode
+fn parse_rmc(fields: &Fields) -> Result<Rmc, NmeaError> {
+    fields.require_length(9, "date")?;
+
+    let magnetic_variation = fields.optional::<f64>(9, "magnetic variation")?
+        .map(|variation| if fields.get(10) == "W" { -variation } else { variation });
+
+    Ok(Rmc {
+        time: fields.time(0)?,
+        valid: fields.get(1) == "A",
+        latitude: fields.coordinate(2, "latitude", 90.0)?,
+        longitude: fields.coordinate(4, "longitude", 180.0)?,
+        speed_knots: fields.optional(6, "speed")?,
+        course: fields.optional(7, "course")?,
+        date: fields.date(8)?,
+        magnetic_variation: magnetic_variation,
+        mode: fields.optional_char(11)
+    })
+}
+
+// mode, fix type, 12 satellite ids, pdop, hdop, vdop, system id
+fn parse_gsa(fields: &Fields) -> Result<Gsa, NmeaError> {
+    fields.require_length(17, "vdop")?;
+
+    let mut satellite_ids: Vec<u8> = Vec::new();
+
+    for index in 2..14 {
+        if let Some(satellite_id) = fields.optional(index, "satellite id")? {
+            satellite_ids.push(satellite_id);
+        }
+    }
+
+    Ok(Gsa {
+        automatic: fields.get(0) == "A",
+        fix_type: fields.optional(1, "fix type")?.unwrap_or(1),
+        satellite_ids: satellite_ids,
+        pdop: fields.optional(14, "pdop")?,
+        hdop: fields.optional(15, "hdop")?,
+        vdop: fields.optional(16, "vdop")?,
+        system_id: fields.optional(17, "system id")?
+    })
+}
+
+// total messages, message number, satellites in view, then prn, elevation, azimuth, snr for up to 4 satellites
+fn parse_gsv(fields: &Fields) -> Result<Gsv, NmeaError> {
+    fields.require_length(3, "satellites in view")?;
+
+    let mut satellites: Vec<SatelliteInView> = Vec::new();
+    let mut index = 3;
+
+    // A trailing signal id (NMEA 4.1+) leaves a single field that isn't a whole satellite
+    while index + 4 <= fields.fields.len() {
+        if let Some(prn) = fields.optional(index, "prn")? {
+            satellites.push(SatelliteInView {
+                prn: prn,
+                elevation: fields.optional(index + 1, "elevation")?,
+                azimuth: fields.optional(index + 2, "azimuth")?,
+                snr: fields.optional(index + 3, "snr")?
+            });
+        }
+
+        index += 4;
+    }
+
+    Ok(Gsv {
+        total_messages: fields.optional(0, "total messages")?.ok_or(NmeaError::MissingField {sentence: "GSV", field: "total messages"})?,
+        message_number: fields.optional(1, "message number")?.ok_or(NmeaError::MissingField {sentence: "GSV", field: "message number"})?,
+        satellites_in_view: fields.optional(2, "satellites in view")?.unwrap_or(0),
+        satellites: satellites
+    })
+}
+
+// course true, T, course magnetic, M, speed knots, N, speed km/h, K, mode
+fn parse_vtg(fields: &Fields) -> Result<Vtg, NmeaError> {
+    fields.require_length(8, "speed km/h")?;
+
+    Ok(Vtg {
+        course_true: fields.optional(0, "course true")?,
+        course_magnetic: fields.optional(2, "course magnetic")?,
+        speed_knots: fields.optional(4, "speed knots")?,
+        speed_kmh: fields.optional(6, "speed km/h")?,
+        mode: fields.optional_char(8)
+    })
+}
+
+// lat, N/S, lon, E/W, time, status, mode
+fn parse_gll(fields: &Fields) -> Result<Gll, NmeaError> {
+    fields.require_length(6, "status")?;
+
+    Ok(Gll {
+        latitude: fields.coordinate(0, "latitude", 90.0)?,
+        longitude: fields.coordinate(2, "longitude", 180.0)?,
+        time: fields.time(4)?,
+        valid: fields.get(5) == "A",
+        mode: fields.optional_char(6)
+    })
+}
+
+// time, day, month, year, local zone hours, local zone minutes
+fn parse_zda(fields: &Fields) -> Result<Zda, NmeaError> {
+    fields.require_length(4, "year")?;
+
+    let day: Option<u8> = fields.optional(1, "day")?;
+    let month: Option<u8> = fields.optional(2, "month")?;
+    let year: Option<u16> = fields.optional(3, "year")?;
+
+    let date = match (day, month, year) {
+        (Some(day), Some(month), Some(year)) => Some(valid_date(day, month, year).ok_or(fields.invalid("date", &format!("{}-{}-{}", year, month, day)))?),
+        _ => None
+    };
+
+    Ok(Zda {
+        time: fields.time(0)?,
+        date: date,
+        local_zone_hours: fields.optional(4, "local zone hours")?,
+        local_zone_minutes: fields.optional(5, "local zone minutes")?
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Adds the $ and checksum to the data between them
+    fn sentence(data: &str) -> String {
+        format!("${}*{:02X}", data, calculate_checksum(data))
+    }
+
+    fn assert_close(value: Option<f64>, expected: f64) {
+        let value = value.expect("value is missing");
+        assert!((value - expected).abs() < 1e-6, "{} isnt {}", value, expected);
+    }
+
+    #[test]
+    fn checksum_mismatch_is_rejected() {
+        assert_eq!(
+            parse("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*48"),
+            Err(NmeaError::BadChecksum {expected: 0x48, calculated: 0x47})
+        );
+    }
+
+    #[test]
+    fn missing_or_unreadable_checksum_is_rejected() {
+        assert_eq!(parse("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,"), Err(NmeaError::MissingChecksum));
+        assert_eq!(parse("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*"), Err(NmeaError::MissingChecksum));
+        assert_eq!(parse("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*4G"), Err(NmeaError::MissingChecksum));
+        assert_eq!(parse("GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47"), Err(NmeaError::MissingStart));
+    }
+
+    #[test]
+    fn gga_fields() {
+        let parsed = parse("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n").unwrap();
+        assert_eq!(parsed.talker, Talker::Gps);
+
+        let SentenceData::Gga(gga) = parsed.data else { panic!("not a GGA: {:?}", parsed.data) };
+        assert_eq!(gga.time, Some(Time {hours: 12, minutes: 35, seconds: 19.0}));
+        assert_close(gga.latitude, 48.0 + 7.038 / 60.0);
+        assert_close(gga.longitude, 11.0 + 31.0 / 60.0);
+        assert_eq!(gga.fix_quality, 1);
+        assert_eq!(gga.satellites_used, Some(8));
+        assert_eq!(gga.hdop, Some(0.9));
+        assert_eq!(gga.altitude, Some(545.4));
+        assert_eq!(gga.geoid_separation, Some(46.9));
+    }
+
+    #[test]
+    fn gga_without_a_fix_has_empty_fields() {
+        let parsed = parse(&sentence("GNGGA,000001.00,,,,,0,00,,,M,,M,,")).unwrap();
+        assert_eq!(parsed.talker, Talker::Gnss);
+
+        let SentenceData::Gga(gga) = parsed.data else { panic!("not a GGA: {:?}", parsed.data) };
+        assert_eq!(gga.time, Some(Time {hours: 0, minutes: 0, seconds: 1.0}));
+        assert_eq!(gga.latitude, None);
+        assert_eq!(gga.longitude, None);
+        assert_eq!(gga.fix_quality, 0);
+        assert_eq!(gga.hdop, None);
+    }
+
+    #[test]
+    fn rmc_fields() {
+        let parsed = parse("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A").unwrap();
+
+        let SentenceData::Rmc(rmc) = parsed.data else { panic!("not a RMC: {:?}", parsed.data) };
+        assert_eq!(rmc.time, Some(Time {hours: 12, minutes: 35, seconds: 19.0}));
+        assert!(rmc.valid);
+        assert_close(rmc.latitude, 48.0 + 7.038 / 60.0);
+        assert_close(rmc.longitude, 11.0 + 31.0 / 60.0);
+        assert_eq!(rmc.speed_knots, Some(22.4));
+        assert_eq!(rmc.course, Some(84.4));
+        assert_eq!(rmc.date, Some(Date {day: 23, month: 3, year: 1994}));
+        assert_eq!(rmc.magnetic_variation, Some(-3.1));
+        assert_eq!(rmc.mode, None);
+    }
+
+    #[test]
+    fn rmc_southern_and_western_coordinates_are_negative() {
+        let parsed = parse(&sentence("GPRMC,235959.50,V,3351.000,S,15112.000,W,,,010125,,,N")).unwrap();
+
+        let SentenceData::Rmc(rmc) = parsed.data else { panic!("not a RMC: {:?}", parsed.data) };
+        assert!(!rmc.valid);
+        assert_close(rmc.latitude, -(33.0 + 51.0 / 60.0));
+        assert_close(rmc.longitude, -(151.0 + 12.0 / 60.0));
+        assert_eq!(rmc.date, Some(Date {day: 1, month: 1, year: 2025}));
+        assert_eq!(rmc.mode, Some('N'));
+    }
+
+    #[test]
+    fn coordinates_out_of_range_are_rejected() {
+        // 95 degrees north
+        assert!(matches!(
+            parse(&sentence("GPRMC,123519,A,9500.000,N,01131.000,E,,,230394,,")),
+            Err(NmeaError::InvalidField {field: "latitude", ..})
+        ));
+
+        // 90 degrees east is fine for a longitude, 181 isnt
+        assert!(parse(&sentence("GPRMC,123519,A,4807.038,N,09000.000,E,,,230394,,")).is_ok());
+        assert!(matches!(
+            parse(&sentence("GPGGA,123519,4807.038,N,18100.000,E,1,08,0.9,545.4,M,46.9,M,,")),
+            Err(NmeaError::InvalidField {field: "longitude", ..})
+        ));
+
+        // 60 minutes
+        assert!(matches!(
+            parse(&sentence("GPGGA,123519,4860.000,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,")),
+            Err(NmeaError::InvalidField {field: "latitude", ..})
+        ));
+
+        assert!(matches!(
+            parse(&sentence("GPGGA,123519,4807.038,X,01131.000,E,1,08,0.9,545.4,M,46.9,M,,")),
+            Err(NmeaError::InvalidField {field: "latitude", ..})
+        ));
+    }
+
+    #[test]
+    fn gsa_fields() {
+        let parsed = parse("$GPGSA,A,3,04,05,,09,12,,,24,,,,,2.5,1.3,2.1*39").unwrap();
+
+        let SentenceData::Gsa(gsa) = parsed.data else { panic!("not a GSA: {:?}", parsed.data) };
+        assert!(gsa.automatic);
+        assert_eq!(gsa.fix_type, 3);
+        assert_eq!(gsa.satellite_ids, vec![4, 5, 9, 12, 24]);
+        assert_eq!(gsa.pdop, Some(2.5));
+        assert_eq!(gsa.hdop, Some(1.3));
+        assert_eq!(gsa.vdop, Some(2.1));
+        assert_eq!(gsa.system_id, None);
+    }
+
+    #[test]
+    fn gsa_system_id() {
+        let parsed = parse(&sentence("GNGSA,M,2,65,66,,,,,,,,,,,3.0,2.0,2.2,2")).unwrap();
+
+        let SentenceData::Gsa(gsa) = parsed.data else { panic!("not a GSA: {:?}", parsed.data) };
+        assert!(!gsa.automatic);
+        assert_eq!(gsa.fix_type, 2);
+        assert_eq!(gsa.satellite_ids, vec![65, 66]);
+        assert_eq!(gsa.system_id, Some(2));
+    }
+
+    #[test]
+    fn gsv_fields() {
+        let parsed = parse(&sentence("GPGSV,3,1,11,03,03,111,00,04,15,270,00,06,01,010,,13,06,292,00")).unwrap();
+
+        let SentenceData::Gsv(gsv) = parsed.data else { panic!("not a GSV: {:?}", parsed.data) };
+        assert_eq!(gsv.total_messages, 3);
+        assert_eq!(gsv.message_number, 1);
+        assert_eq!(gsv.satellites_in_view, 11);
+        assert_eq!(gsv.satellites.len(), 4);
+        assert_eq!(gsv.satellites[0], SatelliteInView {prn: 3, elevation: Some(3), azimuth: Some(111), snr: Some(0)});
+        assert_eq!(gsv.satellites[2], SatelliteInView {prn: 6, elevation: Some(1), azimuth: Some(10), snr: None});
+    }
+
+    #[test]
+    fn gsv_without_satellites_or_message_count() {
+        // Last message of a group with a trailing signal id and no satellites left
+        let parsed = parse(&sentence("GLGSV,2,2,04,,,,,1")).unwrap();
+
+        let SentenceData::Gsv(gsv) = parsed.data else { panic!("not a GSV: {:?}", parsed.data) };
+        assert_eq!(gsv.message_number, 2);
+        assert!(gsv.satellites.is_empty());
+
+        assert_eq!(
+            parse(&sentence("GPGSV,,1,11")),
+            Err(NmeaError::MissingField {sentence: "GSV", field: "total messages"})
+        );
+        assert_eq!(
+            parse(&sentence("GPGSV,3,1")),
+            Err(NmeaError::MissingField {sentence: "GSV", field: "satellites in view"})
+        );
+    }
+
+    #[test]
+    fn vtg_fields() {
+        let parsed = parse(&sentence("GPVTG,054.7,T,034.4,M,005.5,N,010.2,K,A")).unwrap();
+
+        let SentenceData::Vtg(vtg) = parsed.data else { panic!("not a VTG: {:?}", parsed.data) };
+        assert_eq!(vtg.course_true, Some(54.7));
+        assert_eq!(vtg.course_magnetic, Some(34.4));
+        assert_eq!(vtg.speed_knots, Some(5.5));
+        assert_eq!(vtg.speed_kmh, Some(10.2));
+        assert_eq!(vtg.mode, Some('A'));
+    }
+
+    #[test]
+    fn vtg_without_a_fix_or_fields() {
+        let parsed = parse(&sentence("GPVTG,,T,,M,,N,,K,N")).unwrap();
+
+        let SentenceData::Vtg(vtg) = parsed.data else { panic!("not a VTG: {:?}", parsed.data) };
+        assert_eq!(vtg.course_true, None);
+        assert_eq!(vtg.speed_knots, None);
+        assert_eq!(vtg.speed_kmh, None);
+        assert_eq!(vtg.mode, Some('N'));
+
+        assert_eq!(
+            parse(&sentence("GPVTG,054.7,T,034.4,M,005.5")),
+            Err(NmeaError::MissingField {sentence: "VTG", field: "speed km/h"})
+        );
+    }
+
+    #[test]
+    fn gll_fields() {
+        let parsed = parse(&sentence("GPGLL,4916.45,N,12311.12,W,225444,A,A")).unwrap();
+
+        let SentenceData::Gll(gll) = parsed.data else { panic!("not a GLL: {:?}", parsed.data) };
+        assert_close(gll.latitude, 49.0 + 16.45 / 60.0);
+        assert_close(gll.longitude, -(123.0 + 11.12 / 60.0));
+        assert_eq!(gll.time, Some(Time {hours: 22, minutes: 54, seconds: 44.0}));
+        assert!(gll.valid);
+        assert_eq!(gll.mode, Some('A'));
+    }
+
+    #[test]
+    fn gll_without_a_fix_or_fields() {
+        let parsed = parse(&sentence("GPGLL,,,,,225444,V")).unwrap();
+
+        let SentenceData::Gll(gll) = parsed.data else { panic!("not a GLL: {:?}", parsed.data) };
+        assert_eq!(gll.latitude, None);
+        assert_eq!(gll.longitude, None);
+        assert!(!gll.valid);
+        assert_eq!(gll.mode, None);
+
+        assert_eq!(
+            parse(&sentence("GPGLL,4916.45,N,12311.12,W")),
+            Err(NmeaError::MissingField {sentence: "GLL", field: "status"})
+        );
+    }
+
+    #[test]
+    fn zda_fields() {
+        let parsed = parse(&sentence("GPZDA,201530.00,04,07,2002,-05,30")).unwrap();
+
+        let SentenceData::Zda(zda) = parsed.data else { panic!("not a ZDA: {:?}", parsed.data) };
+        assert_eq!(zda.time, Some(Time {hours: 20, minutes: 15, seconds: 30.0}));
+        assert_eq!(zda.date, Some(Date {day: 4, month: 7, year: 2002}));
+        assert_eq!(zda.local_zone_hours, Some(-5));
+        assert_eq!(zda.local_zone_minutes, Some(30));
+    }
+
+    #[test]
+    fn zda_without_a_date_or_fields() {
+        // Receivers send empty fields until they know the time
+        let parsed = parse(&sentence("GPZDA,,,,,,")).unwrap();
+
+        let SentenceData::Zda(zda) = parsed.data else { panic!("not a ZDA: {:?}", parsed.data) };
+        assert_eq!(zda.time, None);
+        assert_eq!(zda.date, None);
+        assert_eq!(zda.local_zone_hours, None);
+
+        assert_eq!(
+            parse(&sentence("GPZDA,201530.00,04,07")),
+            Err(NmeaError::MissingField {sentence: "ZDA", field: "year"})
+        );
+        assert!(matches!(
+            parse(&sentence("GPZDA,201530.00,32,07,2002,,")),
+            Err(NmeaError::InvalidField {field: "date", ..})
+        ));
+    }
+
+    #[test]
+    fn short_sentences_are_missing_fields() {
+        assert_eq!(
+            parse(&sentence("GPGSA,A,3,04,05")),
+            Err(NmeaError::MissingField {sentence: "GSA", field: "vdop"})
+        );
+        assert_eq!(
+            parse(&sentence("GPRMC,123519,A")),
+            Err(NmeaError::MissingField {sentence: "RMC", field: "date"})
+        );
+    }
+
+    #[test]
+    fn unknown_talkers_and_sentences() {
+        assert_eq!(parse(&sentence("XXGGA,123519")), Err(NmeaError::UnknownTalker("XX".to_string())));
+        assert_eq!(parse(&sentence("GPTXT,01,01,02,hello")), Err(NmeaError::UnsupportedSentence("GPTXT".to_string())));
+    }
+}
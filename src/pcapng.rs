@@ -0,0 +1,194 @@
+use std::{
+    fs::{File, OpenOptions},
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+    io::{Error as io_error, ErrorKind, Write, BufWriter}
+};
+
+// https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-02.html
+// pcap's Savefile only writes the classic pcap format which has nowhere to put a per packet comment, so the
+// blocks are written here directly.
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x00000001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x00000006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const SHB_USER_APPL: u16 = 4;
+
+pub mod linktypes {
+    pub const IEEE802_11: u16 = 105;
+    pub const IEEE802_11_RADIOTAP: u16 = 127;
+}
+
+const SNAPLEN: u32 = 65_535;
+
+// Writes packets into pcapng files in a directory, starting a new file once the current one grows past rotate_bytes
+pub struct PcapngWriter {
+    directory: PathBuf,
+    rotate_bytes: u64,
+    writer: BufWriter<File>,
+    written_bytes: u64,
+    interfaces: Vec<u16> // Link type of each interface description block in the current file. Index is the interface id
+}
+
+fn padded_length(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+fn push_option(block: &mut Vec<u8>, code: u16, value: &[u8]) {
+    block.extend_from_slice(&code.to_le_bytes());
+    block.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    block.extend_from_slice(value);
+    block.resize(padded_length(block.len()), 0);
+}
+
+// Wraps a block body with its type and the total length that appears at both ends
+fn finish_block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let total_length = (12 + body.len()) as u32;
+    let mut block = Vec::with_capacity(total_length as usize);
+
+    block.extend_from_slice(&block_type.to_le_bytes());
+    block.extend_from_slice(&total_length.to_le_bytes());
+    block.extend_from_slice(body);
+    block.extend_from_slice(&total_length.to_le_bytes());
+
+    return block;
+}
+
+impl PcapngWriter {
+    pub fn open(directory: PathBuf, rotate_bytes: u64) -> Result<PcapngWriter, io_error> {
+        std::fs::create_dir_all(&directory)?;
+
+        let (writer, written_bytes) = PcapngWriter::new_file(&directory)?;
+
+        return Ok(PcapngWriter {directory: directory, rotate_bytes: rotate_bytes, writer: writer, written_bytes: written_bytes, interfaces: Vec::new()});
+    }
+
+    fn new_file(directory: &PathBuf) -> Result<(BufWriter<File>, u64), io_error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let name = format!("beacon_{}_{:03}", now.as_secs(), now.subsec_millis());
+
+        // Files started within the same millisecond get a counter rather than overwriting each other
+        let mut counter = 0;
+
+        let file = loop {
+            let path = match counter {
+                0 => directory.join(format!("{}.pcapng", name)),
+                counter => directory.join(format!("{}_{}.pcapng", name, counter))
+            };
+
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(file) => break file,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => counter += 1,
+                Err(e) => return Err(e)
+            }
+        };
+
+        let mut writer = BufWriter::new(file);
+
+        let mut body: Vec<u8> = Vec::new();
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes()); // Major version
+        body.extend_from_slice(&0u16.to_le_bytes()); // Minor version
+        body.extend_from_slice(&(-1i64).to_le_bytes()); // Section length not specified
+        push_option(&mut body, SHB_USER_APPL, b"Beacon / Probe Request Logging");
+        push_option(&mut body, OPT_END_OF_OPT, &[]);
+
+        let block = finish_block(SECTION_HEADER_BLOCK, &body);
+        writer.write_all(&block)?;
+
+        return Ok((writer, block.len() as u64));
+    }
+
+    pub fn rotate(&mut self) -> Result<(), io_error> {
+        self.writer.flush()?;
+
+        let (writer, written_bytes) = PcapngWriter::new_file(&self.directory)?;
+
+        self.writer = writer;
+        self.written_bytes = written_bytes;
+        self.interfaces.clear();
+
+        return Ok(());
+    }
+
+    // Interface description blocks are written the first time a link type shows up in a file
+    fn interface_id(&mut self, linktype: u16) -> Result<u32, io_error> {
+        if let Some(interface_id) = self.interfaces.iter().position(|&existing| existing == linktype) {
+            return Ok(interface_id as u32);
+        }
+
+        let mut body: Vec<u8> = Vec::new();
+        body.extend_from_slice(&linktype.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes()); // Reserved
+        body.extend_from_slice(&SNAPLEN.to_le_bytes());
+
+        let block = finish_block(INTERFACE_DESCRIPTION_BLOCK, &body);
+        self.writer.write_all(&block)?;
+        self.written_bytes += block.len() as u64;
+
+        self.interfaces.push(linktype);
+
+        return Ok((self.interfaces.len() - 1) as u32);
+    }
+
+    // timestamp is since the unix epoch. Timestamps are written with the default microsecond resolution.
+    pub fn write_packet(&mut self, linktype: u16, timestamp: Duration, original_length: u32, data: &[u8], comment: &str) -> Result<(), io_error> {
+        if self.written_bytes >= self.rotate_bytes {
+            self.rotate()?;
+        }
+
+        let interface_id = self.interface_id(linktype)?;
+        let timestamp_micros = timestamp.as_micros() as u64;
+
+        let mut body: Vec<u8> = Vec::with_capacity(20 + padded_length(data.len()) + padded_length(comment.len()) + 8);
+        body.extend_from_slice(&interface_id.to_le_bytes());
+        body.extend_from_slice(&((timestamp_micros >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(timestamp_micros as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&original_length.to_le_bytes());
+        body.extend_from_slice(data);
+        body.resize(padded_length(body.len()), 0);
+
+        if !comment.is_empty() {
+            push_option(&mut body, OPT_COMMENT, comment.as_bytes());
+            push_option(&mut body, OPT_END_OF_OPT, &[]);
+        }
+
+        let block = finish_block(ENHANCED_PACKET_BLOCK, &body);
+        self.writer.write_all(&block)?;
+        self.written_bytes += block.len() as u64;
+
+        return Ok(());
+    }
+
+    pub fn flush(&mut self) -> Result<(), io_error> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, process};
+
+    #[test]
+    fn rotating_within_a_millisecond_keeps_every_file() {
+        let directory = std::env::temp_dir().join(format!("wifi_logging_{}_pcapng", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        let mut writer = PcapngWriter::open(directory.clone(), 1_000_000).unwrap();
+
+        for _ in 0..3 {
+            writer.rotate().unwrap();
+        }
+
+        writer.writer.flush().unwrap();
+
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 4);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
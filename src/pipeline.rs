@@ -0,0 +1,298 @@
+use serde::{Serialize, Deserialize};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, Condvar, atomic::{AtomicU64, Ordering::Relaxed}},
+    time::{Duration, Instant}
+};
+use crate::capture::{Broadcast, GpsDataDecoded};
+
+pub type Sighting = (Broadcast, GpsDataDecoded);
+
+// What the capture thread does when the writer has fallen behind and the queue is full
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DropPolicy {
+    #[default]
+    Block, // Wait for room. Nothing is lost in the pipeline, but a live capture falls behind and libpcap drops frames instead
+    DropNewest, // Keep what is queued and drop the new sighting
+    DropOldest // Make room by dropping the oldest queued sighting
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub queue_capacity: usize, // Sightings that can wait for the writer before the drop policy applies
+    pub drop_policy: DropPolicy,
+    pub flush_items: usize, // Sinks are written and flushed once this many sightings are waiting
+    pub flush_interval_ms: u64 // and at least this often otherwise
+}
+
+impl Default for PipelineConfig {
+    fn default() -> PipelineConfig {
+        PipelineConfig {queue_capacity: 10_000, drop_policy: DropPolicy::Block, flush_items: 100, flush_interval_ms: 1_000}
+    }
+}
+
+struct QueueState {
+    sightings: VecDeque<Sighting>,
+    sender_closed: bool,
+    receiver_closed: bool
+}
+
+struct Queue {
+    state: Mutex<QueueState>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    drop_policy: DropPolicy,
+    dropped: AtomicU64
+}
+
+// Like an mpsc channel with a single sender, except it is bounded and the receiver takes batches. Dropping either end
+// closes the queue, so a capture thread that ends or panics still lets the writer finish.
+pub struct SightingSender {
+    queue: Arc<Queue>
+}
+
+pub struct SightingReceiver {
+    queue: Arc<Queue>
+}
+
+pub fn sighting_channel(capacity: usize, drop_policy: DropPolicy) -> (SightingSender, SightingReceiver) {
+    let queue = Arc::new(Queue {
+        state: Mutex::new(QueueState {sightings: VecDeque::new(), sender_closed: false, receiver_closed: false}),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+        capacity: capacity.max(1),
+        drop_policy: drop_policy,
+        dropped: AtomicU64::new(0)
+    });
+
+    return (SightingSender {queue: Arc::clone(&queue)}, SightingReceiver {queue: queue});
+}
+
+impl SightingSender {
+    // Returns false once the receiver is gone and there is no point sending any more
+    pub fn send(&self, sighting: Sighting) -> bool {
+        let mut state = self.queue.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        while state.sightings.len() >= self.queue.capacity && !state.receiver_closed {
+            match self.queue.drop_policy {
+                DropPolicy::Block => state = self.queue.not_full.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner()),
+                DropPolicy::DropNewest => {
+                    self.queue.dropped.fetch_add(1, Relaxed);
+                    return true;
+                },
+                DropPolicy::DropOldest => {
+                    state.sightings.pop_front();
+                    self.queue.dropped.fetch_add(1, Relaxed);
+                }
+            }
+        }
+
+        if state.receiver_closed {
+            return false;
+        }
+
+        state.sightings.push_back(sighting);
+        self.queue.not_empty.notify_one();
+
+        return true;
+    }
+}
+
+impl Drop for SightingSender {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.sender_closed = true;
+
+        self.queue.not_empty.notify_all();
+    }
+}
+
+impl SightingReceiver {
+    // Waits until max_items sightings are queued, max_wait has passed or the sender is gone, then takes up to max_items.
+    // The bool is false once the sender is gone and everything has been taken.
+    pub fn recv_batch(&self, max_items: usize, max_wait: Duration) -> (Vec<Sighting>, bool) {
+        let deadline = Instant::now() + max_wait;
+        let mut state = self.queue.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        while state.sightings.len() < max_items && !state.sender_closed {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining.is_zero() {
+                break;
+            }
+
+            state = self.queue.not_empty.wait_timeout(state, remaining).unwrap_or_else(|poisoned| poisoned.into_inner()).0;
+        }
+
+        let take = state.sightings.len().min(max_items.max(1));
+        let batch: Vec<Sighting> = state.sightings.drain(..take).collect();
+        let open = !state.sender_closed || !state.sightings.is_empty();
+
+        self.queue.not_full.notify_all();
+
+        return (batch, open);
+    }
+
+    // Sightings thrown away by the drop policy so far
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Relaxed)
+    }
+}
+
+impl Drop for SightingReceiver {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.receiver_closed = true;
+
+        self.queue.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, thread, sync::atomic::AtomicBool, time::UNIX_EPOCH};
+    use crate::radiotap::RadiotapHeader;
+    use crate::types::packet_types;
+
+    // Told apart by timestamp
+    fn sighting(number: u64) -> Sighting {
+        let broadcast = Broadcast {
+            packet_type: packet_types::BEACON,
+            transmitter_mac_address: [0x02, 0x00, 0x00, 0x00, 0x00, 0x01],
+            receiver_mac_address: [0xff; 6],
+            bssid: [0x02, 0x00, 0x00, 0x00, 0x00, 0x01],
+            found_tags: HashMap::new(),
+            radiotap: RadiotapHeader::default(),
+            channel: None,
+            timestamp: Duration::from_secs(number),
+            received_at: UNIX_EPOCH,
+            received_monotonic: Duration::ZERO,
+            original_length: 0,
+            raw_packet: Vec::new(),
+            archive_only: false
+        };
+
+        return (broadcast, GpsDataDecoded::no_fix());
+    }
+
+    fn numbers(batch: &[Sighting]) -> Vec<u64> {
+        batch.iter().map(|(broadcast, _gps_data)| broadcast.timestamp.as_secs()).collect()
+    }
+
+    #[test]
+    fn drop_newest_keeps_what_is_queued() {
+        let (sender, receiver) = sighting_channel(2, DropPolicy::DropNewest);
+
+        for number in 1..=4 {
+            assert!(sender.send(sighting(number)));
+        }
+
+        assert_eq!(receiver.dropped(), 2);
+        assert_eq!(numbers(&receiver.recv_batch(10, Duration::ZERO).0), vec![1, 2]);
+    }
+
+    #[test]
+    fn drop_oldest_makes_room() {
+        let (sender, receiver) = sighting_channel(2, DropPolicy::DropOldest);
+
+        for number in 1..=4 {
+            assert!(sender.send(sighting(number)));
+        }
+
+        assert_eq!(receiver.dropped(), 2);
+        assert_eq!(numbers(&receiver.recv_batch(10, Duration::ZERO).0), vec![3, 4]);
+    }
+
+    #[test]
+    fn block_waits_for_room() {
+        let (sender, receiver) = sighting_channel(2, DropPolicy::Block);
+        let sent = Arc::new(AtomicBool::new(false));
+
+        sender.send(sighting(1));
+        sender.send(sighting(2));
+
+        let sender_sent = Arc::clone(&sent);
+        let handle = thread::spawn(move || {
+            let result = sender.send(sighting(3));
+            sender_sent.store(true, Relaxed);
+
+            result
+        });
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!sent.load(Relaxed));
+
+        assert_eq!(numbers(&receiver.recv_batch(1, Duration::ZERO).0), vec![1]);
+        assert!(handle.join().unwrap());
+
+        assert_eq!(receiver.dropped(), 0);
+        assert_eq!(numbers(&receiver.recv_batch(10, Duration::ZERO).0), vec![2, 3]);
+    }
+
+    #[test]
+    fn batch_is_taken_once_max_items_are_queued() {
+        let (sender, receiver) = sighting_channel(10, DropPolicy::Block);
+
+        for number in 1..=3 {
+            sender.send(sighting(number));
+        }
+
+        let started = Instant::now();
+        let (batch, open) = receiver.recv_batch(2, Duration::from_secs(10));
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(numbers(&batch), vec![1, 2]);
+        assert!(open);
+    }
+
+    #[test]
+    fn batch_is_taken_after_max_wait() {
+        let (sender, receiver) = sighting_channel(10, DropPolicy::Block);
+        sender.send(sighting(1));
+
+        let started = Instant::now();
+        let (batch, open) = receiver.recv_batch(5, Duration::from_millis(50));
+
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(numbers(&batch), vec![1]);
+        assert!(open);
+    }
+
+    #[test]
+    fn closed_queue_is_drained_first() {
+        let (sender, receiver) = sighting_channel(10, DropPolicy::Block);
+
+        for number in 1..=3 {
+            sender.send(sighting(number));
+        }
+
+        drop(sender);
+
+        let (batch, open) = receiver.recv_batch(2, Duration::from_secs(10));
+        assert_eq!(numbers(&batch), vec![1, 2]);
+        assert!(open);
+
+        let (batch, open) = receiver.recv_batch(2, Duration::from_secs(10));
+        assert_eq!(numbers(&batch), vec![3]);
+        assert!(!open);
+
+        let (batch, open) = receiver.recv_batch(2, Duration::from_secs(10));
+        assert!(batch.is_empty());
+        assert!(!open);
+    }
+
+    #[test]
+    fn sending_stops_once_the_receiver_is_gone() {
+        let (sender, receiver) = sighting_channel(1, DropPolicy::Block);
+        sender.send(sighting(1));
+
+        drop(receiver);
+
+        // Would block forever if the full queue was waited on
+        assert!(!sender.send(sighting(2)));
+    }
+}
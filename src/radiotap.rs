@@ -0,0 +1,428 @@
+use serde::Serialize;
+
+// https://www.radiotap.org/
+// "Data is specified in little endian byte-order" and every field is aligned to its natural boundary
+// relative to the start of the radiotap header.
+
+pub mod present_bits {
+    pub const TSFT: u32 = 0;
+    pub const FLAGS: u32 = 1;
+    pub const RATE: u32 = 2;
+    pub const CHANNEL: u32 = 3;
+    pub const FHSS: u32 = 4;
+    pub const DBM_ANTSIGNAL: u32 = 5;
+    pub const DBM_ANTNOISE: u32 = 6;
+    pub const LOCK_QUALITY: u32 = 7;
+    pub const TX_ATTENUATION: u32 = 8;
+    pub const DB_TX_ATTENUATION: u32 = 9;
+    pub const DBM_TX_POWER: u32 = 10;
+    pub const ANTENNA: u32 = 11;
+    pub const DB_ANTSIGNAL: u32 = 12;
+    pub const DB_ANTNOISE: u32 = 13;
+    pub const RX_FLAGS: u32 = 14;
+    pub const TX_FLAGS: u32 = 15;
+    pub const RTS_RETRIES: u32 = 16;
+    pub const DATA_RETRIES: u32 = 17;
+    pub const XCHANNEL: u32 = 18;
+    pub const MCS: u32 = 19;
+    pub const AMPDU_STATUS: u32 = 20;
+    pub const VHT: u32 = 21;
+    pub const TIMESTAMP: u32 = 22;
+    pub const HE: u32 = 23;
+    pub const HE_MU: u32 = 24;
+    pub const HE_MU_OTHER_USER: u32 = 25;
+    pub const ZERO_LEN_PSDU: u32 = 26;
+    pub const L_SIG: u32 = 27;
+    pub const TLV: u32 = 28;
+    pub const RADIOTAP_NAMESPACE: u32 = 29;
+    pub const VENDOR_NAMESPACE: u32 = 30;
+    pub const EXT: u32 = 31;
+}
+
+// Bits of the flags field (present bit 1)
+pub mod flags {
+    pub const FCS_AT_END: u8 = 0x10;
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Mcs {
+    pub known: u8,
+    pub flags: u8,
+    pub index: u8
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Vht {
+    pub known: u16,
+    pub flags: u8,
+    pub bandwidth: u8,
+    pub mcs_nss: [u8; 4],
+    pub coding: u8,
+    pub group_id: u8,
+    pub partial_aid: u16
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct He {
+    pub data: [u16; 6]
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RadiotapHeader {
+    pub length: u16,
+    pub present: Vec<u32>,
+    pub tsft: Option<u64>,
+    pub flags: Option<u8>,
+    pub rate: Option<u8>, // In 500 kbps units
+    pub channel_frequency: Option<u16>, // MHz
+    pub channel_flags: Option<u16>,
+    pub antenna_signal: Option<i8>, // dBm
+    pub antenna_noise: Option<i8>, // dBm
+    pub antenna: Option<u8>,
+    pub mcs: Option<Mcs>,
+    pub vht: Option<Vht>,
+    pub he: Option<He>,
+    // (antenna index, dBm signal) pairs from the per-antenna namespaces that follow the first one
+    pub antenna_signals: Vec<(u8, i8)>
+}
+
+// (alignment, size) of each field in the radiotap namespace. None means the field is not known so
+// anything after it can't be located.
+fn field_layout(field: u32) -> Option<(usize, usize)> {
+    use present_bits::*;
+
+    let layout = match field {
+        TSFT => (8, 8),
+        FLAGS | RATE => (1, 1),
+        CHANNEL => (2, 4),
+        FHSS => (1, 2),
+        DBM_ANTSIGNAL | DBM_ANTNOISE => (1, 1),
+        LOCK_QUALITY | TX_ATTENUATION | DB_TX_ATTENUATION => (2, 2),
+        DBM_TX_POWER | ANTENNA | DB_ANTSIGNAL | DB_ANTNOISE => (1, 1),
+        RX_FLAGS | TX_FLAGS => (2, 2),
+        RTS_RETRIES | DATA_RETRIES => (1, 1),
+        XCHANNEL => (4, 8),
+        MCS => (1, 3),
+        AMPDU_STATUS => (4, 8),
+        VHT => (2, 12),
+        TIMESTAMP => (8, 12),
+        HE => (2, 12),
+        HE_MU => (2, 12),
+        HE_MU_OTHER_USER => (2, 6),
+        ZERO_LEN_PSDU => (1, 1),
+        L_SIG => (2, 4),
+        // TLVs take up the rest of the header
+        TLV => return None,
+        _ => return None
+    };
+
+    return Some(layout);
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    (offset + alignment - 1) & !(alignment - 1)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset + 8)?;
+
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+#[derive(PartialEq)]
+enum Namespace {
+    Radiotap,
+    Vendor
+}
+
+// Decodes a radiotap header from the start of a packet. Returns None if the header is too short for
+// its own length field or its present bitmaps.
+pub fn parse(packet: &[u8]) -> Option<RadiotapHeader> {
+    // it_version, it_pad, it_len, it_present
+    if packet.len() < 8 || packet[0] != 0x00 {
+        return None;
+    }
+
+    let length = read_u16(packet, 2)?;
+    let header = packet.get(..length as usize)?;
+
+    let mut radiotap = RadiotapHeader {length: length, ..Default::default()};
+
+    // Walk the present bitmask chain. Bit 31 means another u32 bitmap follows.
+    let mut offset = 4;
+
+    loop {
+        let word = read_u32(header, offset)?;
+        radiotap.present.push(word);
+        offset += 4;
+
+        if word & (1 << present_bits::EXT) == 0 {
+            break;
+        }
+    }
+
+    let mut namespace = Namespace::Radiotap;
+    let mut namespace_index = 0; // Which bitmap within the current namespace (bit n of word i is field i * 32 + n)
+    let mut namespace_count = 0; // How many radiotap namespaces have been seen. Anything after the first is per antenna.
+    let mut per_antenna: (Option<u8>, Option<i8>) = (None, None);
+
+    'words: for word_position in 0..radiotap.present.len() {
+        let word = radiotap.present[word_position];
+
+        match namespace {
+            Namespace::Radiotap => {
+                for bit in 0..present_bits::RADIOTAP_NAMESPACE {
+                    if word & (1 << bit) == 0 {
+                        continue;
+                    }
+
+                    let field = namespace_index * 32 + bit;
+
+                    // Nothing after a TLV or a field of unknown size can be found
+                    let (alignment, size) = match field_layout(field) {
+                        Some(layout) => layout,
+                        None => break 'words
+                    };
+
+                    offset = align(offset, alignment);
+
+                    let field_data = match header.get(offset..offset + size) {
+                        Some(field_data) => field_data,
+                        None => break 'words
+                    };
+
+                    if namespace_count == 0 {
+                        decode_field(&mut radiotap, field, field_data);
+                    } else if field == present_bits::DBM_ANTSIGNAL {
+                        per_antenna.1 = Some(field_data[0] as i8);
+                    } else if field == present_bits::ANTENNA {
+                        per_antenna.0 = Some(field_data[0]);
+                    }
+
+                    offset += size;
+                }
+            },
+            Namespace::Vendor => {
+                // The vendor namespace data starts with OUI (3), sub namespace (1) and skip length (2). Only the first
+                // bitmap of the namespace has this header.
+                if namespace_index == 0 {
+                    offset = align(offset, 2);
+
+                    let skip_length = match read_u16(header, offset + 4) {
+                        Some(skip_length) => skip_length as usize,
+                        None => break 'words
+                    };
+
+                    offset += 6 + skip_length;
+                }
+            }
+        }
+
+        // Decide which namespace the next bitmap belongs to
+        if word & (1 << present_bits::RADIOTAP_NAMESPACE) != 0 {
+            finish_antenna(&mut radiotap, &mut per_antenna, namespace_count);
+
+            namespace = Namespace::Radiotap;
+            namespace_index = 0;
+            namespace_count += 1;
+        } else if word & (1 << present_bits::VENDOR_NAMESPACE) != 0 {
+            finish_antenna(&mut radiotap, &mut per_antenna, namespace_count);
+
+            namespace = Namespace::Vendor;
+            namespace_index = 0;
+        } else {
+            namespace_index += 1;
+        }
+    }
+
+    finish_antenna(&mut radiotap, &mut per_antenna, namespace_count);
+
+    return Some(radiotap);
+}
+
+fn finish_antenna(radiotap: &mut RadiotapHeader, per_antenna: &mut (Option<u8>, Option<i8>), namespace_count: u32) {
+    if namespace_count == 0 {
+        return;
+    }
+
+    if let (Some(antenna), Some(signal)) = *per_antenna {
+        radiotap.antenna_signals.push((antenna, signal));
+    }
+
+    *per_antenna = (None, None);
+}
+
+fn decode_field(radiotap: &mut RadiotapHeader, field: u32, data: &[u8]) {
+    use present_bits::*;
+
+    match field {
+        TSFT => radiotap.tsft = read_u64(data, 0),
+        FLAGS => radiotap.flags = Some(data[0]),
+        RATE => radiotap.rate = Some(data[0]),
+        CHANNEL => {
+            radiotap.channel_frequency = read_u16(data, 0);
+            radiotap.channel_flags = read_u16(data, 2);
+        },
+        DBM_ANTSIGNAL => radiotap.antenna_signal = Some(data[0] as i8),
+        DBM_ANTNOISE => radiotap.antenna_noise = Some(data[0] as i8),
+        ANTENNA => radiotap.antenna = Some(data[0]),
+        XCHANNEL => {
+            // Only use XChannel if the regular channel field wasnt present
+            if radiotap.channel_frequency.is_none() {
+                radiotap.channel_frequency = read_u16(data, 4);
+                radiotap.channel_flags = read_u32(data, 0).map(|flags| flags as u16);
+            }
+        },
+        MCS => radiotap.mcs = Some(Mcs {known: data[0], flags: data[1], index: data[2]}),
+        VHT => {
+            radiotap.vht = Some(Vht {
+                known: read_u16(data, 0).unwrap_or(0),
+                flags: data[2],
+                bandwidth: data[3],
+                mcs_nss: [data[4], data[5], data[6], data[7]],
+                coding: data[8],
+                group_id: data[9],
+                partial_aid: read_u16(data, 10).unwrap_or(0)
+            });
+        },
+        HE => {
+            let mut he = He::default();
+
+            for (index, value) in he.data.iter_mut().enumerate() {
+                *value = read_u16(data, index * 2).unwrap_or(0);
+            }
+
+            radiotap.he = Some(he);
+        },
+        _ => {}
+    }
+}
+
+// Converts a channel frequency in MHz to an 802.11 channel number
+pub fn frequency_to_channel(frequency: u16) -> Option<u8> {
+    let channel = match frequency {
+        2484 => 14,
+        2412..=2472 => (frequency - 2407) / 5,
+        5955..=7115 => (frequency - 5950) / 5,
+        5000..=5925 => (frequency - 5000) / 5,
+        _ => return None
+    };
+
+    return Some(channel as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::present_bits::*;
+
+    // Version, pad, length and the present words, followed by the field data
+    fn header(present: &[u32], fields: &[u8]) -> Vec<u8> {
+        let length = 4 + present.len() * 4 + fields.len();
+
+        let mut header = vec![0, 0];
+        header.extend_from_slice(&(length as u16).to_le_bytes());
+
+        for word in present {
+            header.extend_from_slice(&word.to_le_bytes());
+        }
+
+        header.extend_from_slice(fields);
+
+        return header;
+    }
+
+    #[test]
+    fn per_antenna_namespaces_in_extra_present_words() {
+        let present = [
+            1 << FLAGS | 1 << CHANNEL | 1 << DBM_ANTSIGNAL | 1 << RADIOTAP_NAMESPACE | 1 << EXT,
+            1 << DBM_ANTSIGNAL | 1 << ANTENNA | 1 << RADIOTAP_NAMESPACE | 1 << EXT,
+            1 << DBM_ANTSIGNAL | 1 << ANTENNA
+        ];
+        // Fields start at 16. flags, pad to 18, channel, signal, then signal / antenna pairs
+        let fields = [0x10, 0, 0x85, 0x09, 0xa0, 0x00, (-40i8) as u8, (-42i8) as u8, 0, (-45i8) as u8, 1];
+
+        let radiotap = parse(&header(&present, &fields)).unwrap();
+
+        assert_eq!(radiotap.present.len(), 3);
+        assert_eq!(radiotap.flags, Some(flags::FCS_AT_END));
+        assert_eq!(radiotap.channel_frequency, Some(2437));
+        assert_eq!(radiotap.channel_flags, Some(0x00a0));
+        assert_eq!(radiotap.antenna_signal, Some(-40));
+        assert_eq!(radiotap.antenna_signals, vec![(0, -42), (1, -45)]);
+        assert_eq!(frequency_to_channel(2437), Some(6));
+    }
+
+    #[test]
+    fn fields_are_aligned_to_their_size() {
+        let present = [1 << TSFT | 1 << FLAGS | 1 << CHANNEL | 1 << DBM_ANTSIGNAL | 1 << MCS | 1 << EXT, 0];
+
+        // Present words end at 12 so the TSFT is padded to 16. The channel is padded from 25 to 26.
+        let mut fields = vec![0xee; 4];
+        fields.extend_from_slice(&0x0102030405060708u64.to_le_bytes());
+        fields.extend_from_slice(&[0x00, 0xee, 0x3c, 0x14, 0x40, 0x01, (-60i8) as u8, 0x07, 0x00, 0x05]);
+
+        let radiotap = parse(&header(&present, &fields)).unwrap();
+
+        assert_eq!(radiotap.tsft, Some(0x0102030405060708));
+        assert_eq!(radiotap.flags, Some(0));
+        assert_eq!(radiotap.channel_frequency, Some(5180));
+        assert_eq!(radiotap.antenna_signal, Some(-60));
+
+        let mcs = radiotap.mcs.unwrap();
+        assert_eq!((mcs.known, mcs.flags, mcs.index), (0x07, 0x00, 0x05));
+    }
+
+    #[test]
+    fn vendor_namespace_is_skipped() {
+        let present = [
+            1 << FLAGS | 1 << VENDOR_NAMESPACE | 1 << EXT,
+            1 | 1 << RADIOTAP_NAMESPACE | 1 << EXT,
+            1 << DBM_ANTSIGNAL | 1 << ANTENNA
+        ];
+        // flags at 16, pad to 18, OUI, sub namespace, skip length 4, vendor data that looks like a signal, then the
+        // next radiotap namespace
+        let fields = [0x00, 0xee, 0x00, 0x11, 0x22, 0x01, 0x04, 0x00, 0x7f, 0x7f, 0x7f, 0x7f, (-50i8) as u8, 2];
+
+        let radiotap = parse(&header(&present, &fields)).unwrap();
+
+        assert_eq!(radiotap.flags, Some(0));
+        assert_eq!(radiotap.antenna_signal, None);
+        assert_eq!(radiotap.antenna_signals, vec![(2, -50)]);
+    }
+
+    #[test]
+    fn unknown_field_stops_the_walk() {
+        // Bit 28 is a TLV, which runs to the end of the header. Whatever was decoded before it is kept.
+        let present = [1 << FLAGS | 1 << TLV];
+        let radiotap = parse(&header(&present, &[0x10, 0xaa, 0xbb])).unwrap();
+
+        assert_eq!(radiotap.flags, Some(flags::FCS_AT_END));
+    }
+
+    #[test]
+    fn truncated_headers_are_rejected() {
+        assert!(parse(&[0, 0, 8]).is_none());
+
+        // Length says 8 but only 6 bytes are there
+        assert!(parse(&[0, 0, 8, 0, 0, 0]).is_none());
+
+        // The EXT bit promises another present word that isnt there
+        assert!(parse(&header(&[1 << EXT], &[])).is_none());
+
+        // Only version 0 exists
+        assert!(parse(&[1, 0, 8, 0, 0, 0, 0, 0]).is_none());
+    }
+}
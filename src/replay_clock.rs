@@ -0,0 +1,76 @@
+use std::{
+    thread,
+    sync::Mutex,
+    time::{Duration, Instant}
+};
+use crate::shutdown;
+
+// Long gaps in a recording are slept through in steps of this so shutdown isnt held up
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+fn sleep_unless_shutdown(duration: Duration) {
+    let deadline = Instant::now() + duration;
+
+    while !shutdown::requested() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if remaining.is_zero() {
+            return;
+        }
+
+        thread::sleep(remaining.min(SHUTDOWN_CHECK_INTERVAL));
+    }
+}
+
+// Shared by the location replay and the pcap replay so a recorded drive plays back with the frames and fixes lined up.
+// Recorded times are seconds since the unix epoch (GPS UTC for fixes, the pcap header time for frames).
+pub struct ReplayClock {
+    speed: f64, // 2.0 plays back twice as fast as it was recorded
+    started: Instant,
+    anchor: Mutex<Option<f64>> // Recorded time that lines up with started
+}
+
+impl ReplayClock {
+    pub fn new(speed: f64) -> ReplayClock {
+        // Anything that isn't a positive speed would never finish waiting
+        let speed = if speed.is_finite() && speed > 0.0 { speed } else { 1.0 };
+
+        ReplayClock {speed: speed, started: Instant::now(), anchor: Mutex::new(None)}
+    }
+
+    // Lines recorded_time up with the moment the clock was created. Only the first anchor counts.
+    pub fn anchor(&self, recorded_time: f64) {
+        let mut anchor = self.anchor.lock().unwrap();
+
+        if anchor.is_none() {
+            *anchor = Some(recorded_time);
+        }
+    }
+
+    // Sleeps until recorded_time comes around. Times before the anchor return straight away.
+    pub fn wait_until(&self, recorded_time: f64) {
+        let anchor = {
+            let mut anchor = self.anchor.lock().unwrap();
+
+            *anchor.get_or_insert(recorded_time)
+        };
+
+        let offset = (recorded_time - anchor) / self.speed;
+
+        if offset <= 0.0 || !offset.is_finite() {
+            return;
+        }
+
+        let elapsed = self.started.elapsed();
+        let target = Duration::from_secs_f64(offset);
+
+        if target > elapsed {
+            sleep_unless_shutdown(target - elapsed);
+        }
+    }
+
+    // For sources with no timestamps to pace by
+    pub fn sleep(&self, recorded_duration: Duration) {
+        sleep_unless_shutdown(recorded_duration.div_f64(self.speed));
+    }
+}
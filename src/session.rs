@@ -0,0 +1,328 @@
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File, OpenOptions},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+    io::{BufRead, BufReader, Write}
+};
+use crate::capture::{Broadcast, CaptureStats, GpsDataDecoded};
+use crate::gps::format_utc;
+use crate::gpsd::parse_iso_utc;
+use crate::sinks::unescape_text_field;
+use crate::sqlite;
+use crate::tagged_params::tagged_params_ws;
+use crate::types::packet_types;
+
+// A sighting read back from a log for the offline exports. Only what every log layout can provide.
+pub struct LoggedSighting {
+    pub packet_type: String, // BEACON or PROBE_REQUEST
+    pub ssid: String,
+    pub mac: String,
+    pub position: Option<(f64, f64)>, // Latitude, longitude. None if the sighting had no usable fix
+    pub altitude: Option<f64>,
+    pub hdop: Option<f64>,
+    pub signal_dbm: Option<i32>,
+    pub channel: Option<u32>,
+    pub seen: SystemTime,
+    pub auth_mode: Option<String> // WiGLE style, e.g. [WPA2-PSK-CCMP][ESS]. Only known for beacons read from a database
+}
+
+fn is_mac_address(field: &str) -> bool {
+    field.len() == 17 && field.split(':').all(|octet| octet.len() == 2 && octet.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+// hhmmss.ss from the older logs on the date the log was last written
+fn time_of_day_on(hhmmss: f64, date: SystemTime) -> SystemTime {
+    let hhmmss = hhmmss as u64;
+    let seconds_of_day = hhmmss / 10_000 * 3_600 + hhmmss / 100 % 100 * 60 + hhmmss % 100;
+
+    let day = date.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 86_400;
+
+    return UNIX_EPOCH + Duration::from_secs(day * 86_400 + seconds_of_day);
+}
+
+// One line of logged_packets.txt. The columns after the MAC address are either the original
+// lat, lon, time
+// or the current
+// lat, lon, signal, noise, channel, GPS UTC, position method, fix quality, satellites, hdop, pdop, host UTC, host monotonic, altitude,
+//     speed, heading
+fn parse_text_line(line: &str, log_date: SystemTime) -> Option<LoggedSighting> {
+    let fields: Vec<&str> = line.split('\t').collect();
+
+    // Older logs didnt escape tabs in SSIDs, so the SSID is everything up to the MAC address
+    let mac_index = (2..fields.len()).find(|&index| is_mac_address(fields[index]))?;
+    let ssid = String::from_utf8_lossy(&unescape_text_field(&fields[1..mac_index].join("\t"))).to_string();
+    let rest = &fields[mac_index + 1..];
+
+    let current = match rest.len() {
+        3 => false,
+        16 => true,
+        _ => return None
+    };
+
+    let position = match (rest[0].parse::<f64>(), rest[1].parse::<f64>()) {
+        // The original layout had no other sign of there being no fix
+        (Ok(lat), Ok(lon)) if !current && lat == 0.0 && lon == 0.0 => None,
+        (Ok(lat), Ok(lon)) => Some((lat, lon)),
+        _ => None
+    };
+
+    let seen = if current {
+        // The GPS time is empty if the receiver hadnt sent a date yet, in which case the host time is the next best thing
+        let time = if rest[5].is_empty() { rest[11] } else { rest[5] };
+
+        UNIX_EPOCH + Duration::from_secs_f64(parse_iso_utc(time)?)
+    } else {
+        time_of_day_on(rest[2].parse().ok()?, log_date)
+    };
+
+    Some(LoggedSighting {
+        packet_type: fields[0].to_string(),
+        ssid: ssid,
+        mac: fields[mac_index].to_string(),
+        position: position,
+        altitude: if current { rest[13].parse().ok() } else { None },
+        hdop: if current { rest[9].parse().ok() } else { None },
+        signal_dbm: if current { rest[2].parse().ok() } else { None },
+        channel: if current { rest[4].parse().ok() } else { None },
+        seen: seen,
+        auth_mode: None
+    })
+}
+
+// Lines that cant be understood are skipped
+pub fn read_text_log(path: &Path) -> Result<Vec<LoggedSighting>, String> {
+    let log_file = File::open(path).map_err(|e| format!("Unable to open {}: {}", path.display(), e))?;
+    let log_date = fs::metadata(path).and_then(|metadata| metadata.modified()).unwrap_or(SystemTime::now());
+
+    let mut sightings: Vec<LoggedSighting> = Vec::new();
+
+    for line in BufReader::new(log_file).lines() {
+        let line = line.map_err(|e| e.to_string())?;
+
+        if let Some(sighting) = parse_text_line(&line, log_date) {
+            sightings.push(sighting);
+        }
+    }
+
+    return Ok(sightings);
+}
+
+// A .sqlite / .db file is read as a database, anything else as a text log. session_id picks the session from a database and
+// defaults to the latest one.
+pub fn read_session(path: &Path, session_id: Option<i64>) -> Result<Vec<LoggedSighting>, String> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("sqlite" | "sqlite3" | "db") => sqlite::read_session(path, session_id).map_err(|e| format!("Unable to read {}: {}", path.display(), e)),
+        _ => read_text_log(path)
+    }
+}
+
+// What the stats command prints about a log
+pub fn print_stats(sightings: &[LoggedSighting]) {
+    let beacons = sightings.iter().filter(|sighting| sighting.packet_type == "BEACON").count();
+    let probe_requests = sightings.iter().filter(|sighting| sighting.packet_type == "PROBE_REQUEST").count();
+    let with_position = sightings.iter().filter(|sighting| sighting.position.is_some()).count();
+    let transmitters: HashSet<&str> = sightings.iter().map(|sighting| sighting.mac.as_str()).collect();
+    let ssids: HashSet<&str> = sightings.iter().map(|sighting| sighting.ssid.as_str()).collect();
+
+    println!("{} sightings ({} beacons, {} probe requests), {} with a position", sightings.len(), beacons, probe_requests, with_position);
+    println!("{} transmitters and {} SSIDs", transmitters.len(), ssids.len());
+
+    if let (Some(first), Some(last)) = (sightings.iter().map(|sighting| sighting.seen).min(), sightings.iter().map(|sighting| sighting.seen).max()) {
+        println!("First seen {}, last seen {}", format_utc(first), format_utc(last));
+    }
+
+    let mut channels: BTreeMap<u32, usize> = BTreeMap::new();
+
+    for channel in sightings.iter().filter_map(|sighting| sighting.channel) {
+        *channels.entry(channel).or_default() += 1;
+    }
+
+    for (channel, count) in channels {
+        println!("Channel {:>3}: {}", channel, count);
+    }
+}
+
+// Counted by the writer as sightings go to the sinks
+#[derive(Default)]
+pub struct SessionTally {
+    pub sightings: u64,
+    pub beacons: u64,
+    pub probe_requests: u64,
+    pub with_position: u64,
+    transmitters: HashSet<[u8; 6]>,
+    ssids: HashSet<Vec<u8>>
+}
+
+impl SessionTally {
+    pub fn add(&mut self, broadcast: &Broadcast, gps_data: &GpsDataDecoded) {
+        self.sightings += 1;
+
+        match broadcast.packet_type {
+            packet_types::BEACON => self.beacons += 1,
+            packet_types::PROBE_REQUEST => self.probe_requests += 1,
+            _ => {}
+        }
+
+        if gps_data.has_position {
+            self.with_position += 1;
+        }
+
+        self.transmitters.insert(broadcast.transmitter_mac_address);
+
+        if let Some(ssid) = broadcast.found_tags.get(&tagged_params_ws::SSID) {
+            self.ssids.insert(ssid.clone());
+        }
+    }
+}
+
+// Written at the end of every capture so a run can be checked without opening the logs
+#[derive(Serialize)]
+pub struct SessionSummary {
+    pub source: String,
+    pub started_at: String,
+    pub ended_at: String,
+    pub duration_seconds: f64,
+    pub ended_by: String, // "capture finished" or "signal"
+    pub sightings: u64,
+    pub beacons: u64,
+    pub probe_requests: u64,
+    pub with_position: u64,
+    pub transmitters: usize,
+    pub ssids: usize,
+    pub discarded_frames: u64, // Malformed frames skipped by the capture thread
+    pub dropped_sightings: u64, // Thrown away by the pipeline drop policy
+    pub pcap_received: Option<u32>, // libpcap counters. Only for a live capture
+    pub pcap_dropped: Option<u32>,
+    pub pcap_interface_dropped: Option<u32>
+}
+
+impl SessionSummary {
+    pub fn new(source: &str, started_at: SystemTime, ended_by: &str, tally: &SessionTally, discarded_frames: u64, dropped_sightings: u64, capture_stats: Option<&CaptureStats>) -> SessionSummary {
+        let ended_at = SystemTime::now();
+
+        SessionSummary {
+            source: source.to_string(),
+            started_at: format_utc(started_at),
+            ended_at: format_utc(ended_at),
+            duration_seconds: ended_at.duration_since(started_at).unwrap_or_default().as_secs_f64(),
+            ended_by: ended_by.to_string(),
+            sightings: tally.sightings,
+            beacons: tally.beacons,
+            probe_requests: tally.probe_requests,
+            with_position: tally.with_position,
+            transmitters: tally.transmitters.len(),
+            ssids: tally.ssids.len(),
+            discarded_frames: discarded_frames,
+            dropped_sightings: dropped_sightings,
+            pcap_received: capture_stats.map(|stats| stats.received),
+            pcap_dropped: capture_stats.map(|stats| stats.dropped),
+            pcap_interface_dropped: capture_stats.map(|stats| stats.interface_dropped)
+        }
+    }
+
+    pub fn print(&self) {
+        println!("\nSession on {} ended by {} after {:.0} seconds", self.source, self.ended_by, self.duration_seconds);
+        println!("Logged {} sightings ({} beacons, {} probe requests), {} with a position", self.sightings, self.beacons, self.probe_requests, self.with_position);
+        println!("Saw {} transmitters and {} SSIDs", self.transmitters, self.ssids);
+        println!("Discarded {} malformed frames and dropped {} sightings", self.discarded_frames, self.dropped_sightings);
+
+        if let (Some(received), Some(dropped), Some(interface_dropped)) = (self.pcap_received, self.pcap_dropped, self.pcap_interface_dropped) {
+            println!("libpcap received {} packets, dropped {} and the interface dropped {}", received, dropped, interface_dropped);
+        }
+    }
+
+    // One JSON object per line, so every session is kept
+    pub fn append_to(&self, path: &Path) -> Result<(), String> {
+        let mut file = OpenOptions::new().append(true).create(true).open(path).map_err(|e| e.to_string())?;
+        let line = serde_json::to_string(self).map_err(|e| e.to_string())?;
+
+        writeln!(file, "{}", line).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-05-01T00:00:00Z
+    const LOG_DAY: u64 = 1_714_521_600;
+
+    fn log_date() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(LOG_DAY + 20 * 3_600)
+    }
+
+    fn current_line(ssid: &str, lat: &str, lon: &str, gps_utc: &str) -> String {
+        let fields = [
+            "BEACON", ssid, "aa:bb:cc:dd:ee:ff", lat, lon, "-60", "-95", "6", gps_utc, "Measured", "1", "8", "0.9", "1.5",
+            "2024-05-01T17:03:10.000Z", "12345.678", "545.4", "1.2", "84.4"
+        ];
+
+        return fields.join("\t");
+    }
+
+    #[test]
+    fn original_layout() {
+        let sighting = parse_text_line("PROBE_REQUEST\tHome\taa:bb:cc:dd:ee:ff\t48.117300\t11.516667\t170309", log_date()).unwrap();
+
+        assert_eq!(sighting.packet_type, "PROBE_REQUEST");
+        assert_eq!(sighting.ssid, "Home");
+        assert_eq!(sighting.mac, "aa:bb:cc:dd:ee:ff");
+        assert_eq!(sighting.position, Some((48.1173, 11.516667)));
+        assert_eq!(sighting.seen, UNIX_EPOCH + Duration::from_secs(LOG_DAY + 17 * 3_600 + 3 * 60 + 9));
+        assert_eq!((sighting.signal_dbm, sighting.channel, sighting.hdop, sighting.altitude), (None, None, None, None));
+    }
+
+    #[test]
+    fn original_layout_without_a_fix() {
+        let sighting = parse_text_line("BEACON\tHome\taa:bb:cc:dd:ee:ff\t0.000000\t0.000000\t170309", log_date()).unwrap();
+
+        assert_eq!(sighting.position, None);
+    }
+
+    #[test]
+    fn unescaped_tabs_are_part_of_the_ssid() {
+        let sighting = parse_text_line("BEACON\tMy\tNet\taa:bb:cc:dd:ee:ff\t48.117300\t11.516667\t170309", log_date()).unwrap();
+        assert_eq!(sighting.ssid, "My\tNet");
+        assert_eq!(sighting.mac, "aa:bb:cc:dd:ee:ff");
+
+        // The current layout escapes them
+        let sighting = parse_text_line(&current_line("My\\tNet", "48.117300", "11.516667", "2024-05-01T17:03:09.250Z"), log_date()).unwrap();
+        assert_eq!(sighting.ssid, "My\tNet");
+    }
+
+    #[test]
+    fn current_layout() {
+        let sighting = parse_text_line(&current_line("Home", "48.117300", "11.516667", "2024-05-01T17:03:09.250Z"), log_date()).unwrap();
+
+        assert_eq!(sighting.packet_type, "BEACON");
+        assert_eq!(sighting.ssid, "Home");
+        assert_eq!(sighting.position, Some((48.1173, 11.516667)));
+        assert_eq!(sighting.signal_dbm, Some(-60));
+        assert_eq!(sighting.channel, Some(6));
+        assert_eq!(sighting.hdop, Some(0.9));
+        assert_eq!(sighting.altitude, Some(545.4));
+        assert_eq!(sighting.seen, UNIX_EPOCH + Duration::from_millis(LOG_DAY * 1_000 + 61_389_250));
+    }
+
+    #[test]
+    fn current_layout_without_a_position_or_gps_time() {
+        let sighting = parse_text_line(&current_line("Home", "", "", ""), log_date()).unwrap();
+
+        assert_eq!(sighting.position, None);
+        // The host time instead
+        assert_eq!(sighting.seen, UNIX_EPOCH + Duration::from_secs(LOG_DAY + 17 * 3_600 + 3 * 60 + 10));
+
+        // 0, 0 is a real position once there are empty fields for no fix
+        let sighting = parse_text_line(&current_line("Home", "0.000000", "0.000000", ""), log_date()).unwrap();
+        assert_eq!(sighting.position, Some((0.0, 0.0)));
+    }
+
+    #[test]
+    fn other_layouts_are_skipped() {
+        assert!(parse_text_line("BEACON\tHome\taa:bb:cc:dd:ee:ff\t48.117300\t11.516667\t-60\t-95\t170309", log_date()).is_none());
+        assert!(parse_text_line("BEACON\tHome\t48.117300\t11.516667\t170309", log_date()).is_none());
+        assert!(parse_text_line("", log_date()).is_none());
+    }
+}
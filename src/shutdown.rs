@@ -0,0 +1,54 @@
+use std::{
+    io::Error as io_error,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst}
+};
+
+// Signals received so far. The first asks every thread to wind down, the second exits straight away.
+static SIGNALS: AtomicUsize = AtomicUsize::new(0);
+// Set by request(). Kept apart from SIGNALS so a single Ctrl-C after the capture ended by itself doesnt exit straight away
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+const FORCED_EXIT_MESSAGE: &[u8] = b"\nSecond signal received, exiting without flushing.\n";
+
+// Only async signal safe calls in here. https://man7.org/linux/man-pages/man7/signal-safety.7.html
+extern "C" fn handle_signal(_signal: libc::c_int) {
+    if SIGNALS.fetch_add(1, SeqCst) >= 1 {
+        unsafe {
+            libc::write(libc::STDERR_FILENO, FORCED_EXIT_MESSAGE.as_ptr() as *const libc::c_void, FORCED_EXIT_MESSAGE.len());
+            libc::_exit(130);
+        }
+    }
+}
+
+// Handles SIGINT (Ctrl-C) and SIGTERM (systemd stop)
+pub fn install() -> Result<(), io_error> {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_signal as *const () as libc::sighandler_t;
+            // Blocking reads carry on rather than failing with EINTR. Everything polls requested() instead.
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+
+            if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+                return Err(io_error::last_os_error());
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+pub fn requested() -> bool {
+    signalled() || STOP_REQUESTED.load(SeqCst)
+}
+
+// Whether a signal rather than request() started the shutdown
+pub fn signalled() -> bool {
+    SIGNALS.load(SeqCst) > 0
+}
+
+// Winds everything down the same way a signal would, for when the capture ends by itself
+pub fn request() {
+    STOP_REQUESTED.store(true, SeqCst);
+}
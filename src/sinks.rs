@@ -0,0 +1,600 @@
+use serde::{Serialize, Deserialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+    io::{Write, BufWriter}
+};
+use crate::capture::{self, Broadcast, GpsDataDecoded};
+use crate::gps::format_utc;
+use crate::pcapng::{PcapngWriter, linktypes};
+use crate::sqlite::SqliteDatabase;
+use crate::tagged_params::tagged_params_ws;
+use crate::types;
+use crate::value_to_type;
+use crate::wigle;
+
+// Every enabled sink gets the same batches from the writer thread. Relative paths are from the output directory.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum SinkConfig {
+    Text {path: String}, // Tab separated, no header
+    Csv {path: String},
+    Jsonl {path: String}, // One JSON object per sighting
+    Sqlite {path: String},
+    Wigle {path: String}, // Beacons with a position in the WiGLE upload format
+    Pcapng {
+        directory: String,
+        #[serde(default = "default_rotate_megabytes")]
+        rotate_megabytes: u64
+    }
+}
+
+fn default_rotate_megabytes() -> u64 {
+    64
+}
+
+pub fn default_sinks() -> Vec<SinkConfig> {
+    vec![SinkConfig::Text {path: "logged_packets.txt".to_string()}]
+}
+
+// open is called once before the first batch and close once after the last. rotate starts a new file (or the equivalent)
+// without losing anything already written.
+pub trait OutputSink: Send {
+    fn name(&self) -> String;
+    fn open(&mut self, source: &str) -> Result<(), String>;
+    fn write_batch(&mut self, batch: &[(Broadcast, GpsDataDecoded)]) -> Result<(), String>;
+    fn flush(&mut self) -> Result<(), String>;
+    fn rotate(&mut self) -> Result<(), String>;
+    fn close(&mut self) -> Result<(), String>;
+
+    // Sinks that archive every captured frame also get the ones that arent sightings (Broadcast::archive_only)
+    fn archives_every_frame(&self) -> bool {
+        false
+    }
+}
+
+pub fn new_sink(sink_config: &SinkConfig, base_directory: &Path) -> Box<dyn OutputSink> {
+    match sink_config {
+        SinkConfig::Text {path} => Box::new(TextSink {file: LineFile::new(base_directory.join(path))}),
+        SinkConfig::Csv {path} => Box::new(CsvSink {file: LineFile::new(base_directory.join(path))}),
+        SinkConfig::Jsonl {path} => Box::new(JsonlSink {file: LineFile::new(base_directory.join(path))}),
+        SinkConfig::Wigle {path} => Box::new(WigleSink {file: LineFile::new(base_directory.join(path))}),
+        SinkConfig::Sqlite {path} => Box::new(SqliteSink {path: base_directory.join(path), database: None}),
+        SinkConfig::Pcapng {directory, rotate_megabytes} => Box::new(PcapngSink {
+            directory: base_directory.join(directory),
+            rotate_bytes: rotate_megabytes * 1_000_000,
+            writer: None
+        })
+    }
+}
+
+const NOT_OPEN: &str = "Sink has not been opened";
+
+// Values the receiver or adapter didnt report are left empty in the log
+fn optional_to_string<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn optional_float_to_string(value: Option<f64>, decimals: usize) -> String {
+    value.map(|value| format!("{:.*}", decimals, value)).unwrap_or_default()
+}
+
+const COLUMNS: &[&str] = &[
+    "type", "ssid", "mac", "lat", "lon", "signal_dbm", "noise_dbm", "channel", "gps_time", "position_method", "fix_quality", "satellites",
+    "hdop", "pdop", "host_time", "host_monotonic", "altitude", "speed", "heading"
+];
+
+// Shared by the text and CSV sinks. Lines up with COLUMNS.
+fn row(broadcast: &Broadcast, gps_data: &GpsDataDecoded) -> Vec<String> {
+    // The capture thread drops frames without an SSID or with a hidden one, so this is never empty
+    let ssid = broadcast.found_tags.get(&tagged_params_ws::SSID).map(|ssid| ssid.as_slice()).unwrap_or_default();
+
+    // Latitude and longitude are left empty rather than stamped with a stale or invalid fix
+    let (lat, lon) = if gps_data.has_position {
+        (format!("{:.6}", gps_data.lat), format!("{:.6}", gps_data.lon))
+    } else {
+        (String::new(), String::new())
+    };
+
+    vec![
+        value_to_type!(broadcast.packet_type).to_string(),
+        String::from_utf8_lossy(ssid).to_string(),
+        capture::mac_address_to_string(&broadcast.transmitter_mac_address),
+        lat,
+        lon,
+        optional_to_string(broadcast.radiotap.antenna_signal),
+        optional_to_string(broadcast.radiotap.antenna_noise),
+        optional_to_string(broadcast.channel),
+        // Host times are logged alongside the GPS time so clock skew between the two can be found
+        gps_data.utc.map(format_utc).unwrap_or_default(),
+        gps_data.position_method.as_str().to_string(),
+        optional_to_string(gps_data.fix_quality),
+        optional_to_string(gps_data.satellites_used),
+        optional_to_string(gps_data.hdop),
+        optional_to_string(gps_data.pdop),
+        format_utc(broadcast.received_at),
+        format!("{:.6}", broadcast.received_monotonic.as_secs_f64()),
+        optional_float_to_string(gps_data.altitude, 1),
+        optional_float_to_string(gps_data.speed, 2),
+        optional_float_to_string(gps_data.course, 1)
+    ]
+}
+
+// An append only file shared by the line based sinks. Rotating renames the current file with the time and starts a new one.
+struct LineFile {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>
+}
+
+impl LineFile {
+    fn new(path: PathBuf) -> LineFile {
+        LineFile {path: path, writer: None}
+    }
+
+    // Returns whether the file was empty so a header can be written
+    fn open(&mut self) -> Result<bool, String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+
+        let file = OpenOptions::new().append(true).create(true).open(&self.path).map_err(|e| e.to_string())?;
+        let empty = file.metadata().map(|metadata| metadata.len() == 0).unwrap_or(false);
+
+        self.writer = Some(BufWriter::new(file));
+
+        return Ok(empty);
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        let writer = self.writer.as_mut().ok_or(NOT_OPEN)?;
+
+        writeln!(writer, "{}", line).map_err(|e| e.to_string())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        match &mut self.writer {
+            Some(writer) => writer.flush().map_err(|e| e.to_string()),
+            None => Ok(())
+        }
+    }
+
+    // logged_packets.txt becomes logged_packets_<seconds since the unix epoch>.txt
+    fn rotate(&mut self) -> Result<bool, String> {
+        self.flush()?;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let stem = self.path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+
+        let rotated_name = match self.path.extension() {
+            Some(extension) => format!("{}_{}.{}", stem, now, extension.to_string_lossy()),
+            None => format!("{}_{}", stem, now)
+        };
+
+        // The old file stays open and keeps being written to if it cant be renamed
+        fs::rename(&self.path, self.path.with_file_name(rotated_name)).map_err(|e| e.to_string())?;
+
+        return self.open();
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.flush()?;
+        self.writer = None;
+
+        return Ok(());
+    }
+}
+
+// SSIDs can contain anything, so tabs and newlines are escaped to keep one sighting per line and the columns lined up.
+// Bytes that arent UTF-8 are written as \xHH rather than being lost.
+pub fn escape_text_field(field: &[u8]) -> String {
+    let mut escaped = String::with_capacity(field.len());
+
+    for chunk in field.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\t' => escaped.push_str("\\t"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                c => escaped.push(c)
+            }
+        }
+
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+
+    return escaped;
+}
+
+pub fn unescape_text_field(field: &str) -> Vec<u8> {
+    let mut unescaped: Vec<u8> = Vec::with_capacity(field.len());
+    let mut chars = field.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => unescaped.push(b'\t'),
+            Some('n') => unescaped.push(b'\n'),
+            Some('r') => unescaped.push(b'\r'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) => unescaped.push(byte),
+                    Err(_e) => unescaped.extend_from_slice(format!("\\x{}", hex).as_bytes())
+                }
+            },
+            Some(other) => unescaped.extend_from_slice(other.encode_utf8(&mut [0; 4]).as_bytes()),
+            None => unescaped.push(b'\\')
+        }
+    }
+
+    return unescaped;
+}
+
+pub struct TextSink {
+    file: LineFile
+}
+
+impl OutputSink for TextSink {
+    fn name(&self) -> String {
+        format!("text log {}", self.file.path.display())
+    }
+
+    fn open(&mut self, _source: &str) -> Result<(), String> {
+        self.file.open().map(|_empty| ())
+    }
+
+    fn write_batch(&mut self, batch: &[(Broadcast, GpsDataDecoded)]) -> Result<(), String> {
+        for (broadcast, gps_data) in batch {
+            let ssid = broadcast.found_tags.get(&tagged_params_ws::SSID).map(|ssid| ssid.as_slice()).unwrap_or_default();
+
+            let mut fields = row(broadcast, gps_data);
+            fields[1] = escape_text_field(ssid);
+
+            self.file.write_line(&fields.join("\t"))?;
+        }
+
+        return Ok(());
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.file.flush()
+    }
+
+    fn rotate(&mut self) -> Result<(), String> {
+        self.file.rotate().map(|_empty| ())
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.file.close()
+    }
+}
+
+pub struct CsvSink {
+    file: LineFile
+}
+
+// https://www.rfc-editor.org/rfc/rfc4180
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl CsvSink {
+    fn write_header_if(&mut self, empty: bool) -> Result<(), String> {
+        if empty {
+            self.file.write_line(&COLUMNS.join(","))?;
+        }
+
+        return Ok(());
+    }
+}
+
+impl OutputSink for CsvSink {
+    fn name(&self) -> String {
+        format!("CSV {}", self.file.path.display())
+    }
+
+    fn open(&mut self, _source: &str) -> Result<(), String> {
+        let empty = self.file.open()?;
+
+        self.write_header_if(empty)
+    }
+
+    fn write_batch(&mut self, batch: &[(Broadcast, GpsDataDecoded)]) -> Result<(), String> {
+        for (broadcast, gps_data) in batch {
+            let fields: Vec<String> = row(broadcast, gps_data).iter().map(|field| csv_field(field)).collect();
+
+            self.file.write_line(&fields.join(","))?;
+        }
+
+        return Ok(());
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.file.flush()
+    }
+
+    fn rotate(&mut self) -> Result<(), String> {
+        let empty = self.file.rotate()?;
+
+        self.write_header_if(empty)
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.file.close()
+    }
+}
+
+pub struct WigleSink {
+    file: LineFile
+}
+
+impl WigleSink {
+    fn write_header_if(&mut self, empty: bool) -> Result<(), String> {
+        if empty {
+            self.file.write_line(&wigle::pre_header())?;
+            self.file.write_line(wigle::HEADER)?;
+        }
+
+        return Ok(());
+    }
+}
+
+impl OutputSink for WigleSink {
+    fn name(&self) -> String {
+        format!("WiGLE CSV {}", self.file.path.display())
+    }
+
+    fn open(&mut self, _source: &str) -> Result<(), String> {
+        let empty = self.file.open()?;
+
+        self.write_header_if(empty)
+    }
+
+    fn write_batch(&mut self, batch: &[(Broadcast, GpsDataDecoded)]) -> Result<(), String> {
+        for (broadcast, gps_data) in batch {
+            if let Some(row) = wigle::sighting_row(broadcast, gps_data) {
+                self.file.write_line(&row)?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.file.flush()
+    }
+
+    fn rotate(&mut self) -> Result<(), String> {
+        let empty = self.file.rotate()?;
+
+        self.write_header_if(empty)
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.file.close()
+    }
+}
+
+// The frame fields at the top level with the fix under gps
+#[derive(Serialize)]
+struct JsonSighting<'a> {
+    #[serde(flatten)]
+    broadcast: &'a Broadcast,
+    raw_ies: Vec<serde_json::Value>,
+    gps: &'a GpsDataDecoded
+}
+
+pub struct JsonlSink {
+    file: LineFile
+}
+
+impl OutputSink for JsonlSink {
+    fn name(&self) -> String {
+        format!("JSON lines {}", self.file.path.display())
+    }
+
+    fn open(&mut self, _source: &str) -> Result<(), String> {
+        self.file.open().map(|_empty| ())
+    }
+
+    fn write_batch(&mut self, batch: &[(Broadcast, GpsDataDecoded)]) -> Result<(), String> {
+        for (broadcast, gps_data) in batch {
+            let line = serde_json::to_string(&JsonSighting {broadcast: broadcast, raw_ies: broadcast.raw_ies(), gps: gps_data}).map_err(|e| e.to_string())?;
+
+            self.file.write_line(&line)?;
+        }
+
+        return Ok(());
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.file.flush()
+    }
+
+    fn rotate(&mut self) -> Result<(), String> {
+        self.file.rotate().map(|_empty| ())
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.file.close()
+    }
+}
+
+pub struct SqliteSink {
+    path: PathBuf,
+    database: Option<SqliteDatabase>
+}
+
+impl OutputSink for SqliteSink {
+    fn name(&self) -> String {
+        format!("SQLite database {}", self.path.display())
+    }
+
+    fn open(&mut self, source: &str) -> Result<(), String> {
+        self.database = Some(SqliteDatabase::open(&self.path, source).map_err(|e| e.to_string())?);
+
+        return Ok(());
+    }
+
+    // Each batch is committed as it is written so there is nothing to flush
+    fn write_batch(&mut self, batch: &[(Broadcast, GpsDataDecoded)]) -> Result<(), String> {
+        self.database.as_mut().ok_or(NOT_OPEN)?.write_batch(batch).map_err(|e| e.to_string())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    // Sessions already split the database up, so there is nothing to rotate
+    fn rotate(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        match self.database.take() {
+            Some(database) => database.close().map_err(|e| e.to_string()),
+            None => Ok(())
+        }
+    }
+}
+
+pub struct PcapngSink {
+    directory: PathBuf,
+    rotate_bytes: u64,
+    writer: Option<PcapngWriter>
+}
+
+// Stored as the pcapng packet comment so the location stays with the frame when opened in Wireshark
+fn gps_comment(gps_data: &GpsDataDecoded) -> String {
+    let utc = gps_data.utc.map(format_utc).unwrap_or_default();
+
+    if !gps_data.has_position {
+        return format!("no position time={}", utc);
+    }
+
+    format!(
+        "lat={:.6} lon={:.6} method={} time={} quality={} satellites={} hdop={} pdop={} altitude={} speed={} heading={}",
+        gps_data.lat,
+        gps_data.lon,
+        gps_data.position_method.as_str(),
+        utc,
+        optional_to_string(gps_data.fix_quality),
+        optional_to_string(gps_data.satellites_used),
+        optional_to_string(gps_data.hdop),
+        optional_to_string(gps_data.pdop),
+        optional_float_to_string(gps_data.altitude, 1),
+        optional_float_to_string(gps_data.speed, 2),
+        optional_float_to_string(gps_data.course, 1)
+    )
+}
+
+impl OutputSink for PcapngSink {
+    fn name(&self) -> String {
+        format!("pcapng archive {}", self.directory.display())
+    }
+
+    fn archives_every_frame(&self) -> bool {
+        true
+    }
+
+    fn open(&mut self, _source: &str) -> Result<(), String> {
+        self.writer = Some(PcapngWriter::open(self.directory.clone(), self.rotate_bytes).map_err(|e| e.to_string())?);
+
+        return Ok(());
+    }
+
+    fn write_batch(&mut self, batch: &[(Broadcast, GpsDataDecoded)]) -> Result<(), String> {
+        let writer = self.writer.as_mut().ok_or(NOT_OPEN)?;
+
+        for (broadcast, gps_data) in batch {
+            let linktype = if broadcast.radiotap.length > 0 { linktypes::IEEE802_11_RADIOTAP } else { linktypes::IEEE802_11 };
+
+            writer.write_packet(linktype, broadcast.timestamp, broadcast.original_length, &broadcast.raw_packet, &gps_comment(gps_data))
+                .map_err(|e| e.to_string())?;
+        }
+
+        return Ok(());
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        match &mut self.writer {
+            Some(writer) => writer.flush().map_err(|e| e.to_string()),
+            None => Ok(())
+        }
+    }
+
+    fn rotate(&mut self) -> Result<(), String> {
+        self.writer.as_mut().ok_or(NOT_OPEN)?.rotate().map_err(|e| e.to_string())
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        let result = self.flush();
+        self.writer = None;
+
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn temp_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("wifi_logging_{}_{}", process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        return directory;
+    }
+
+    #[test]
+    fn rotation_starts_a_new_file() {
+        let directory = temp_directory("rotate");
+        let mut line_file = LineFile::new(directory.join("log.txt"));
+
+        assert!(line_file.open().unwrap());
+        line_file.write_line("first").unwrap();
+        assert!(line_file.rotate().unwrap());
+        line_file.write_line("second").unwrap();
+        line_file.close().unwrap();
+
+        let mut contents: Vec<String> = fs::read_dir(&directory).unwrap().map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap()).collect();
+        contents.sort();
+
+        assert_eq!(contents, vec!["first\n".to_string(), "second\n".to_string()]);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn failed_rotation_keeps_writing_to_the_old_file() {
+        let directory = temp_directory("failed_rotate");
+        let mut line_file = LineFile::new(directory.join("log.txt"));
+
+        line_file.open().unwrap();
+        line_file.write_line("first").unwrap();
+
+        // Nothing left to rename
+        fs::remove_file(directory.join("log.txt")).unwrap();
+
+        assert!(line_file.rotate().is_err());
+        assert!(line_file.write_line("second").is_ok());
+        assert!(line_file.close().is_ok());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,370 @@
+use rusqlite::{params, Connection, OpenFlags, Transaction, OptionalExtension};
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
+use crate::capture::{self, Broadcast, GpsDataDecoded};
+use crate::session::LoggedSighting;
+use crate::tagged_params::tagged_params_ws;
+use crate::types::{self, packet_types};
+use crate::value_to_type;
+use crate::wigle;
+
+// Each entry moves the schema up one version. PRAGMA user_version holds how many have been applied, so new entries are
+// only ever appended.
+// Times are seconds since the unix epoch. Positions are NULL when the fix didnt meet the thresholds.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE sessions (
+        id INTEGER PRIMARY KEY,
+        started_at REAL NOT NULL,
+        ended_at REAL,
+        source TEXT NOT NULL -- Interface name or the replayed capture file
+    );
+
+    CREATE TABLE devices (
+        id INTEGER PRIMARY KEY,
+        mac TEXT NOT NULL UNIQUE,
+        first_seen REAL NOT NULL,
+        last_seen REAL NOT NULL
+    );
+
+    -- Probe requests dont have a BSSID so they are stored with an empty one
+    CREATE TABLE networks (
+        id INTEGER PRIMARY KEY,
+        ssid BLOB NOT NULL,
+        bssid TEXT NOT NULL,
+        UNIQUE (ssid, bssid)
+    );
+
+    CREATE TABLE sightings (
+        id INTEGER PRIMARY KEY,
+        session_id INTEGER NOT NULL REFERENCES sessions (id),
+        device_id INTEGER NOT NULL REFERENCES devices (id),
+        network_id INTEGER NOT NULL REFERENCES networks (id),
+        packet_type TEXT NOT NULL,
+        frame_time REAL NOT NULL,
+        host_time REAL NOT NULL,
+        host_monotonic REAL NOT NULL,
+        gps_time REAL,
+        lat REAL,
+        lon REAL,
+        altitude REAL,
+        speed REAL,
+        heading REAL,
+        position_method TEXT NOT NULL,
+        fix_quality INTEGER,
+        satellites INTEGER,
+        hdop REAL,
+        pdop REAL,
+        signal_dbm INTEGER,
+        noise_dbm INTEGER,
+        channel INTEGER
+    );
+
+    CREATE INDEX sightings_device ON sightings (device_id);
+    CREATE INDEX sightings_network ON sightings (network_id);
+    CREATE INDEX sightings_frame_time ON sightings (frame_time);
+
+    -- Every information element of the frame in the order it appeared
+    CREATE TABLE raw_ies (
+        sighting_id INTEGER NOT NULL REFERENCES sightings (id),
+        position INTEGER NOT NULL,
+        tag_number INTEGER NOT NULL,
+        value BLOB NOT NULL,
+        PRIMARY KEY (sighting_id, position)
+    );
+    ",
+    "
+    -- Capability information from the fixed parameters of beacons, which the auth mode needs along with the raw IEs.
+    -- NULL for probe requests and sightings written before it was kept.
+    ALTER TABLE sightings ADD COLUMN capability INTEGER;
+    "
+];
+
+fn unix_seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+// Brings the schema up to the latest version in a single transaction
+fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    if version >= MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    let transaction = connection.transaction()?;
+
+    for migration in &MIGRATIONS[version..] {
+        transaction.execute_batch(migration)?;
+    }
+
+    // PRAGMA doesnt take bound parameters
+    transaction.execute_batch(&format!("PRAGMA user_version = {}", MIGRATIONS.len()))?;
+
+    return transaction.commit();
+}
+
+pub struct SqliteDatabase {
+    connection: Connection,
+    session_id: i64
+}
+
+impl SqliteDatabase {
+    // Starts a new session in the database at path, creating or migrating it first
+    pub fn open(path: &Path, source: &str) -> rusqlite::Result<SqliteDatabase> {
+        let mut connection = Connection::open(path)?;
+
+        // WAL lets the analysis notebooks read while the logger is writing
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+
+        migrate(&mut connection)?;
+
+        connection.execute("INSERT INTO sessions (started_at, source) VALUES (?1, ?2)", params![unix_seconds(SystemTime::now()), source])?;
+        let session_id = connection.last_insert_rowid();
+
+        return Ok(SqliteDatabase {connection: connection, session_id: session_id});
+    }
+
+    // The whole batch goes in one transaction, so it is either all written or none of it is
+    pub fn write_batch(&mut self, batch: &[(Broadcast, GpsDataDecoded)]) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+
+        for (broadcast, gps_data) in batch {
+            insert_sighting(&transaction, self.session_id, broadcast, gps_data)?;
+        }
+
+        return transaction.commit();
+    }
+
+    pub fn close(self) -> rusqlite::Result<()> {
+        self.connection.execute("UPDATE sessions SET ended_at = ?1 WHERE id = ?2", params![unix_seconds(SystemTime::now()), self.session_id])?;
+
+        return self.connection.close().map_err(|(_connection, e)| e);
+    }
+}
+
+fn device_id(transaction: &Transaction, mac_address: &str, seen: f64) -> rusqlite::Result<i64> {
+    transaction.prepare_cached(
+        "INSERT INTO devices (mac, first_seen, last_seen) VALUES (?1, ?2, ?2)
+        ON CONFLICT (mac) DO UPDATE SET first_seen = min(first_seen, excluded.first_seen), last_seen = max(last_seen, excluded.last_seen)
+        RETURNING id"
+    )?.query_row(params![mac_address, seen], |row| row.get(0))
+}
+
+fn network_id(transaction: &Transaction, ssid: &[u8], bssid: &str) -> rusqlite::Result<i64> {
+    let existing: Option<i64> = transaction.prepare_cached("SELECT id FROM networks WHERE ssid = ?1 AND bssid = ?2")?
+        .query_row(params![ssid, bssid], |row| row.get(0))
+        .optional()?;
+
+    if let Some(network_id) = existing {
+        return Ok(network_id);
+    }
+
+    transaction.prepare_cached("INSERT INTO networks (ssid, bssid) VALUES (?1, ?2)")?.execute(params![ssid, bssid])?;
+
+    return Ok(transaction.last_insert_rowid());
+}
+
+fn insert_sighting(transaction: &Transaction, session_id: i64, broadcast: &Broadcast, gps_data: &GpsDataDecoded) -> rusqlite::Result<()> {
+    let frame_time = broadcast.timestamp.as_secs_f64();
+    let mac_address = capture::mac_address_to_string(&broadcast.transmitter_mac_address);
+
+    // Beacons are sent by the access point so the transmitter is the BSSID
+    let bssid = if broadcast.packet_type == packet_types::BEACON { mac_address.clone() } else { String::new() };
+    let ssid = broadcast.found_tags.get(&tagged_params_ws::SSID).map(|ssid| ssid.as_slice()).unwrap_or_default();
+
+    let device_id = device_id(transaction, &mac_address, frame_time)?;
+    let network_id = network_id(transaction, ssid, &bssid)?;
+
+    let (lat, lon) = if gps_data.has_position { (Some(gps_data.lat), Some(gps_data.lon)) } else { (None, None) };
+
+    // The capture thread only keeps the tags it was asked for, so the whole frame is parsed again for the capability and
+    // the raw IEs. It already parsed once so this can't fail in practice, but a frame without them is still worth keeping.
+    let parsed_frame = broadcast.parse_raw_packet().ok();
+
+    transaction.prepare_cached(
+        "INSERT INTO sightings (
+            session_id, device_id, network_id, packet_type, frame_time, host_time, host_monotonic, gps_time, lat, lon, altitude, speed,
+            heading, position_method, fix_quality, satellites, hdop, pdop, signal_dbm, noise_dbm, channel, capability
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22)"
+    )?.execute(params![
+        session_id,
+        device_id,
+        network_id,
+        value_to_type!(broadcast.packet_type),
+        frame_time,
+        unix_seconds(broadcast.received_at),
+        broadcast.received_monotonic.as_secs_f64(),
+        gps_data.utc.map(unix_seconds),
+        lat,
+        lon,
+        gps_data.altitude,
+        gps_data.speed,
+        gps_data.course,
+        gps_data.position_method.as_str(),
+        gps_data.fix_quality,
+        gps_data.satellites_used,
+        gps_data.hdop,
+        gps_data.pdop,
+        broadcast.radiotap.antenna_signal,
+        broadcast.radiotap.antenna_noise,
+        broadcast.channel,
+        parsed_frame.as_ref().and_then(|parsed_frame| parsed_frame.capability())
+    ])?;
+
+    let sighting_id = transaction.last_insert_rowid();
+
+    if let Some(parsed_frame) = parsed_frame {
+        let mut insert_ie = transaction.prepare_cached("INSERT INTO raw_ies (sighting_id, position, tag_number, value) VALUES (?1, ?2, ?3, ?4)")?;
+
+        for (position, tag) in parsed_frame.tags().enumerate() {
+            let (tag_number, value) = match tag {
+                Ok(tag) => tag,
+                Err(_e) => break
+            };
+
+            insert_ie.execute(params![sighting_id, position as i64, tag_number, value])?;
+        }
+    }
+
+    return Ok(());
+}
+
+// Worked out from the stored IEs the same way the WiGLE sink does from the frame
+fn read_auth_mode(connection: &Connection, sighting_id: i64, capability: Option<u16>) -> rusqlite::Result<String> {
+    let mut statement = connection.prepare_cached("SELECT tag_number, value FROM raw_ies WHERE sighting_id = ?1 AND tag_number IN (?2, ?3) ORDER BY position")?;
+
+    let ies = statement.query_map(params![sighting_id, tagged_params_ws::RSN_IE, tagged_params_ws::VENDOR_SPECIFIC_IE], |row| Ok((row.get::<_, u8>(0)?, row.get::<_, Vec<u8>>(1)?)))?
+        .collect::<rusqlite::Result<Vec<(u8, Vec<u8>)>>>()?;
+
+    return Ok(wigle::auth_mode_from_ies(capability, ies.iter().map(|(tag_number, value)| (*tag_number, value.as_slice()))));
+}
+
+// Reads the sightings of one session back in the order they were captured. Defaults to the latest session.
+pub fn read_session(path: &Path, session_id: Option<i64>) -> rusqlite::Result<Vec<LoggedSighting>> {
+    // Read only so a database that is being logged to can be exported
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    let session_id: i64 = match session_id {
+        Some(session_id) => session_id,
+        None => connection.query_row("SELECT max(id) FROM sessions", [], |row| row.get::<_, Option<i64>>(0))?.unwrap_or(0)
+    };
+
+    // A read only connection cant migrate, so databases from before the capability column was added go without it
+    let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let capability_column = if version >= 2 { "sightings.capability" } else { "NULL" };
+
+    let mut statement = connection.prepare(&format!(
+        "SELECT sightings.packet_type, networks.ssid, devices.mac, sightings.lat, sightings.lon, sightings.altitude, sightings.hdop,
+            sightings.signal_dbm, sightings.channel, sightings.frame_time, sightings.id, {}
+        FROM sightings
+        JOIN devices ON devices.id = sightings.device_id
+        JOIN networks ON networks.id = sightings.network_id
+        WHERE sightings.session_id = ?1
+        ORDER BY sightings.frame_time",
+        capability_column
+    ))?;
+
+    let sightings = statement.query_map(params![session_id], |row| {
+        let packet_type: String = row.get(0)?;
+        let lat: Option<f64> = row.get(3)?;
+        let lon: Option<f64> = row.get(4)?;
+        let ssid: Vec<u8> = row.get(1)?;
+
+        // Probe requests are sent by clients, which dont have an auth mode
+        let auth_mode = if packet_type == "BEACON" {
+            Some(read_auth_mode(&connection, row.get(10)?, row.get(11)?)?)
+        } else {
+            None
+        };
+
+        Ok(LoggedSighting {
+            packet_type: packet_type,
+            ssid: String::from_utf8_lossy(&ssid).to_string(),
+            mac: row.get(2)?,
+            position: lat.zip(lon),
+            altitude: row.get(5)?,
+            hdop: row.get(6)?,
+            signal_dbm: row.get(7)?,
+            channel: row.get(8)?,
+            seen: UNIX_EPOCH + Duration::from_secs_f64(row.get::<_, f64>(9)?.max(0.0)),
+            auth_mode: auth_mode
+        })
+    })?;
+
+    return sightings.collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, fs, process};
+    use crate::radiotap::RadiotapHeader;
+
+    // A bare beacon with an ESS + privacy capability and an RSN element for WPA2 PSK with CCMP
+    fn wpa2_beacon(timestamp: u64) -> Broadcast {
+        let mut packet = vec![packet_types::BEACON, 0x00, 0x00, 0x00];
+        packet.extend_from_slice(&[0xff; 6]);
+        packet.extend_from_slice(&[0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        packet.extend_from_slice(&[0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        packet.extend_from_slice(&[0x10, 0x00]);
+        packet.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x64, 0x00, 0x11, 0x00]);
+        packet.extend_from_slice(&[0x00, 0x03, b'a', b'b', b'c']);
+        packet.extend_from_slice(&[
+            tagged_params_ws::RSN_IE, 20, 0x01, 0x00, 0x00, 0x0f, 0xac, 0x04, 0x01, 0x00, 0x00, 0x0f, 0xac, 0x04, 0x01, 0x00, 0x00, 0x0f,
+            0xac, 0x02, 0x00, 0x00
+        ]);
+
+        Broadcast {
+            packet_type: packet_types::BEACON,
+            transmitter_mac_address: [0x02, 0x00, 0x00, 0x00, 0x00, 0x01],
+            receiver_mac_address: [0xff; 6],
+            bssid: [0x02, 0x00, 0x00, 0x00, 0x00, 0x01],
+            found_tags: HashMap::from([(tagged_params_ws::SSID, b"abc".to_vec())]),
+            radiotap: RadiotapHeader::default(),
+            channel: Some(6),
+            timestamp: Duration::from_secs(timestamp),
+            received_at: UNIX_EPOCH + Duration::from_secs(timestamp),
+            received_monotonic: Duration::from_secs(1),
+            original_length: packet.len() as u32,
+            raw_packet: packet,
+            archive_only: false
+        }
+    }
+
+    #[test]
+    fn auth_mode_is_read_back_from_the_raw_ies() {
+        let path = std::env::temp_dir().join(format!("wifi_logging_{}_auth_mode.sqlite", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut database = SqliteDatabase::open(&path, "test").unwrap();
+
+        let mut open_beacon = wpa2_beacon(1_714_582_990);
+        // Just the ESS capability and no RSN element
+        open_beacon.raw_packet.truncate(24 + 12 + 5);
+        open_beacon.raw_packet[24 + 10] = 0x01;
+
+        let mut probe_request = wpa2_beacon(1_714_582_991);
+        probe_request.packet_type = packet_types::PROBE_REQUEST;
+
+        database.write_batch(&[
+            (wpa2_beacon(1_714_582_989), GpsDataDecoded::no_fix()),
+            (open_beacon, GpsDataDecoded::no_fix()),
+            (probe_request, GpsDataDecoded::no_fix())
+        ]).unwrap();
+        database.close().unwrap();
+
+        let auth_modes: Vec<Option<String>> = read_session(&path, None).unwrap().into_iter().map(|sighting| sighting.auth_mode).collect();
+
+        assert_eq!(auth_modes, vec![Some("[WPA2-PSK-CCMP][ESS]".to_string()), Some("[ESS]".to_string()), None]);
+
+        // The read only connection leaves the WAL files behind
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
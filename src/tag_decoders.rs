@@ -0,0 +1,111 @@
+use serde_json::{json, Value};
+use crate::tagged_params::tagged_params_ws;
+
+// Decoders for the information elements worth reading in a log. Everything else is written out as hex.
+
+pub const RSN_OUI: [u8; 3] = [0x00, 0x0F, 0xAC];
+pub const WPA_OUI: [u8; 3] = [0x00, 0x50, 0xF2];
+
+pub fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn cipher_name(suite_type: u8) -> &'static str {
+    match suite_type {
+        1 | 5 => "WEP",
+        2 => "TKIP",
+        4 => "CCMP",
+        8 => "GCMP",
+        9 => "GCMP-256",
+        10 => "CCMP-256",
+        _ => "?"
+    }
+}
+
+pub fn akm_name(suite_type: u8) -> &'static str {
+    match suite_type {
+        1 => "EAP",
+        2 => "PSK",
+        3 => "FT/EAP",
+        4 => "FT/PSK",
+        5 => "EAP-SHA256",
+        6 => "PSK-SHA256",
+        8 => "SAE",
+        9 => "FT/SAE",
+        12 => "EAP-SUITE-B-192",
+        18 => "OWE",
+        24 => "SAE-EXT-KEY",
+        _ => "?"
+    }
+}
+
+// RSN and WPA elements share a layout: version, group cipher, pairwise ciphers, AKMs.
+// https://en.wikipedia.org/wiki/IEEE_802.11i-2004
+pub struct SecurityElement {
+    pub version: u16,
+    pub group_cipher: u8,
+    pub pairwise_ciphers: Vec<u8>, // Suite types. Suites from other OUIs are left out
+    pub akms: Vec<u8>
+}
+
+// Reads a suite count followed by that many 4 byte suites. Returns the suite types and the remaining bytes.
+fn suite_list<'a>(data: &'a [u8], oui: &[u8; 3]) -> Option<(Vec<u8>, &'a [u8])> {
+    let count = u16::from_le_bytes(data.get(0..2)?.try_into().ok()?) as usize;
+    let suites = data.get(2..2 + count * 4)?;
+
+    let suite_types = suites.chunks(4).filter(|suite| &suite[0..3] == oui).map(|suite| suite[3]).collect();
+
+    return Some((suite_types, &data[2 + count * 4..]));
+}
+
+// data starts at the version, so the OUI and type of a WPA vendor element have to be skipped first
+pub fn security_element(data: &[u8], oui: &[u8; 3]) -> Option<SecurityElement> {
+    let version = u16::from_le_bytes(data.get(0..2)?.try_into().ok()?);
+    let group_cipher = *data.get(5)?;
+
+    let (pairwise_ciphers, after_ciphers) = suite_list(data.get(6..)?, oui)?;
+    let (akms, _) = suite_list(after_ciphers, oui)?;
+
+    return Some(SecurityElement {version: version, group_cipher: group_cipher, pairwise_ciphers: pairwise_ciphers, akms: akms});
+}
+
+fn security_element_json(security: &SecurityElement, protocol: &str) -> Value {
+    json!({
+        "protocol": protocol,
+        "version": security.version,
+        "group_cipher": cipher_name(security.group_cipher),
+        "pairwise_ciphers": security.pairwise_ciphers.iter().map(|&cipher| cipher_name(cipher)).collect::<Vec<&str>>(),
+        "akms": security.akms.iter().map(|&akm| akm_name(akm)).collect::<Vec<&str>>()
+    })
+}
+
+// None if there is no decoder for the tag or the element doesnt decode
+pub fn decode(tag_number: u8, data: &[u8]) -> Option<Value> {
+    match tag_number {
+        // SSIDs that arent valid UTF-8 are left as hex so nothing is lost
+        tagged_params_ws::SSID => std::str::from_utf8(data).ok().map(|ssid| json!(ssid)),
+        // The top bit marks a basic rate, the rest is the rate in 500 kbps units
+        tagged_params_ws::SUPP_RATES | tagged_params_ws::EXT_SUPP_RATES => Some(json!(
+            data.iter().map(|&rate| json!({"mbps": (rate & 0x7F) as f64 / 2.0, "basic": rate & 0x80 != 0})).collect::<Vec<Value>>()
+        )),
+        tagged_params_ws::DS_PARAMETER => data.first().map(|&channel| json!({"channel": channel})),
+        tagged_params_ws::COUNTRY_INFO => {
+            let country = std::str::from_utf8(data.get(0..2)?).ok()?;
+
+            Some(json!({"country": country, "environment": data.get(2).map(|&environment| (environment as char).to_string())}))
+        },
+        tagged_params_ws::RSN_IE => security_element(data, &RSN_OUI).map(|security| security_element_json(&security, "RSN")),
+        tagged_params_ws::VENDOR_SPECIFIC_IE => {
+            let oui = data.get(0..3)?;
+            let vendor_type = *data.get(3)?;
+
+            // WPA1 is a Microsoft vendor element with OUI type 1
+            if oui == WPA_OUI && vendor_type == 1 {
+                return security_element(&data[4..], &WPA_OUI).map(|security| security_element_json(&security, "WPA"));
+            }
+
+            Some(json!({"oui": hex(oui), "vendor_type": vendor_type, "hex": hex(&data[4..])}))
+        },
+        _ => None
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    fs::File,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+    io::{Write, BufWriter}
+};
+use crate::capture::{self, Broadcast, GpsDataDecoded};
+use crate::gps::format_utc;
+use crate::session::LoggedSighting;
+use crate::sinks::csv_field;
+use crate::tag_decoders::{SecurityElement, RSN_OUI, WPA_OUI, security_element, cipher_name, akm_name};
+use crate::tagged_params::tagged_params_ws;
+
+// https://api.wigle.net/csvFormat.html
+pub const HEADER: &str = "MAC,SSID,AuthMode,FirstSeen,Channel,RSSI,CurrentLatitude,CurrentLongitude,AltitudeMeters,AccuracyMeters,Type";
+
+// Typical GPS user equivalent range error. WiGLE wants an accuracy in meters and HDOP is all there is to go on.
+const METERS_PER_HDOP: f64 = 5.0;
+
+pub fn pre_header() -> String {
+    format!(
+        "WigleWifi-1.4,appRelease={},model=Beacon / Probe Request Logging,release={},device=wifi_logging,display=,board=,brand=",
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+// Capability information bits in the beacon fixed parameters
+const CAPABILITY_ESS: u16 = 0x0001;
+const CAPABILITY_IBSS: u16 = 0x0002;
+const CAPABILITY_PRIVACY: u16 = 0x0010;
+
+// e.g. [WPA2-PSK-CCMP]
+fn security_label(security: &SecurityElement, wpa: bool) -> String {
+    let protocol = if wpa {
+        "WPA"
+    } else if !security.akms.is_empty() && security.akms.iter().all(|&akm| matches!(akm, 8 | 9 | 12 | 18 | 24)) {
+        "WPA3"
+    } else {
+        "WPA2"
+    };
+
+    let akms: Vec<&str> = security.akms.iter().map(|&akm| akm_name(akm)).collect();
+    let ciphers: Vec<&str> = security.pairwise_ciphers.iter().map(|&cipher| cipher_name(cipher)).collect();
+
+    return format!("[{}-{}-{}]", protocol, akms.join("+"), ciphers.join("+"));
+}
+
+// e.g. [WPA2-PSK-CCMP][ESS], in the same style as Android and so the WiGLE app
+pub fn auth_mode(broadcast: &Broadcast) -> String {
+    let parsed_frame = match broadcast.parse_raw_packet() {
+        Ok(parsed_frame) => parsed_frame,
+        Err(_e) => return String::new()
+    };
+
+    return auth_mode_from_ies(parsed_frame.capability(), parsed_frame.tags().map_while(|tag| tag.ok()));
+}
+
+// The same from the capability information and information elements stored in a log. Only the RSN and vendor specific
+// elements are looked at. Without the capability there is no [WEP], [ESS] or [IBSS].
+pub fn auth_mode_from_ies<'a, I: IntoIterator<Item = (u8, &'a [u8])>>(capability: Option<u16>, ies: I) -> String {
+    let mut auth_mode = String::new();
+
+    for (tag_number, tag_data) in ies {
+        let security = match tag_number {
+            tagged_params_ws::RSN_IE => security_element(tag_data, &RSN_OUI).map(|security| security_label(&security, false)),
+            // WPA1 is a Microsoft vendor element with OUI type 1
+            tagged_params_ws::VENDOR_SPECIFIC_IE if tag_data.starts_with(&WPA_OUI) && tag_data.get(3) == Some(&1) => {
+                security_element(&tag_data[4..], &WPA_OUI).map(|security| security_label(&security, true))
+            },
+            _ => None
+        };
+
+        if let Some(security) = security {
+            auth_mode.push_str(&security);
+        }
+    }
+
+    let capability = capability.unwrap_or(0);
+
+    if auth_mode.is_empty() && capability & CAPABILITY_PRIVACY != 0 {
+        auth_mode.push_str("[WEP]");
+    }
+
+    if capability & CAPABILITY_ESS != 0 {
+        auth_mode.push_str("[ESS]");
+    }
+
+    if capability & CAPABILITY_IBSS != 0 {
+        auth_mode.push_str("[IBSS]");
+    }
+
+    return auth_mode;
+}
+
+// 2024-05-01 17:03:09
+fn first_seen(time: SystemTime) -> String {
+    format_utc(time)[..19].replace('T', " ")
+}
+
+fn optional_to_string<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn row(mac: &str, ssid: &str, auth_mode: &str, seen: SystemTime, channel: &str, rssi: &str, lat: f64, lon: f64, altitude: &str, accuracy: &str) -> String {
+    let fields = [mac, ssid, auth_mode, &first_seen(seen), channel, rssi, &format!("{:.6}", lat), &format!("{:.6}", lon), altitude, accuracy, "WIFI"];
+    let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+
+    return fields.join(",");
+}
+
+// None for probe requests and sightings without a position, which WiGLE has no use for
+pub fn sighting_row(broadcast: &Broadcast, gps_data: &GpsDataDecoded) -> Option<String> {
+    if broadcast.packet_type != crate::types::packet_types::BEACON || !gps_data.has_position {
+        return None;
+    }
+
+    let ssid = broadcast.found_tags.get(&tagged_params_ws::SSID).map(|ssid| ssid.as_slice()).unwrap_or_default();
+
+    Some(row(
+        &capture::mac_address_to_string(&broadcast.transmitter_mac_address).to_uppercase(),
+        &String::from_utf8_lossy(ssid),
+        &auth_mode(broadcast),
+        UNIX_EPOCH + broadcast.timestamp,
+        &optional_to_string(broadcast.channel),
+        &optional_to_string(broadcast.radiotap.antenna_signal),
+        gps_data.lat,
+        gps_data.lon,
+        &gps_data.altitude.map(|altitude| format!("{:.1}", altitude)).unwrap_or_default(),
+        &gps_data.hdop.map(|hdop| format!("{:.1}", hdop * METERS_PER_HDOP)).unwrap_or_default()
+    ))
+}
+
+// Writes the logged beacons with a position as a WiGLE CSV. Returns how many were written.
+pub fn export(sightings: &[LoggedSighting], output: &Path) -> Result<usize, String> {
+    let mut writer = BufWriter::new(File::create(output).map_err(|e| format!("Unable to create {}: {}", output.display(), e))?);
+    writeln!(writer, "{}\n{}", pre_header(), HEADER).map_err(|e| e.to_string())?;
+
+    let mut written = 0;
+
+    for sighting in sightings.iter().filter(|sighting| sighting.packet_type == "BEACON") {
+        let (lat, lon) = match sighting.position {
+            Some(position) => position,
+            None => continue
+        };
+
+        // Only the SQLite database keeps the information elements, so it is left empty for the other logs
+        let row = row(
+            &sighting.mac.to_uppercase(),
+            &sighting.ssid,
+            sighting.auth_mode.as_deref().unwrap_or_default(),
+            sighting.seen,
+            &optional_to_string(sighting.channel),
+            &optional_to_string(sighting.signal_dbm),
+            lat,
+            lon,
+            &sighting.altitude.map(|altitude| format!("{:.1}", altitude)).unwrap_or_default(),
+            &sighting.hdop.map(|hdop| format!("{:.1}", hdop * METERS_PER_HDOP)).unwrap_or_default()
+        );
+
+        writeln!(writer, "{}", row).map_err(|e| e.to_string())?;
+        written += 1;
+    }
+
+    writer.flush().map_err(|e| e.to_string())?;
+
+    return Ok(written);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, process};
+
+    // Body of an RSN element, or of a WPA one after its OUI and type, with CCMP as the group and pairwise cipher
+    fn security_body(oui: &[u8; 3], akms: &[u8]) -> Vec<u8> {
+        let mut body = vec![0x01, 0x00];
+        body.extend_from_slice(oui);
+        body.push(4);
+        body.extend_from_slice(&[0x01, 0x00]);
+        body.extend_from_slice(oui);
+        body.push(4);
+        body.extend_from_slice(&(akms.len() as u16).to_le_bytes());
+
+        for &akm in akms {
+            body.extend_from_slice(oui);
+            body.push(akm);
+        }
+
+        return body;
+    }
+
+    fn rsn(akms: &[u8]) -> (u8, Vec<u8>) {
+        (tagged_params_ws::RSN_IE, security_body(&RSN_OUI, akms))
+    }
+
+    fn wpa(akms: &[u8]) -> (u8, Vec<u8>) {
+        let mut data = WPA_OUI.to_vec();
+        data.push(1);
+        data.extend(security_body(&WPA_OUI, akms));
+
+        (tagged_params_ws::VENDOR_SPECIFIC_IE, data)
+    }
+
+    fn auth_mode_of(capability: Option<u16>, ies: &[(u8, Vec<u8>)]) -> String {
+        auth_mode_from_ies(capability, ies.iter().map(|(tag_number, tag_data)| (*tag_number, tag_data.as_slice())))
+    }
+
+    #[test]
+    fn wpa3_is_sae_only() {
+        assert_eq!(auth_mode_of(Some(CAPABILITY_ESS | CAPABILITY_PRIVACY), &[rsn(&[8])]), "[WPA3-SAE-CCMP][ESS]");
+        assert_eq!(auth_mode_of(Some(CAPABILITY_ESS), &[rsn(&[8, 24])]), "[WPA3-SAE+SAE-EXT-KEY-CCMP][ESS]");
+
+        // Transition mode still lets WPA2 clients in
+        assert_eq!(auth_mode_of(Some(CAPABILITY_ESS), &[rsn(&[2, 8])]), "[WPA2-PSK+SAE-CCMP][ESS]");
+        assert_eq!(auth_mode_of(Some(CAPABILITY_ESS), &[rsn(&[2])]), "[WPA2-PSK-CCMP][ESS]");
+    }
+
+    #[test]
+    fn wpa_and_rsn_are_both_listed() {
+        assert_eq!(auth_mode_of(Some(CAPABILITY_ESS), &[wpa(&[2]), rsn(&[2])]), "[WPA-PSK-CCMP][WPA2-PSK-CCMP][ESS]");
+
+        // A vendor element that isnt WPA is ignored
+        let mut wps = wpa(&[2]);
+        wps.1[3] = 4;
+        assert_eq!(auth_mode_of(Some(CAPABILITY_ESS), &[wps]), "[ESS]");
+    }
+
+    #[test]
+    fn privacy_without_rsn_is_wep() {
+        assert_eq!(auth_mode_of(Some(CAPABILITY_ESS | CAPABILITY_PRIVACY), &[]), "[WEP][ESS]");
+        assert_eq!(auth_mode_of(Some(CAPABILITY_ESS | CAPABILITY_PRIVACY), &[rsn(&[2])]), "[WPA2-PSK-CCMP][ESS]");
+        assert_eq!(auth_mode_of(Some(CAPABILITY_ESS), &[]), "[ESS]");
+    }
+
+    #[test]
+    fn network_type_suffixes() {
+        assert_eq!(auth_mode_of(Some(CAPABILITY_IBSS), &[]), "[IBSS]");
+        assert_eq!(auth_mode_of(Some(CAPABILITY_IBSS | CAPABILITY_PRIVACY), &[]), "[WEP][IBSS]");
+
+        // Logs without the capability only have what the elements say
+        assert_eq!(auth_mode_of(None, &[]), "");
+        assert_eq!(auth_mode_of(None, &[rsn(&[2])]), "[WPA2-PSK-CCMP]");
+    }
+
+    #[test]
+    fn export_starts_with_the_wigle_headers() {
+        let pre_header = pre_header();
+        assert!(pre_header.starts_with("WigleWifi-1.4,appRelease="));
+        assert_eq!(pre_header.split(',').count(), 8);
+
+        let columns: Vec<&str> = HEADER.split(',').collect();
+        assert_eq!(columns.len(), 11);
+        assert_eq!(columns.first(), Some(&"MAC"));
+        assert_eq!(columns.last(), Some(&"Type"));
+
+        let path = std::env::temp_dir().join(format!("wifi_logging_{}_wigle.csv", process::id()));
+        assert_eq!(export(&[], &path), Ok(0));
+
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(written, format!("{}\n{}\n", pre_header, HEADER));
+    }
+}